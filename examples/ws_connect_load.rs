@@ -0,0 +1,170 @@
+//! Connection-storm load test for the room WebSocket endpoint.
+//!
+//! Registers a throwaway user, creates `--rooms` rooms, then opens
+//! `--connections` WebSockets spread across them with at most `--concurrency`
+//! handshakes in flight. Reports how long it took until every socket had
+//! received its first frame (i.e. had joined its room's live channel).
+//!
+//! ```text
+//! cargo run --release --example ws_connect_load -- \
+//!     --addr 127.0.0.1:8080 --connections 5000 --rooms 50 --concurrency 500
+//! ```
+
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use futures_util::StreamExt;
+use serde_json::{json, Value};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+    sync::Semaphore,
+};
+use tokio_tungstenite::tungstenite::{client::IntoClientRequest, http::header::AUTHORIZATION};
+
+struct Args {
+    addr: String,
+    connections: usize,
+    rooms: usize,
+    concurrency: usize,
+}
+
+fn parse_args() -> Args {
+    let mut args = Args {
+        addr: "127.0.0.1:8080".to_string(),
+        connections: 2000,
+        rooms: 50,
+        concurrency: 256,
+    };
+    let mut it = std::env::args().skip(1);
+    while let Some(flag) = it.next() {
+        let value = it.next().unwrap_or_else(|| panic!("missing value for {flag}"));
+        match flag.as_str() {
+            "--addr" => args.addr = value,
+            "--connections" => args.connections = value.parse().expect("--connections"),
+            "--rooms" => args.rooms = value.parse().expect("--rooms"),
+            "--concurrency" => args.concurrency = value.parse().expect("--concurrency"),
+            other => panic!("unknown flag {other}"),
+        }
+    }
+    args
+}
+
+/// Minimal HTTP/1.1 JSON POST so the load test needs no HTTP client crate.
+async fn post_json(addr: &str, path: &str, token: Option<&str>, body: Value) -> anyhow::Result<Value> {
+    let body = body.to_string();
+    let auth = token
+        .map(|t| format!("Authorization: Bearer {t}\r\n"))
+        .unwrap_or_default();
+    let request = format!(
+        "POST {path} HTTP/1.1\r\nHost: {addr}\r\nContent-Type: application/json\r\n{auth}Content-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    );
+
+    let mut stream = TcpStream::connect(addr).await?;
+    stream.write_all(request.as_bytes()).await?;
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await?;
+
+    let (head, body) = response
+        .split_once("\r\n\r\n")
+        .ok_or_else(|| anyhow::anyhow!("malformed response"))?;
+    if !head.starts_with("HTTP/1.1 2") {
+        anyhow::bail!("{path} failed: {head}\n{body}");
+    }
+    Ok(serde_json::from_str(body)?)
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let args = parse_args();
+    let suffix = uuid::Uuid::new_v4().simple().to_string();
+
+    let login = post_json(
+        &args.addr,
+        "/api/auth/register",
+        None,
+        json!({ "username": format!("load-{}", &suffix[..12]), "password": suffix }),
+    )
+    .await?;
+    let token = login["token"].as_str().expect("token").to_string();
+
+    let mut room_ids = Vec::with_capacity(args.rooms);
+    for i in 0..args.rooms {
+        let room = post_json(
+            &args.addr,
+            "/api/rooms",
+            Some(&token),
+            json!({ "name": format!("load-{}-{i}", &suffix[..12]) }),
+        )
+        .await?;
+        room_ids.push(room["id"].as_str().expect("room id").to_string());
+    }
+
+    let permits = Arc::new(Semaphore::new(args.concurrency));
+    let started = Instant::now();
+    let mut tasks = Vec::with_capacity(args.connections);
+    for i in 0..args.connections {
+        let url = format!("ws://{}/ws/rooms/{}", args.addr, room_ids[i % room_ids.len()]);
+        let mut request = url.into_client_request()?;
+        // Not `?token=`: it is deprecated and ends up in access logs.
+        request
+            .headers_mut()
+            .insert(AUTHORIZATION, format!("Bearer {token}").parse()?);
+        let permits = permits.clone();
+        tasks.push(tokio::spawn(async move {
+            let _permit = permits.acquire_owned().await.expect("semaphore closed");
+            let t = Instant::now();
+            let (mut ws, _) = tokio_tungstenite::connect_async(request).await?;
+            ws.next()
+                .await
+                .ok_or_else(|| anyhow::anyhow!("socket closed before first frame"))??;
+            Ok::<_, anyhow::Error>((t.elapsed(), ws))
+        }));
+    }
+
+    let mut latencies = Vec::with_capacity(args.connections);
+    let mut sockets = Vec::with_capacity(args.connections);
+    let mut failures = 0usize;
+    for task in tasks {
+        match task.await? {
+            Ok((latency, ws)) => {
+                latencies.push(latency);
+                sockets.push(ws);
+            }
+            Err(err) => {
+                failures += 1;
+                if failures <= 5 {
+                    eprintln!("connect failed: {err}");
+                }
+            }
+        }
+    }
+    let elapsed = started.elapsed();
+
+    latencies.sort();
+    let pct = |p: f64| -> Duration {
+        latencies
+            .get(((latencies.len() as f64 * p) as usize).min(latencies.len().saturating_sub(1)))
+            .copied()
+            .unwrap_or_default()
+    };
+    println!(
+        "{} connected, {} failed in {:.2?} ({:.0} connects/s)",
+        latencies.len(),
+        failures,
+        elapsed,
+        latencies.len() as f64 / elapsed.as_secs_f64()
+    );
+    println!(
+        "latency p50 {:.2?}  p95 {:.2?}  p99 {:.2?}",
+        pct(0.50),
+        pct(0.95),
+        pct(0.99)
+    );
+
+    drop(sockets);
+    Ok(())
+}
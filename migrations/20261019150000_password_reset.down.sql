@@ -0,0 +1,6 @@
+DROP INDEX IF EXISTS idx_password_reset_tokens_user;
+
+DROP TABLE IF EXISTS password_reset_tokens;
+
+DROP INDEX IF EXISTS idx_users_email_lower;
+ALTER TABLE users DROP COLUMN IF EXISTS email;
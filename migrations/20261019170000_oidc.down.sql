@@ -0,0 +1,8 @@
+DROP TABLE IF EXISTS oidc_logins;
+
+DROP INDEX IF EXISTS idx_user_identities_user;
+DROP TABLE IF EXISTS user_identities;
+
+-- Password-less accounts cannot survive the constraint.
+DELETE FROM users WHERE password_hash IS NULL;
+ALTER TABLE users ALTER COLUMN password_hash SET NOT NULL;
@@ -0,0 +1,36 @@
+-- Accounts created through single sign-on have no password.
+ALTER TABLE users ALTER COLUMN password_hash DROP NOT NULL;
+
+-- An account at an OpenID Connect provider (`subject` is the ID token's
+-- `sub`, unique per provider) linked to a local user.
+CREATE TABLE user_identities (
+    id UUID PRIMARY KEY,
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    provider TEXT NOT NULL,
+    subject TEXT NOT NULL,
+    email TEXT,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    last_login_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    UNIQUE (provider, subject)
+);
+
+CREATE INDEX idx_user_identities_user ON user_identities (user_id);
+
+-- One sign-in through a provider. Created when the browser is sent to the
+-- provider; once the provider redirects back, `user_id` is set and a
+-- single-use grant is issued for the web client to exchange for tokens.
+CREATE TABLE oidc_logins (
+    id UUID PRIMARY KEY,
+    provider TEXT NOT NULL,
+    state_hash TEXT NOT NULL UNIQUE,
+    -- Hash of the cookie set on the browser that started the sign-in.
+    browser_hash TEXT NOT NULL,
+    nonce TEXT NOT NULL,
+    code_verifier TEXT NOT NULL,
+    expires_at TIMESTAMPTZ NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    user_id UUID REFERENCES users(id) ON DELETE CASCADE,
+    grant_hash TEXT UNIQUE,
+    grant_expires_at TIMESTAMPTZ,
+    used_at TIMESTAMPTZ
+);
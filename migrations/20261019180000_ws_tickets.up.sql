@@ -0,0 +1,10 @@
+-- Single-use tickets for opening a WebSocket without putting an access token
+-- in the URL. Stored in the database so any instance can redeem them.
+CREATE TABLE ws_tickets (
+    ticket_hash TEXT PRIMARY KEY,
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    session_id UUID NOT NULL REFERENCES sessions(id) ON DELETE CASCADE,
+    -- When set, the ticket only opens a socket for this room.
+    room_id UUID REFERENCES rooms(id) ON DELETE CASCADE,
+    expires_at TIMESTAMPTZ NOT NULL
+);
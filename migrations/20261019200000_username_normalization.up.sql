@@ -0,0 +1,19 @@
+-- Usernames are stored NFKC-normalized and trimmed, and are unique
+-- regardless of case.
+ALTER TABLE users DROP CONSTRAINT users_username_key;
+
+UPDATE users
+SET username = normalize(btrim(username), NFKC)
+WHERE username <> normalize(btrim(username), NFKC);
+
+-- Where names now differ only in case (or normalization), the oldest account
+-- keeps the name and the others get a suffix from their id.
+UPDATE users
+SET username = users.username || '-' || left(users.id::text, 8)
+FROM (
+    SELECT id, row_number() OVER (PARTITION BY lower(username) ORDER BY created_at, id) AS n
+    FROM users
+) ranked
+WHERE ranked.id = users.id AND ranked.n > 1;
+
+CREATE UNIQUE INDEX idx_users_username_lower ON users (lower(username));
@@ -0,0 +1,6 @@
+-- Optional profile fields shown alongside the username.
+ALTER TABLE users
+    ADD COLUMN display_name TEXT,
+    ADD COLUMN avatar_url TEXT,
+    ADD COLUMN bio TEXT,
+    ADD COLUMN status TEXT;
@@ -0,0 +1,3 @@
+-- Prefix search on usernames and display names (`LIKE 'abc%'`).
+CREATE INDEX idx_users_username_prefix ON users (lower(username) text_pattern_ops);
+CREATE INDEX idx_users_display_name_prefix ON users (lower(display_name) text_pattern_ops);
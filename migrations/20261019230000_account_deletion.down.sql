@@ -0,0 +1,15 @@
+-- Note: this also deletes the messages of deleted accounts.
+ALTER TABLE messages DROP CONSTRAINT messages_user_id_fkey;
+ALTER TABLE messages ADD CONSTRAINT messages_user_id_fkey
+    FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE;
+DELETE FROM users WHERE id = '00000000-0000-0000-0000-000000000001';
+
+DELETE FROM sessions WHERE user_id IS NULL;
+ALTER TABLE sessions DROP CONSTRAINT sessions_user_id_fkey;
+ALTER TABLE sessions ADD CONSTRAINT sessions_user_id_fkey
+    FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE;
+ALTER TABLE sessions ALTER COLUMN user_id SET NOT NULL;
+
+ALTER TABLE rooms DROP CONSTRAINT rooms_owner_user_id_fkey;
+ALTER TABLE rooms ADD CONSTRAINT rooms_owner_user_id_fkey
+    FOREIGN KEY (owner_user_id) REFERENCES users(id);
@@ -0,0 +1,23 @@
+-- Deleting a user must decide what happens to their messages explicitly
+-- instead of silently taking the conversations with it.
+ALTER TABLE messages DROP CONSTRAINT messages_user_id_fkey;
+ALTER TABLE messages ADD CONSTRAINT messages_user_id_fkey
+    FOREIGN KEY (user_id) REFERENCES users(id);
+
+-- Rooms outlive their creator.
+ALTER TABLE rooms DROP CONSTRAINT rooms_owner_user_id_fkey;
+ALTER TABLE rooms ADD CONSTRAINT rooms_owner_user_id_fkey
+    FOREIGN KEY (owner_user_id) REFERENCES users(id) ON DELETE SET NULL;
+
+-- A deleted user's revoked sessions stay around (scrubbed) until the stale
+-- session sweep, so other instances still learn of the revocation.
+ALTER TABLE sessions ALTER COLUMN user_id DROP NOT NULL;
+ALTER TABLE sessions DROP CONSTRAINT sessions_user_id_fkey;
+ALTER TABLE sessions ADD CONSTRAINT sessions_user_id_fkey
+    FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE SET NULL;
+
+-- Anonymized messages of deleted accounts are attributed to this account. It
+-- has no password, email or sign-in identity, and its name is not a valid
+-- username, so nobody can sign in as or register it.
+INSERT INTO users (id, username, display_name)
+VALUES ('00000000-0000-0000-0000-000000000001', '[deleted]', 'Deleted user');
@@ -0,0 +1,18 @@
+-- Files uploaded to a room. An upload is unlinked (message_id NULL) until a
+-- message references it; unlinked uploads are removed by a periodic sweep,
+-- as are attachments whose message was deleted. The bytes live in the
+-- configured storage backend under storage_key.
+CREATE TABLE attachments (
+    id UUID PRIMARY KEY,
+    room_id UUID NOT NULL REFERENCES rooms(id) ON DELETE CASCADE,
+    message_id UUID REFERENCES messages(id) ON DELETE SET NULL,
+    uploader_id UUID REFERENCES users(id) ON DELETE SET NULL,
+    filename TEXT NOT NULL,
+    content_type TEXT NOT NULL,
+    size_bytes BIGINT NOT NULL,
+    storage_key TEXT NOT NULL UNIQUE,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX idx_attachments_message ON attachments (message_id);
+CREATE INDEX idx_attachments_unlinked ON attachments (created_at) WHERE message_id IS NULL;
@@ -0,0 +1,3 @@
+DROP TABLE attachment_thumbnails;
+ALTER TABLE attachments DROP COLUMN height;
+ALTER TABLE attachments DROP COLUMN width;
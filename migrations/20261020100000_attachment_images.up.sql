@@ -0,0 +1,16 @@
+-- Pixel size of image attachments (after applying their EXIF orientation),
+-- so clients can reserve space before the image loads.
+ALTER TABLE attachments ADD COLUMN width INT;
+ALTER TABLE attachments ADD COLUMN height INT;
+
+-- Downscaled copies of image attachments. `size` is the configured bound on
+-- the longest edge; images already within it have no thumbnail of that size.
+CREATE TABLE attachment_thumbnails (
+    attachment_id UUID NOT NULL REFERENCES attachments(id) ON DELETE CASCADE,
+    size INT NOT NULL,
+    width INT NOT NULL,
+    height INT NOT NULL,
+    content_type TEXT NOT NULL,
+    storage_key TEXT NOT NULL UNIQUE,
+    PRIMARY KEY (attachment_id, size)
+);
@@ -0,0 +1,19 @@
+-- Metadata fetched from links posted in messages, cached by URL. A fetch
+-- that failed or found nothing is cached too, with every field NULL, so the
+-- page is not requested again until the entry expires.
+CREATE TABLE link_previews (
+    url TEXT PRIMARY KEY,
+    title TEXT,
+    description TEXT,
+    image_url TEXT,
+    site_name TEXT,
+    fetched_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+-- Previews shown under a message, in the order the links appear in it.
+CREATE TABLE message_link_previews (
+    message_id UUID NOT NULL REFERENCES messages(id) ON DELETE CASCADE,
+    url TEXT NOT NULL REFERENCES link_previews(url) ON DELETE CASCADE,
+    position INT NOT NULL,
+    PRIMARY KEY (message_id, url)
+);
@@ -0,0 +1,519 @@
+//! Processing of uploaded images: metadata stripping, dimensions and
+//! thumbnails.
+//!
+//! Decoding untrusted images is CPU-heavy, so it runs on a fixed number of
+//! blocking workers fed by a bounded queue ([`ImageWorkers`]). When the queue
+//! is full, uploads are turned away with `429` instead of piling up.
+//!
+//! Metadata is removed from the container without re-encoding, so originals
+//! keep their quality: EXIF, XMP, IPTC and comments from JPEG, EXIF and text
+//! chunks from PNG, EXIF and XMP from WebP. GIF cannot carry EXIF and is kept
+//! as is. An image whose EXIF orientation says it must be rotated is
+//! re-encoded upright instead, since dropping the tag would show it sideways.
+
+use std::{io::Cursor, sync::Arc};
+
+use axum::body::Bytes;
+use image::{
+    codecs::{jpeg::JpegEncoder, webp::WebPEncoder},
+    metadata::Orientation,
+    DynamicImage, GenericImageView, ImageDecoder, ImageError, ImageFormat, ImageReader,
+    ImageResult, Limits,
+};
+use tokio::sync::{mpsc, oneshot, Mutex};
+
+use crate::error::{AppError, AppResult};
+
+/// Images wider or taller than this are refused.
+const MAX_DIMENSION: u32 = 12_000;
+
+const JPEG_QUALITY: u8 = 90;
+const THUMBNAIL_JPEG_QUALITY: u8 = 80;
+
+/// An uploaded image, ready to store.
+pub struct ProcessedImage {
+    /// The image without metadata.
+    pub data: Bytes,
+    pub width: u32,
+    pub height: u32,
+    pub thumbnails: Vec<Thumbnail>,
+}
+
+pub struct Thumbnail {
+    /// The configured size: the longest edge is at most this many pixels.
+    pub size: u32,
+    pub width: u32,
+    pub height: u32,
+    pub content_type: &'static str,
+    pub data: Bytes,
+}
+
+/// Whether uploads of `content_type` go through [`ImageWorkers::process`].
+pub fn is_processed_image(content_type: &str) -> bool {
+    image_format(content_type).is_some()
+}
+
+fn image_format(content_type: &str) -> Option<ImageFormat> {
+    match content_type {
+        "image/png" => Some(ImageFormat::Png),
+        "image/jpeg" => Some(ImageFormat::Jpeg),
+        "image/gif" => Some(ImageFormat::Gif),
+        "image/webp" => Some(ImageFormat::WebP),
+        _ => None,
+    }
+}
+
+struct Job {
+    data: Bytes,
+    format: ImageFormat,
+    reply: oneshot::Sender<ImageResult<ProcessedImage>>,
+}
+
+/// Handle to the image worker pool.
+#[derive(Clone)]
+pub struct ImageWorkers {
+    jobs: mpsc::Sender<Job>,
+}
+
+impl ImageWorkers {
+    /// Start `workers` workers sharing a queue of `queue_capacity` jobs.
+    pub fn start(workers: usize, queue_capacity: usize, thumbnail_sizes: Vec<u32>) -> Self {
+        let (jobs, queue) = mpsc::channel::<Job>(queue_capacity);
+        let queue = Arc::new(Mutex::new(queue));
+        let thumbnail_sizes = Arc::new(thumbnail_sizes);
+
+        for _ in 0..workers {
+            let queue = queue.clone();
+            let thumbnail_sizes = thumbnail_sizes.clone();
+            tokio::spawn(async move {
+                loop {
+                    let Some(job) = queue.lock().await.recv().await else {
+                        return;
+                    };
+                    let sizes = thumbnail_sizes.clone();
+                    let Job {
+                        data,
+                        format,
+                        reply,
+                    } = job;
+                    let result = tokio::task::spawn_blocking(move || {
+                        (process(&data, format, &sizes), reply)
+                    })
+                    .await;
+                    match result {
+                        Ok((result, reply)) => {
+                            let _ = reply.send(result);
+                        }
+                        // The reply channel went with the task, so the
+                        // waiting upload sees the job as dropped.
+                        Err(err) => tracing::warn!("image worker panicked: {err}"),
+                    }
+                }
+            });
+        }
+
+        Self { jobs }
+    }
+
+    /// Strip `data`'s metadata, measure it and make its thumbnails.
+    pub async fn process(&self, data: Bytes, content_type: &str) -> AppResult<ProcessedImage> {
+        let format = image_format(content_type).ok_or_else(|| {
+            AppError::Internal(anyhow::anyhow!("{content_type} is not processed"))
+        })?;
+        let (reply, result) = oneshot::channel();
+        self.jobs
+            .try_send(Job {
+                data,
+                format,
+                reply,
+            })
+            .map_err(|err| match err {
+                mpsc::error::TrySendError::Full(_) => AppError::TooManyRequests {
+                    message: "too many images are being processed; try again shortly".into(),
+                    retry_after_secs: 5,
+                },
+                mpsc::error::TrySendError::Closed(_) => {
+                    AppError::Internal(anyhow::anyhow!("image workers have stopped"))
+                }
+            })?;
+
+        match result.await {
+            Ok(Ok(image)) => Ok(image),
+            Ok(Err(ImageError::Limits(_))) => Err(AppError::PayloadTooLarge(format!(
+                "images may be at most {MAX_DIMENSION} pixels wide and high"
+            ))),
+            Ok(Err(ImageError::Encoding(err))) => Err(AppError::Internal(err.into())),
+            Ok(Err(err)) => {
+                tracing::debug!("rejected image upload: {err}");
+                Err(AppError::validation("file", "not a valid image"))
+            }
+            // The decoder panicked.
+            Err(_) => Err(AppError::validation("file", "not a valid image")),
+        }
+    }
+}
+
+fn process(
+    data: &[u8],
+    format: ImageFormat,
+    thumbnail_sizes: &[u32],
+) -> ImageResult<ProcessedImage> {
+    let mut limits = Limits::default();
+    limits.max_image_width = Some(MAX_DIMENSION);
+    limits.max_image_height = Some(MAX_DIMENSION);
+    let mut reader = ImageReader::with_format(Cursor::new(data), format);
+    reader.limits(limits);
+    let mut decoder = reader.into_decoder()?;
+    let orientation = decoder.orientation()?;
+    let mut image = DynamicImage::from_decoder(decoder)?;
+
+    let stripped = match orientation {
+        Orientation::NoTransforms => strip_metadata(data, format),
+        _ => None,
+    };
+    let data = match stripped {
+        Some(stripped) => stripped,
+        None => {
+            image.apply_orientation(orientation);
+            encode(&image, format)?
+        }
+    };
+
+    let (width, height) = image.dimensions();
+    let mut thumbnails = Vec::new();
+    for &size in thumbnail_sizes {
+        if width.max(height) <= size {
+            continue;
+        }
+        let thumbnail = image.thumbnail(size, size);
+        let (data, content_type) = if thumbnail.color().has_alpha() {
+            (encode(&thumbnail, ImageFormat::Png)?, "image/png")
+        } else {
+            let mut out = Vec::new();
+            JpegEncoder::new_with_quality(&mut out, THUMBNAIL_JPEG_QUALITY)
+                .encode_image(&thumbnail.to_rgb8())?;
+            (out, "image/jpeg")
+        };
+        thumbnails.push(Thumbnail {
+            size,
+            width: thumbnail.width(),
+            height: thumbnail.height(),
+            content_type,
+            data: data.into(),
+        });
+    }
+
+    Ok(ProcessedImage {
+        data: data.into(),
+        width,
+        height,
+        thumbnails,
+    })
+}
+
+/// Encode `image` as `format`; written this way it carries no metadata.
+fn encode(image: &DynamicImage, format: ImageFormat) -> ImageResult<Vec<u8>> {
+    let mut out = Vec::new();
+    match format {
+        ImageFormat::Jpeg => {
+            JpegEncoder::new_with_quality(&mut out, JPEG_QUALITY).encode_image(&image.to_rgb8())?
+        }
+        ImageFormat::WebP => WebPEncoder::new_lossless(&mut out).encode(
+            image.to_rgba8().as_raw(),
+            image.width(),
+            image.height(),
+            image::ExtendedColorType::Rgba8,
+        )?,
+        _ => image.write_to(&mut Cursor::new(&mut out), format)?,
+    }
+    Ok(out)
+}
+
+/// `data` without metadata, or `None` if its structure is not understood.
+fn strip_metadata(data: &[u8], format: ImageFormat) -> Option<Vec<u8>> {
+    match format {
+        ImageFormat::Jpeg => strip_jpeg(data),
+        ImageFormat::Png => strip_png(data),
+        ImageFormat::WebP => strip_webp(data),
+        ImageFormat::Gif => Some(data.to_vec()),
+        _ => None,
+    }
+}
+
+/// Drops APP1 (EXIF, XMP), APP13 (IPTC) and comment segments, and anything
+/// after the end of the image, such as the extra images of an MPF file (which
+/// carry their own EXIF).
+fn strip_jpeg(data: &[u8]) -> Option<Vec<u8>> {
+    if !data.starts_with(&[0xFF, 0xD8]) {
+        return None;
+    }
+    let mut out = Vec::with_capacity(data.len());
+    out.extend_from_slice(&data[..2]);
+    let mut pos = 2;
+    loop {
+        if *data.get(pos)? != 0xFF {
+            return None;
+        }
+        // Markers may be preceded by any number of 0xFF fill bytes.
+        while *data.get(pos + 1)? == 0xFF {
+            pos += 1;
+        }
+        let marker = data[pos + 1];
+        match marker {
+            // Markers without a length.
+            0x01 | 0xD0..=0xD8 => {
+                pos += 2;
+                continue;
+            }
+            0xD9 => {
+                out.extend_from_slice(&[0xFF, 0xD9]);
+                return Some(out);
+            }
+            _ => {}
+        }
+        let len = u16::from_be_bytes([*data.get(pos + 2)?, *data.get(pos + 3)?]) as usize;
+        if len < 2 {
+            return None;
+        }
+        let end = pos + 2 + len;
+        if end > data.len() {
+            return None;
+        }
+        // Start of scan: image data follows, up to the next marker. In it, 0xFF
+        // is only followed by a stuffed 0x00 or a restart marker.
+        if marker == 0xDA {
+            out.extend_from_slice(&[0xFF, marker]);
+            out.extend_from_slice(&data[pos + 2..end]);
+            let mut scan_end = end;
+            loop {
+                scan_end += data.get(scan_end..)?.iter().position(|&b| b == 0xFF)?;
+                if !matches!(*data.get(scan_end + 1)?, 0x00 | 0xD0..=0xD7) {
+                    break;
+                }
+                scan_end += 2;
+            }
+            out.extend_from_slice(&data[end..scan_end]);
+            pos = scan_end;
+            continue;
+        }
+        if !matches!(marker, 0xE1 | 0xED | 0xFE) {
+            out.extend_from_slice(&[0xFF, marker]);
+            out.extend_from_slice(&data[pos + 2..end]);
+        }
+        pos = end;
+    }
+}
+
+/// Drops `eXIf`, text and timestamp chunks, and anything after `IEND`.
+fn strip_png(data: &[u8]) -> Option<Vec<u8>> {
+    const SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+    if !data.starts_with(SIGNATURE) {
+        return None;
+    }
+    let mut out = Vec::with_capacity(data.len());
+    out.extend_from_slice(SIGNATURE);
+    let mut pos = SIGNATURE.len();
+    while pos < data.len() {
+        let len = u32::from_be_bytes(data.get(pos..pos + 4)?.try_into().ok()?) as usize;
+        let end = pos.checked_add(12)?.checked_add(len)?;
+        let chunk = data.get(pos..end)?;
+        let kind = &chunk[4..8];
+        if !matches!(kind, b"eXIf" | b"tEXt" | b"zTXt" | b"iTXt" | b"tIME") {
+            out.extend_from_slice(chunk);
+        }
+        if kind == b"IEND" {
+            return Some(out);
+        }
+        pos = end;
+    }
+    None
+}
+
+/// Drops `EXIF` and `XMP ` chunks and clears their flags in `VP8X`, and
+/// anything after the RIFF container.
+fn strip_webp(data: &[u8]) -> Option<Vec<u8>> {
+    if data.len() < 12 || &data[..4] != b"RIFF" || &data[8..12] != b"WEBP" {
+        return None;
+    }
+    let riff_len = u32::from_le_bytes(data[4..8].try_into().ok()?) as usize;
+    let body_end = riff_len.checked_add(8)?;
+    if body_end < 12 || body_end > data.len() {
+        return None;
+    }
+    let mut out = Vec::with_capacity(body_end);
+    out.extend_from_slice(&data[..12]);
+    let mut pos = 12;
+    while pos < body_end {
+        let len = u32::from_le_bytes(data.get(pos + 4..pos + 8)?.try_into().ok()?) as usize;
+        let data_end = pos.checked_add(8)?.checked_add(len)?;
+        if data_end > body_end {
+            return None;
+        }
+        // Chunks are padded to an even length; tolerate a missing final pad.
+        let end = (data_end + (len & 1)).min(body_end);
+        let chunk = &data[pos..end];
+        match &chunk[..4] {
+            b"EXIF" | b"XMP " => {}
+            b"VP8X" if chunk.len() > 8 => {
+                out.extend_from_slice(&chunk[..8]);
+                out.push(chunk[8] & !(0x08 | 0x04));
+                out.extend_from_slice(&chunk[9..]);
+            }
+            _ => out.extend_from_slice(chunk),
+        }
+        pos = end;
+    }
+    let riff_len = u32::try_from(out.len() - 8).ok()?;
+    out[4..8].copy_from_slice(&riff_len.to_le_bytes());
+    Some(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use image::RgbImage;
+
+    use super::*;
+
+    /// A noisy image, so its JPEG scan has stuffed 0xFF bytes to step over.
+    fn sample(width: u32, height: u32) -> DynamicImage {
+        DynamicImage::ImageRgb8(RgbImage::from_fn(width, height, |x, y| {
+            let v = (x * 7919 + y * 104_729) as u8;
+            image::Rgb([v, v.wrapping_mul(31), 0xFF - v])
+        }))
+    }
+
+    /// EXIF with the given orientation and a GPS IFD holding a latitude ref.
+    fn exif(orientation: u16) -> Vec<u8> {
+        let mut tiff = b"MM\0\x2a\0\0\0\x08".to_vec();
+        tiff.extend_from_slice(&[0, 2]);
+        tiff.extend_from_slice(&[0x01, 0x12, 0, 3, 0, 0, 0, 1]);
+        tiff.extend_from_slice(&orientation.to_be_bytes());
+        tiff.extend_from_slice(&[0, 0]);
+        // GPSInfo, pointing just past this IFD.
+        tiff.extend_from_slice(&[0x88, 0x25, 0, 4, 0, 0, 0, 1, 0, 0, 0, 38]);
+        tiff.extend_from_slice(&[0, 0, 0, 0]);
+        tiff.extend_from_slice(&[0, 1]);
+        tiff.extend_from_slice(&[0, 1, 0, 2, 0, 0, 0, 2, b'N', 0, 0, 0]);
+        tiff.extend_from_slice(&[0, 0, 0, 0]);
+        [b"Exif\0\0".as_slice(), &tiff].concat()
+    }
+
+    fn with_app1(jpeg: &[u8], payload: &[u8]) -> Vec<u8> {
+        let len = u16::try_from(payload.len() + 2).unwrap();
+        [
+            &jpeg[..2],
+            &[0xFF, 0xE1],
+            &len.to_be_bytes(),
+            payload,
+            &jpeg[2..],
+        ]
+        .concat()
+    }
+
+    fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+        haystack.windows(needle.len()).any(|w| w == needle)
+    }
+
+    fn png_chunk(kind: &[u8; 4], data: &[u8]) -> Vec<u8> {
+        let len = u32::try_from(data.len()).unwrap();
+        // The CRC is not checked when stripping.
+        [&len.to_be_bytes(), kind.as_slice(), data, &[0; 4]].concat()
+    }
+
+    fn riff_chunk(kind: &[u8; 4], data: &[u8]) -> Vec<u8> {
+        let len = u32::try_from(data.len()).unwrap();
+        let pad: &[u8] = if data.len() % 2 == 1 { &[0] } else { &[] };
+        [kind.as_slice(), &len.to_le_bytes(), data, pad].concat()
+    }
+
+    fn riff(chunks: &[u8]) -> Vec<u8> {
+        let len = u32::try_from(chunks.len() + 4).unwrap();
+        [b"RIFF".as_slice(), &len.to_le_bytes(), b"WEBP", chunks].concat()
+    }
+
+    #[test]
+    fn strips_jpeg_exif_and_trailing_images() {
+        let clean = encode(&sample(64, 32), ImageFormat::Jpeg).unwrap();
+        // An MPF-style file: a second image, with its own EXIF, after EOI.
+        let secondary = with_app1(&encode(&sample(8, 8), ImageFormat::Jpeg).unwrap(), &exif(1));
+        let tagged = [with_app1(&clean, &exif(1)), secondary].concat();
+
+        let stripped = strip_jpeg(&tagged).unwrap();
+        assert!(!contains(&stripped, b"Exif"));
+        assert_eq!(stripped, clean);
+    }
+
+    #[test]
+    fn refuses_truncated_jpeg() {
+        let clean = encode(&sample(64, 32), ImageFormat::Jpeg).unwrap();
+        assert_eq!(strip_jpeg(&clean[..clean.len() - 2]), None);
+        assert_eq!(strip_jpeg(&clean[..clean.len() / 2]), None);
+        assert_eq!(strip_jpeg(&clean[..20]), None);
+        assert_eq!(strip_jpeg(b"not a jpeg"), None);
+    }
+
+    #[test]
+    fn keeps_jpeg_orientation() {
+        let clean = encode(&sample(64, 32), ImageFormat::Jpeg).unwrap();
+
+        let upright = process(&with_app1(&clean, &exif(1)), ImageFormat::Jpeg, &[]).unwrap();
+        assert_eq!((upright.width, upright.height), (64, 32));
+        assert_eq!(upright.data.as_ref(), clean.as_slice());
+
+        // Rotated 90° clockwise for display: re-encoded upright, without EXIF.
+        let rotated = process(&with_app1(&clean, &exif(6)), ImageFormat::Jpeg, &[]).unwrap();
+        assert_eq!((rotated.width, rotated.height), (32, 64));
+        assert!(!contains(&rotated.data, b"Exif"));
+        let decoded = image::load_from_memory(&rotated.data).unwrap();
+        assert_eq!(decoded.dimensions(), (32, 64));
+    }
+
+    #[test]
+    fn strips_png_metadata_and_trailing_data() {
+        let clean = encode(&sample(16, 8), ImageFormat::Png).unwrap();
+        let iend = clean.len() - 12;
+        let tagged = [
+            &clean[..iend],
+            &png_chunk(b"eXIf", &exif(1)[6..]),
+            &png_chunk(b"tEXt", b"Comment\0somewhere"),
+            &png_chunk(b"tIME", &[7, 234, 10, 19, 12, 0, 0]),
+            &clean[iend..],
+            b"trailing",
+        ]
+        .concat();
+
+        let stripped = strip_png(&tagged).unwrap();
+        assert!(!contains(&stripped, b"somewhere"));
+        assert_eq!(stripped, clean);
+        assert_eq!(strip_png(&clean[..clean.len() - 4]), None);
+        assert_eq!(strip_png(&clean[..iend]), None);
+    }
+
+    #[test]
+    fn strips_webp_metadata_and_trailing_data() {
+        let simple = encode(&sample(16, 8), ImageFormat::WebP).unwrap();
+        let bitstream = &simple[12..];
+        let vp8x = |flags: u8| riff_chunk(b"VP8X", &[flags, 0, 0, 0, 15, 0, 0, 7, 0, 0]);
+        let chunks = [
+            vp8x(0x08 | 0x04),
+            bitstream.to_vec(),
+            riff_chunk(b"EXIF", &exif(1)[6..]),
+            riff_chunk(b"XMP ", b"<x:xmpmeta />"),
+        ]
+        .concat();
+        let tagged = [riff(&chunks), b"trailing".to_vec()].concat();
+
+        let stripped = strip_webp(&tagged).unwrap();
+        assert_eq!(stripped, riff(&[vp8x(0), bitstream.to_vec()].concat()));
+        let decoded = image::load_from_memory(&stripped).unwrap();
+        assert_eq!(decoded.dimensions(), (16, 8));
+    }
+
+    #[test]
+    fn refuses_truncated_webp() {
+        let simple = encode(&sample(16, 8), ImageFormat::WebP).unwrap();
+        assert_eq!(strip_webp(&simple[..simple.len() - 3]), None);
+        assert_eq!(strip_webp(&simple[..16]), None);
+        assert_eq!(strip_webp(b"RIFF\0\0\0\0WEBP"), None);
+    }
+}
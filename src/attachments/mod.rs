@@ -0,0 +1,149 @@
+//! File attachments.
+//!
+//! A file is uploaded to a room first and then sent by listing its id in a
+//! message's `attachment_ids`. The stored content type comes from the file's
+//! leading bytes, never from the client, and must be one of
+//! `ATTACHMENT_TYPES`. Images are cleaned of metadata and thumbnailed
+//! ([`images`]) before they are stored. Uploads that are never sent are swept
+//! after a day.
+
+use std::time::Duration;
+
+use chrono::Utc;
+use unicode_normalization::UnicodeNormalization;
+use uuid::Uuid;
+
+use crate::{db::attachments::delete_unlinked_attachments, state::AppState};
+
+pub mod images;
+
+/// How long an upload may stay unattached before it is deleted.
+const UNLINKED_TTL: chrono::Duration = chrono::Duration::hours(24);
+
+/// Longest file name kept, in characters.
+const MAX_FILENAME_LEN: usize = 255;
+
+/// Unlinked attachments deleted per sweep query.
+const SWEEP_BATCH: i64 = 100;
+
+/// The content type of `data`, judged by its leading bytes. Text is only
+/// recognised as `text/plain` if it is valid UTF-8 without NUL bytes; anything
+/// unrecognised is `application/octet-stream`.
+pub fn sniff_content_type(data: &[u8]) -> &'static str {
+    if data.starts_with(b"\x89PNG\r\n\x1a\n") {
+        "image/png"
+    } else if data.starts_with(b"\xff\xd8\xff") {
+        "image/jpeg"
+    } else if data.starts_with(b"GIF87a") || data.starts_with(b"GIF89a") {
+        "image/gif"
+    } else if data.len() >= 12 && &data[..4] == b"RIFF" && &data[8..12] == b"WEBP" {
+        "image/webp"
+    } else if data.starts_with(b"%PDF-") {
+        "application/pdf"
+    } else if !data.contains(&0) && std::str::from_utf8(data).is_ok() {
+        "text/plain"
+    } else {
+        "application/octet-stream"
+    }
+}
+
+/// Whether `content_type` matches one of `allowed` (`type/subtype` or
+/// `type/*`).
+pub fn is_allowed_type(content_type: &str, allowed: &[String]) -> bool {
+    allowed
+        .iter()
+        .any(|pattern| match pattern.strip_suffix("/*") {
+            Some(top) => content_type.split('/').next() == Some(top),
+            None => pattern == content_type,
+        })
+}
+
+/// A display name for an uploaded file: the last path component of what the
+/// client sent, without control characters, quotes or backslashes, at most
+/// [`MAX_FILENAME_LEN`] characters. Falls back to `file`.
+pub fn sanitize_filename(name: Option<&str>) -> String {
+    let name: String = name
+        .unwrap_or_default()
+        .rsplit(['/', '\\'])
+        .next()
+        .unwrap_or_default()
+        .nfc()
+        .map(|c| if c.is_control() || c == '"' { '_' } else { c })
+        .take(MAX_FILENAME_LEN)
+        .collect();
+    let name = name.trim();
+    if name.is_empty() || name == "." || name == ".." {
+        "file".to_string()
+    } else {
+        name.to_string()
+    }
+}
+
+/// Where an attachment's bytes are kept.
+pub fn storage_key(room_id: Uuid, id: Uuid) -> String {
+    format!("attachments/{room_id}/{id}")
+}
+
+/// Where the thumbnail of `size` of the attachment stored at `key` is kept.
+pub fn thumbnail_storage_key(key: &str, size: u32) -> String {
+    format!("{key}.thumb{size}")
+}
+
+/// `Content-Disposition` for serving a file called `filename`: `inline` for
+/// images, `attachment` for everything else.
+pub fn content_disposition(content_type: &str, filename: &str) -> String {
+    let kind = if content_type.starts_with("image/") {
+        "inline"
+    } else {
+        "attachment"
+    };
+    let fallback: String = filename
+        .chars()
+        .map(|c| {
+            if c.is_ascii_graphic() || c == ' ' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    let mut encoded = String::with_capacity(filename.len());
+    for b in filename.bytes() {
+        if b.is_ascii_alphanumeric() || matches!(b, b'-' | b'_' | b'.' | b'~') {
+            encoded.push(b as char);
+        } else {
+            encoded.push_str(&format!("%{b:02X}"));
+        }
+    }
+    format!("{kind}; filename=\"{fallback}\"; filename*=UTF-8''{encoded}")
+}
+
+/// Periodically delete uploads that were never attached to a message (or
+/// whose message was deleted), from the database and from storage.
+pub fn spawn_attachment_sweeper(state: AppState, interval: Duration) {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        loop {
+            ticker.tick().await;
+            if let Err(err) = sweep_unlinked(&state).await {
+                tracing::warn!("attachment sweep failed: {err}");
+            }
+        }
+    });
+}
+
+async fn sweep_unlinked(state: &AppState) -> anyhow::Result<()> {
+    let cutoff = Utc::now() - UNLINKED_TTL;
+    loop {
+        let keys = delete_unlinked_attachments(&state.db, cutoff, SWEEP_BATCH).await?;
+        if keys.is_empty() {
+            return Ok(());
+        }
+        for key in &keys {
+            if let Err(err) = state.storage.delete(key).await {
+                tracing::warn!("failed to delete attachment file {key}: {err}");
+            }
+        }
+        tracing::debug!("swept {} unlinked attachment files", keys.len());
+    }
+}
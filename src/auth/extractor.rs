@@ -12,6 +12,7 @@ use crate::{
 pub struct AuthUser {
     pub user_id: Uuid,
     pub username: String,
+    pub session_id: Uuid,
     pub claims: Claims,
 }
 
@@ -46,6 +47,7 @@ where
             Ok(AuthUser {
                 user_id: claims.sub,
                 username: claims.username.clone(),
+                session_id: claims.sid,
                 claims,
             })
         }
@@ -1,7 +1,7 @@
-use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use std::time::{SystemTime, UNIX_EPOCH};
 
 use chrono::Utc;
-use jsonwebtoken::{encode, decode, EncodingKey, DecodingKey, Header, Validation};
+use jsonwebtoken::{decode, decode_header, encode, errors::ErrorKind, Validation};
 use uuid::Uuid;
 
 use crate::{
@@ -14,12 +14,13 @@ pub fn generate_token(
     state: &AppState,
     user_id: Uuid,
     username: &str,
+    session_id: Uuid,
 ) -> Result<String, AppError> {
     let now = SystemTime::now()
         .duration_since(UNIX_EPOCH)
         .map_err(|e| AppError::Internal(e.into()))?;
     let iat = now.as_secs() as i64;
-    let exp = (now + Duration::from_secs((state.jwt_exp_hours * 3600) as u64)).as_secs() as i64;
+    let exp = iat + state.access_token_ttl.num_seconds();
 
     let claims = Claims {
         sub: user_id,
@@ -27,20 +28,26 @@ pub fn generate_token(
         exp,
         iat,
         iss: (*state.jwt_issuer).clone(),
+        jti: Uuid::new_v4(),
+        sid: session_id,
     };
 
-    let key = EncodingKey::from_secret(state.jwt_secret.as_bytes());
-    let token = encode(&Header::default(), &claims, &key)?;
+    let keys = &state.jwt_keys;
+    let token = encode(keys.header(), &claims, keys.encoding_key())?;
     Ok(token)
 }
 
 pub fn validate_token(state: &AppState, token: &str) -> Result<Claims, AppError> {
-    let key = DecodingKey::from_secret(state.jwt_secret.as_bytes());
-    let mut validation = Validation::default();
+    let header = decode_header(token).map_err(rejected)?;
+    let (key, algorithm) = state
+        .jwt_keys
+        .decoding_key(&header)
+        .ok_or_else(|| AppError::Unauthorized("unknown signing key".into()))?;
+    let mut validation = Validation::new(algorithm);
     validation.validate_exp = true;
     validation.set_issuer(&[&state.jwt_issuer]);
 
-    let token_data = decode::<Claims>(token, &key, &validation)?;
+    let token_data = decode::<Claims>(token, key, &validation).map_err(rejected)?;
     let claims = token_data.claims;
 
     // Basic sanity check on exp
@@ -49,6 +56,21 @@ pub fn validate_token(state: &AppState, token: &str) -> Result<Claims, AppError>
         return Err(AppError::Unauthorized("token expired".into()));
     }
 
+    if state.revocations.is_revoked(claims.sid) {
+        return Err(AppError::Unauthorized("session revoked".into()));
+    }
+    if state.revocations.is_token_revoked(claims.jti) {
+        return Err(AppError::Unauthorized("token revoked".into()));
+    }
+
     Ok(claims)
 }
 
+
+/// A token that fails to decode or verify is the client's problem, not ours.
+fn rejected(err: jsonwebtoken::errors::Error) -> AppError {
+    match err.kind() {
+        ErrorKind::ExpiredSignature => AppError::Unauthorized("token expired".into()),
+        _ => AppError::Unauthorized("invalid token".into()),
+    }
+}
@@ -0,0 +1,386 @@
+//! Access token signing keys.
+//!
+//! With `HS256` tokens are signed and verified with `JWT_SECRET`. With `RS256`
+//! or `EdDSA` they are signed with one private key and accepted from any of
+//! several public keys, which are published as a JWKS so other services can
+//! verify tokens without holding a secret. Each public key's `kid` is its
+//! RFC 7638 thumbprint.
+//!
+//! To rotate: add the new public key to `JWT_VERIFICATION_KEY_FILES` and roll
+//! it out (verifiers pick it up from the JWKS), then switch
+//! `JWT_SIGNING_KEY_FILE` to the new private key, and drop the old public key
+//! once the last token it signed has expired (`ACCESS_TOKEN_TTL_MINUTES`).
+//!
+//! Tokens without a `kid` are `HS256` ones. With a key pair they are only
+//! accepted while `JWT_LEGACY_HS256_UNTIL` is in the future, so a leaked
+//! `JWT_SECRET` cannot mint tokens once the switch is over.
+
+use anyhow::{anyhow, bail, Context};
+use chrono::{DateTime, Utc};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use jsonwebtoken::{Algorithm, DecodingKey, EncodingKey, Header, Validation};
+use serde::Serialize;
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use simple_asn1::{oid, ASN1Block};
+
+use crate::config::{Config, JwtAlgorithm};
+
+/// A public key in JWK form (RFC 7517).
+#[derive(Debug, Clone, Serialize)]
+pub struct PublicJwk {
+    kty: &'static str,
+    #[serde(rename = "use")]
+    use_: &'static str,
+    alg: &'static str,
+    kid: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    n: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    e: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    crv: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    x: Option<String>,
+}
+
+struct VerificationKey {
+    kid: String,
+    algorithm: Algorithm,
+    key: DecodingKey,
+    jwk: PublicJwk,
+}
+
+pub struct JwtKeys {
+    signing_header: Header,
+    signing_key: EncodingKey,
+    verification_keys: Vec<VerificationKey>,
+    secret: Option<DecodingKey>,
+    /// When `secret` stops being accepted; `None` for as long as it signs.
+    secret_until: Option<DateTime<Utc>>,
+}
+
+impl JwtKeys {
+    pub fn from_config(config: &Config) -> anyhow::Result<Self> {
+        let secret = config.jwt_secret.as_deref();
+        let algorithm = match config.jwt_algorithm {
+            JwtAlgorithm::Hs256 => {
+                let secret = secret.context("JWT_SECRET must be set for HS256")?;
+                return Ok(Self {
+                    signing_header: Header::new(Algorithm::HS256),
+                    signing_key: EncodingKey::from_secret(secret.as_bytes()),
+                    verification_keys: Vec::new(),
+                    secret: Some(DecodingKey::from_secret(secret.as_bytes())),
+                    secret_until: None,
+                });
+            }
+            JwtAlgorithm::Rs256 => Algorithm::RS256,
+            JwtAlgorithm::EdDsa => Algorithm::EdDSA,
+        };
+        let signing_file = config
+            .jwt_signing_key_file
+            .as_deref()
+            .context("JWT_SIGNING_KEY_FILE must be set")?;
+        let pem = read(signing_file)?;
+        let signing_key = match algorithm {
+            Algorithm::RS256 => EncodingKey::from_rsa_pem(&pem),
+            _ => EncodingKey::from_ed_pem(&pem),
+        }
+        .with_context(|| format!("invalid private key in {signing_file}"))?;
+
+        let verification_keys = config
+            .jwt_verification_key_files
+            .iter()
+            .map(|path| load_public_key(path))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        // Find the signing key's own public key by signing a probe token.
+        let probe = jsonwebtoken::encode(
+            &Header::new(algorithm),
+            &json!({ "probe": true }),
+            &signing_key,
+        )?;
+        let mut validation = Validation::new(algorithm);
+        validation.required_spec_claims.clear();
+        validation.validate_exp = false;
+        let kid = verification_keys
+            .iter()
+            .filter(|k| k.algorithm == algorithm)
+            .find(|k| {
+                jsonwebtoken::decode::<serde_json::Value>(&probe, &k.key, &validation).is_ok()
+            })
+            .map(|k| k.kid.clone())
+            .ok_or_else(|| {
+                anyhow!("the public key of {signing_file} is not in JWT_VERIFICATION_KEY_FILES")
+            })?;
+
+        let mut signing_header = Header::new(algorithm);
+        signing_header.kid = Some(kid);
+        let secret = match (config.jwt_legacy_hs256_until, secret) {
+            (Some(until), Some(secret)) if until > Utc::now() => {
+                tracing::warn!(
+                    "accepting HS256 tokens signed with JWT_SECRET until {until} \
+                     (JWT_LEGACY_HS256_UNTIL); unset both once it has passed"
+                );
+                Some(DecodingKey::from_secret(secret.as_bytes()))
+            }
+            _ => None,
+        };
+        Ok(Self {
+            signing_header,
+            signing_key,
+            verification_keys,
+            secret,
+            secret_until: config.jwt_legacy_hs256_until,
+        })
+    }
+
+    pub fn header(&self) -> &Header {
+        &self.signing_header
+    }
+
+    pub fn encoding_key(&self) -> &EncodingKey {
+        &self.signing_key
+    }
+
+    /// The key and algorithm to verify a token with the given header. The
+    /// algorithm comes from our key, never from the token.
+    pub fn decoding_key(&self, header: &Header) -> Option<(&DecodingKey, Algorithm)> {
+        match &header.kid {
+            Some(kid) => self
+                .verification_keys
+                .iter()
+                .find(|k| &k.kid == kid)
+                .map(|k| (&k.key, k.algorithm)),
+            None => match self.secret_until {
+                Some(until) if Utc::now() >= until => None,
+                _ => self.secret.as_ref().map(|key| (key, Algorithm::HS256)),
+            },
+        }
+    }
+
+    /// Public keys for `/.well-known/jwks.json`.
+    pub fn jwks(&self) -> Vec<PublicJwk> {
+        self.verification_keys
+            .iter()
+            .map(|k| k.jwk.clone())
+            .collect()
+    }
+}
+
+fn read(path: &str) -> anyhow::Result<Vec<u8>> {
+    std::fs::read(path).with_context(|| format!("failed to read key file {path}"))
+}
+
+/// Load an RSA or Ed25519 public key (PEM `PUBLIC KEY`, i.e.
+/// SubjectPublicKeyInfo).
+fn load_public_key(path: &str) -> anyhow::Result<VerificationKey> {
+    let pem = pem::parse(read(path)?).with_context(|| format!("invalid PEM in {path}"))?;
+    if pem.tag() != "PUBLIC KEY" {
+        bail!("{path} must hold a PEM \"PUBLIC KEY\", not {:?}", pem.tag());
+    }
+    let (algorithm_oid, key_bits) =
+        parse_spki(pem.contents()).with_context(|| format!("invalid public key in {path}"))?;
+
+    let (algorithm, key, jwk) = if algorithm_oid == oid!(1, 2, 840, 113549, 1, 1, 1) {
+        let (n, e) = parse_rsa_public_key(&key_bits)
+            .with_context(|| format!("invalid RSA public key in {path}"))?;
+        let (n, e) = (URL_SAFE_NO_PAD.encode(n), URL_SAFE_NO_PAD.encode(e));
+        let kid = thumbprint(&format!(r#"{{"e":"{e}","kty":"RSA","n":"{n}"}}"#));
+        let key = DecodingKey::from_rsa_components(&n, &e)?;
+        let jwk = PublicJwk {
+            kty: "RSA",
+            use_: "sig",
+            alg: "RS256",
+            kid,
+            n: Some(n),
+            e: Some(e),
+            crv: None,
+            x: None,
+        };
+        (Algorithm::RS256, key, jwk)
+    } else if algorithm_oid == oid!(1, 3, 101, 112) {
+        if key_bits.len() != 32 {
+            bail!("invalid Ed25519 public key in {path}");
+        }
+        let x = URL_SAFE_NO_PAD.encode(&key_bits);
+        let kid = thumbprint(&format!(r#"{{"crv":"Ed25519","kty":"OKP","x":"{x}"}}"#));
+        let key = DecodingKey::from_ed_components(&x)?;
+        let jwk = PublicJwk {
+            kty: "OKP",
+            use_: "sig",
+            alg: "EdDSA",
+            kid,
+            n: None,
+            e: None,
+            crv: Some("Ed25519"),
+            x: Some(x),
+        };
+        (Algorithm::EdDSA, key, jwk)
+    } else {
+        bail!("{path} holds neither an RSA nor an Ed25519 key");
+    };
+
+    Ok(VerificationKey {
+        kid: jwk.kid.clone(),
+        algorithm,
+        key,
+        jwk,
+    })
+}
+
+/// SubjectPublicKeyInfo ::= SEQUENCE { SEQUENCE { algorithm OID, .. },
+/// subjectPublicKey BIT STRING }
+fn parse_spki(der: &[u8]) -> anyhow::Result<(simple_asn1::OID, Vec<u8>)> {
+    let blocks = simple_asn1::from_der(der)?;
+    let [ASN1Block::Sequence(_, fields)] = blocks.as_slice() else {
+        bail!("expected a SubjectPublicKeyInfo sequence");
+    };
+    let [ASN1Block::Sequence(_, algorithm), ASN1Block::BitString(_, _, key)] = fields.as_slice()
+    else {
+        bail!("expected an algorithm and a key");
+    };
+    let Some(ASN1Block::ObjectIdentifier(_, oid)) = algorithm.first() else {
+        bail!("missing algorithm identifier");
+    };
+    Ok((oid.clone(), key.clone()))
+}
+
+/// RSAPublicKey ::= SEQUENCE { modulus INTEGER, publicExponent INTEGER }.
+/// Returns both as unsigned big-endian bytes.
+fn parse_rsa_public_key(der: &[u8]) -> anyhow::Result<(Vec<u8>, Vec<u8>)> {
+    let blocks = simple_asn1::from_der(der)?;
+    let [ASN1Block::Sequence(_, fields)] = blocks.as_slice() else {
+        bail!("expected an RSAPublicKey sequence");
+    };
+    let [ASN1Block::Integer(_, n), ASN1Block::Integer(_, e)] = fields.as_slice() else {
+        bail!("expected modulus and exponent");
+    };
+    Ok((n.to_bytes_be().1, e.to_bytes_be().1))
+}
+
+/// RFC 7638 thumbprint of a JWK given as its required members, in
+/// lexicographic order and without whitespace.
+fn thumbprint(canonical_jwk: &str) -> String {
+    URL_SAFE_NO_PAD.encode(Sha256::digest(canonical_jwk.as_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use simple_asn1::{to_der, BigInt, BigUint};
+
+    /// The RSA key from RFC 7638 section 3.1.
+    const RFC7638_N: &str = concat!(
+        "0vx7agoebGcQSuuPiLJXZptN9nndrQmbXEps2aiAFbWhM78LhWx4cbbfAAtVT86zwu1RK7aPFFxuhDR1",
+        "L6tSoc_BJECPebWKRXjBZCiFV4n3oknjhMstn64tZ_2W-5JsGY4Hc5n9yBXArwl93lqt7_RN5w6Cf0h4",
+        "QyQ5v-65YGjQR0_FDW2QvzqY368QQMicAtaSqzs8KJZgnYb9c7d0zgdAZHzu6qMQvRL5hajrn1n91CbO",
+        "pbISD08qNLyrdkt-bFTWhAI4vMQFh6WeZu0fM4lFd2NcRwr3XPksINHaQ-G_xBniIqbw0Ls1jF44-csF",
+        "Cur-kEgU8awapJzKnqDKgw",
+    );
+    const RFC7638_E: &str = "AQAB";
+    const RFC7638_THUMBPRINT: &str = "NzbLsXh8uDCcd-6MNwXF4W_7noWXFZAfHkxZsRGC9Xs";
+
+    /// The Ed25519 key from RFC 8037 appendix A.2 and its thumbprint (A.3).
+    const RFC8037_X: &str = "11qYAYKxCrfVS_7TyWQHOg7hcvPapiMlrwIaaPcHURo";
+    const RFC8037_THUMBPRINT: &str = "kPrK_qmxVWaYVA9wwBF6Iuo3vVzz7TxHCTwXBygrS4k";
+
+    fn spki(algorithm: Vec<ASN1Block>, key: Vec<u8>) -> Vec<u8> {
+        to_der(&ASN1Block::Sequence(
+            0,
+            vec![
+                ASN1Block::Sequence(0, algorithm),
+                ASN1Block::BitString(0, key.len() * 8, key),
+            ],
+        ))
+        .unwrap()
+    }
+
+    /// Load `der` the way `JWT_VERIFICATION_KEY_FILES` entries are loaded.
+    fn load(der: Vec<u8>) -> anyhow::Result<VerificationKey> {
+        let name = format!("jwt-key-test-{}.pem", uuid::Uuid::new_v4());
+        let path = std::env::temp_dir().join(name);
+        std::fs::write(&path, pem::encode(&pem::Pem::new("PUBLIC KEY", der))).unwrap();
+        let key = load_public_key(path.to_str().unwrap());
+        std::fs::remove_file(&path).unwrap();
+        key
+    }
+
+    fn keys(secret_until: Option<DateTime<Utc>>) -> JwtKeys {
+        JwtKeys {
+            signing_header: Header::new(Algorithm::HS256),
+            signing_key: EncodingKey::from_secret(b"secret"),
+            verification_keys: Vec::new(),
+            secret: Some(DecodingKey::from_secret(b"secret")),
+            secret_until,
+        }
+    }
+
+    #[test]
+    fn rsa_kid_is_the_rfc_7638_thumbprint() {
+        let canonical = format!(r#"{{"e":"{RFC7638_E}","kty":"RSA","n":"{RFC7638_N}"}}"#);
+        assert_eq!(thumbprint(&canonical), RFC7638_THUMBPRINT);
+
+        let n = URL_SAFE_NO_PAD.decode(RFC7638_N).unwrap();
+        let e = URL_SAFE_NO_PAD.decode(RFC7638_E).unwrap();
+        let rsa_public_key = to_der(&ASN1Block::Sequence(
+            0,
+            vec![
+                ASN1Block::Integer(0, BigInt::from(BigUint::from_bytes_be(&n))),
+                ASN1Block::Integer(0, BigInt::from(BigUint::from_bytes_be(&e))),
+            ],
+        ))
+        .unwrap();
+        let der = spki(
+            vec![
+                ASN1Block::ObjectIdentifier(0, oid!(1, 2, 840, 113549, 1, 1, 1)),
+                ASN1Block::Null(0),
+            ],
+            rsa_public_key,
+        );
+
+        let key = load(der).unwrap();
+        assert_eq!(key.algorithm, Algorithm::RS256);
+        assert_eq!(key.kid, RFC7638_THUMBPRINT);
+        assert_eq!(key.jwk.n.as_deref(), Some(RFC7638_N));
+        assert_eq!(key.jwk.e.as_deref(), Some(RFC7638_E));
+    }
+
+    #[test]
+    fn ed25519_spki_round_trips() {
+        let x = URL_SAFE_NO_PAD.decode(RFC8037_X).unwrap();
+        let der = spki(vec![ASN1Block::ObjectIdentifier(0, oid!(1, 3, 101, 112))], x.clone());
+        // The fixed 12-byte prefix of every Ed25519 SubjectPublicKeyInfo.
+        assert_eq!(&der[..12], b"\x30\x2a\x30\x05\x06\x03\x2b\x65\x70\x03\x21\x00");
+
+        let (algorithm, key_bits) = parse_spki(&der).unwrap();
+        assert_eq!(algorithm, oid!(1, 3, 101, 112));
+        assert_eq!(key_bits, x);
+
+        let key = load(der).unwrap();
+        assert_eq!(key.algorithm, Algorithm::EdDSA);
+        assert_eq!(key.kid, RFC8037_THUMBPRINT);
+        assert_eq!(key.jwk.x.as_deref(), Some(RFC8037_X));
+
+        let short = spki(vec![ASN1Block::ObjectIdentifier(0, oid!(1, 3, 101, 112))], vec![0; 31]);
+        assert!(load(short).is_err());
+    }
+
+    #[test]
+    fn legacy_hs256_tokens_are_accepted_only_inside_the_window() {
+        let legacy = Header::new(Algorithm::HS256);
+        let accepted = |keys: &JwtKeys| keys.decoding_key(&legacy).map(|(_, alg)| alg);
+
+        // HS256 deployments: no window.
+        assert_eq!(accepted(&keys(None)), Some(Algorithm::HS256));
+        let open = keys(Some(Utc::now() + chrono::Duration::hours(1)));
+        assert_eq!(accepted(&open), Some(Algorithm::HS256));
+        let closed = keys(Some(Utc::now() - chrono::Duration::seconds(1)));
+        assert_eq!(accepted(&closed), None);
+
+        // A kid never falls back to the secret.
+        let mut unknown_kid = Header::new(Algorithm::HS256);
+        unknown_kid.kid = Some("unknown".into());
+        assert!(open.decoding_key(&unknown_kid).is_none());
+    }
+}
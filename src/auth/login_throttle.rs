@@ -0,0 +1,135 @@
+//! Login throttling.
+//!
+//! Failed logins (a wrong password, or a wrong code at the 2FA step) are
+//! counted per username and per client address. Past an allowance, every
+//! further failure locks that username or address for twice as long as the
+//! previous one, up to a cap; while locked, logins are refused with `429`
+//! before the password or code is looked at. Usernames are counted whether
+//! or not the account exists, so a lockout reveals nothing. Counters live in
+//! the database, so the limits hold across instances, and are forgotten a day
+//! after the last failure. Each lockout is written to the audit trail.
+
+use chrono::Utc;
+use serde_json::json;
+use uuid::Uuid;
+
+use crate::{
+    auth::session::ClientInfo,
+    db::{
+        audit::insert_audit_event,
+        login_failures::{
+            clear_login_failures, lock_login, login_locked_until, record_login_failure,
+        },
+    },
+    error::{AppError, AppResult},
+    state::AppState,
+};
+
+/// How long failed logins are remembered after the last one.
+pub const FAILURE_MEMORY: chrono::Duration = chrono::Duration::days(1);
+
+const SCOPE_USERNAME: &str = "username";
+const SCOPE_IP: &str = "ip";
+
+/// Usernames differing only in case share a counter.
+fn username_key(username: &str) -> String {
+    username.to_lowercase()
+}
+
+/// Refuse the login if the username or the client's address is locked out.
+pub async fn check(state: &AppState, username: &str, client: &ClientInfo) -> AppResult<()> {
+    let locked_until =
+        login_locked_until(&state.db, &username_key(username), client.ip.as_deref()).await?;
+    match locked_until {
+        Some(until) => Err(AppError::TooManyRequests {
+            message: "too many failed login attempts; try again later".into(),
+            retry_after_secs: (until - Utc::now()).num_seconds().max(0) as u64 + 1,
+        }),
+        None => Ok(()),
+    }
+}
+
+/// Count a failed login, locking the username or address once it is over its
+/// allowance. `user_id` is the account the username belongs to, if any.
+pub async fn record_failure(
+    state: &AppState,
+    username: &str,
+    user_id: Option<Uuid>,
+    client: &ClientInfo,
+) -> AppResult<()> {
+    let limits = state.login_limits;
+    let key = username_key(username);
+    count(
+        state,
+        SCOPE_USERNAME,
+        &key,
+        limits.max_failures,
+        user_id,
+        client,
+    )
+    .await?;
+    if let Some(ip) = &client.ip {
+        count(
+            state,
+            SCOPE_IP,
+            ip,
+            limits.max_failures_per_ip,
+            None,
+            client,
+        )
+        .await?;
+    }
+    Ok(())
+}
+
+/// A successful login (including the second factor, if enabled) clears the
+/// username's failures. The address keeps its
+/// count, or one account of its own would let an attacker reset it.
+pub async fn record_success(state: &AppState, username: &str) -> AppResult<()> {
+    clear_login_failures(&state.db, SCOPE_USERNAME, &username_key(username)).await
+}
+
+async fn count(
+    state: &AppState,
+    scope: &str,
+    key: &str,
+    allowance: i32,
+    user_id: Option<Uuid>,
+    client: &ClientInfo,
+) -> AppResult<()> {
+    let failures = record_login_failure(&state.db, scope, key, Utc::now() - FAILURE_MEMORY).await?;
+    if failures <= allowance {
+        return Ok(());
+    }
+
+    let limits = state.login_limits;
+    let doublings = (failures - allowance - 1).min(30) as u32;
+    let lockout = limits
+        .lockout_base
+        .checked_mul(1 << doublings)
+        .unwrap_or(limits.lockout_max)
+        .min(limits.lockout_max);
+    let locked_until = Utc::now() + lockout;
+    lock_login(&state.db, scope, key, locked_until).await?;
+
+    tracing::warn!(
+        scope,
+        key,
+        failures,
+        "login locked for {}s",
+        lockout.num_seconds()
+    );
+    insert_audit_event(
+        &state.db,
+        "login_locked",
+        user_id,
+        client.ip.as_deref(),
+        json!({
+            "scope": scope,
+            "key": key,
+            "failures": failures,
+            "locked_until": locked_until,
+        }),
+    )
+    .await
+}
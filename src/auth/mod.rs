@@ -1,4 +1,14 @@
 pub mod extractor;
 pub mod jwt;
+pub mod keys;
+pub mod login_throttle;
+pub mod oidc;
+pub mod opaque;
 pub mod password;
-
+pub mod password_reset;
+pub mod refresh;
+pub mod revocation;
+pub mod session;
+pub mod totp;
+pub mod two_factor;
+pub mod ws_ticket;
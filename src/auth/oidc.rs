@@ -0,0 +1,492 @@
+//! Sign-in through OpenID Connect providers (authorization code flow with
+//! PKCE).
+//!
+//! `begin_login` sends the browser to the provider with a fresh `state`,
+//! `nonce` and PKCE challenge, and ties the attempt to that browser with a
+//! cookie. When the provider redirects back, `complete_login` exchanges the
+//! code, verifies the ID token against the provider's published keys and finds
+//! or creates the linked user. The browser then returns to the web client with
+//! a short-lived, single-use grant, which the client exchanges for tokens just
+//! like a password login.
+//!
+//! A first sign-in always creates a new account. Matching existing accounts by
+//! email would hand them to whoever controls that address at the provider.
+
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use anyhow::Context;
+use chrono::Utc;
+use jsonwebtoken::{jwk::JwkSet, Algorithm, DecodingKey, Validation};
+use rand_core::{OsRng, RngCore};
+use reqwest::Url;
+use serde::Deserialize;
+use uuid::Uuid;
+
+use crate::{
+    auth::opaque::{hash_token, new_token},
+    config::{Config, OidcProviderConfig},
+    db::{
+        oidc::{
+            claim_oidc_login, complete_oidc_login, create_user_with_identity, insert_oidc_login,
+            redeem_oidc_grant, touch_identity, NewOidcLogin,
+        },
+        users::get_user_by_email,
+    },
+    error::{AppError, AppResult},
+    state::AppState,
+};
+
+/// How long the user has to finish signing in at the provider.
+const LOGIN_TTL: chrono::Duration = chrono::Duration::minutes(10);
+/// How long the web client has to exchange its grant.
+const GRANT_TTL: chrono::Duration = chrono::Duration::minutes(1);
+/// Provider metadata and keys are re-fetched after this long.
+const METADATA_TTL: Duration = Duration::from_secs(3600);
+/// An ID token signed with an unknown key triggers a key refresh, at most
+/// this often.
+const MIN_JWKS_REFRESH_INTERVAL: Duration = Duration::from_secs(60);
+const HTTP_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Symmetric algorithms are refused: the client secret must not double as a
+/// signing key we would accept.
+const ID_TOKEN_ALGORITHMS: &[Algorithm] = &[
+    Algorithm::RS256,
+    Algorithm::RS384,
+    Algorithm::RS512,
+    Algorithm::PS256,
+    Algorithm::PS384,
+    Algorithm::PS512,
+    Algorithm::ES256,
+    Algorithm::ES384,
+    Algorithm::EdDSA,
+];
+
+/// Name of the cookie that ties a sign-in to the browser that started it.
+pub const BROWSER_COOKIE: &str = "oidc_browser";
+/// Only sent to the OIDC endpoints.
+const BROWSER_COOKIE_PATH: &str = "/api/auth/oidc";
+
+/// The subset of the provider's discovery document we use.
+#[derive(Debug, Deserialize)]
+struct ProviderMetadata {
+    issuer: String,
+    authorization_endpoint: String,
+    token_endpoint: String,
+    jwks_uri: String,
+    #[serde(default)]
+    token_endpoint_auth_methods_supported: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenResponse {
+    id_token: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct IdTokenClaims {
+    sub: String,
+    nonce: Option<String>,
+    azp: Option<String>,
+    email: Option<String>,
+    /// Some providers send the string `"true"`.
+    email_verified: Option<serde_json::Value>,
+    preferred_username: Option<String>,
+    name: Option<String>,
+}
+
+impl IdTokenClaims {
+    fn verified_email(&self) -> Option<&str> {
+        let verified = matches!(&self.email_verified, Some(serde_json::Value::Bool(true)))
+            || matches!(&self.email_verified, Some(serde_json::Value::String(s)) if s == "true");
+        self.email.as_deref().filter(|_| verified)
+    }
+}
+
+struct Provider {
+    config: OidcProviderConfig,
+    metadata: Mutex<Option<(Arc<ProviderMetadata>, Instant)>>,
+    jwks: Mutex<Option<(Arc<JwkSet>, Instant)>>,
+}
+
+/// The configured providers and their cached metadata and signing keys.
+#[derive(Clone)]
+pub struct OidcProviders {
+    providers: Arc<Vec<Provider>>,
+    http: reqwest::Client,
+    login_redirect_url: Arc<String>,
+}
+
+impl OidcProviders {
+    pub fn from_config(config: &Config) -> anyhow::Result<Self> {
+        let http = reqwest::Client::builder()
+            .timeout(HTTP_TIMEOUT)
+            .redirect(reqwest::redirect::Policy::none())
+            .build()
+            .context("failed to build HTTP client for OIDC")?;
+        let providers = config
+            .oidc_providers
+            .iter()
+            .map(|config| Provider {
+                config: config.clone(),
+                metadata: Mutex::new(None),
+                jwks: Mutex::new(None),
+            })
+            .collect();
+
+        Ok(Self {
+            providers: Arc::new(providers),
+            http,
+            login_redirect_url: Arc::new(config.oidc_login_redirect_url.clone()),
+        })
+    }
+
+    pub fn configs(&self) -> impl Iterator<Item = &OidcProviderConfig> {
+        self.providers.iter().map(|p| &p.config)
+    }
+
+    /// Web client page the browser returns to after a sign-in.
+    pub fn login_redirect_url(&self) -> &str {
+        &self.login_redirect_url
+    }
+
+    fn get(&self, name: &str) -> AppResult<&Provider> {
+        self.providers
+            .iter()
+            .find(|p| p.config.name == name)
+            .ok_or_else(|| AppError::NotFound("unknown sign-in provider".into()))
+    }
+
+    async fn metadata(&self, provider: &Provider) -> AppResult<Arc<ProviderMetadata>> {
+        if let Some((metadata, fetched_at)) = &*lock(&provider.metadata) {
+            if fetched_at.elapsed() < METADATA_TTL {
+                return Ok(metadata.clone());
+            }
+        }
+
+        let issuer = provider.config.issuer.trim_end_matches('/');
+        let url = format!("{issuer}/.well-known/openid-configuration");
+        let metadata: ProviderMetadata = self.fetch_json(&url).await?;
+        if metadata.issuer.trim_end_matches('/') != issuer {
+            return Err(AppError::Internal(anyhow::anyhow!(
+                "OIDC provider {} reports issuer {:?}, expected {:?}",
+                provider.config.name,
+                metadata.issuer,
+                provider.config.issuer
+            )));
+        }
+
+        let metadata = Arc::new(metadata);
+        *lock(&provider.metadata) = Some((metadata.clone(), Instant::now()));
+        Ok(metadata)
+    }
+
+    /// The provider key for `kid`, re-fetching the key set if it is stale or
+    /// does not contain that key.
+    async fn signing_key(
+        &self,
+        provider: &Provider,
+        metadata: &ProviderMetadata,
+        kid: Option<&str>,
+    ) -> AppResult<DecodingKey> {
+        let cached = lock(&provider.jwks).clone();
+        let keys = match cached {
+            Some((keys, fetched_at))
+                if fetched_at.elapsed() < METADATA_TTL
+                    && (find_key(&keys, kid).is_some()
+                        || fetched_at.elapsed() < MIN_JWKS_REFRESH_INTERVAL) =>
+            {
+                keys
+            }
+            _ => {
+                let keys: Arc<JwkSet> = Arc::new(self.fetch_json(&metadata.jwks_uri).await?);
+                *lock(&provider.jwks) = Some((keys.clone(), Instant::now()));
+                keys
+            }
+        };
+
+        let jwk = find_key(&keys, kid)
+            .ok_or_else(|| AppError::Unauthorized("ID token signed with an unknown key".into()))?;
+        DecodingKey::from_jwk(jwk)
+            .map_err(|err| AppError::Unauthorized(format!("unusable provider key: {err}")))
+    }
+
+    async fn fetch_json<T: serde::de::DeserializeOwned>(&self, url: &str) -> AppResult<T> {
+        let response = self
+            .http
+            .get(url)
+            .send()
+            .await
+            .and_then(|r| r.error_for_status())
+            .with_context(|| format!("failed to fetch {url}"))?;
+        let body = response
+            .json()
+            .await
+            .with_context(|| format!("invalid JSON from {url}"))?;
+        Ok(body)
+    }
+
+    /// Redeem the authorization code for the provider's ID token.
+    async fn exchange_code(
+        &self,
+        provider: &Provider,
+        metadata: &ProviderMetadata,
+        code: &str,
+        code_verifier: &str,
+    ) -> AppResult<String> {
+        let config = &provider.config;
+        let mut form = vec![
+            ("grant_type", "authorization_code"),
+            ("code", code),
+            ("redirect_uri", config.redirect_uri.as_str()),
+            ("client_id", config.client_id.as_str()),
+            ("code_verifier", code_verifier),
+        ];
+        let mut request = self.http.post(&metadata.token_endpoint);
+        if let Some(secret) = &config.client_secret {
+            // `client_secret_basic` is the default when the provider does not
+            // say; prefer `client_secret_post` when it is offered.
+            let methods = &metadata.token_endpoint_auth_methods_supported;
+            if methods.iter().any(|m| m == "client_secret_post") {
+                form.push(("client_secret", secret.as_str()));
+            } else {
+                request = request.basic_auth(&config.client_id, Some(secret));
+            }
+        }
+
+        let response = request
+            .form(&form)
+            .send()
+            .await
+            .context("failed to reach the OIDC token endpoint")?;
+        if !response.status().is_success() {
+            let status = response.status();
+            let body = response.text().await.unwrap_or_default();
+            tracing::warn!(provider = %config.name, %status, body, "OIDC code exchange failed");
+            return Err(AppError::Unauthorized(
+                "the provider rejected the sign-in".into(),
+            ));
+        }
+        let tokens: TokenResponse = response
+            .json()
+            .await
+            .context("invalid OIDC token response")?;
+        Ok(tokens.id_token)
+    }
+
+    async fn verify_id_token(
+        &self,
+        provider: &Provider,
+        metadata: &ProviderMetadata,
+        id_token: &str,
+        nonce: &str,
+    ) -> AppResult<IdTokenClaims> {
+        let invalid = |err: jsonwebtoken::errors::Error| {
+            AppError::Unauthorized(format!("invalid ID token: {err}"))
+        };
+        let header = jsonwebtoken::decode_header(id_token).map_err(invalid)?;
+        if !ID_TOKEN_ALGORITHMS.contains(&header.alg) {
+            return Err(AppError::Unauthorized(format!(
+                "ID token algorithm {:?} is not accepted",
+                header.alg
+            )));
+        }
+        let key = self
+            .signing_key(provider, metadata, header.kid.as_deref())
+            .await?;
+
+        let client_id = &provider.config.client_id;
+        let mut validation = Validation::new(header.alg);
+        validation.set_issuer(&[&metadata.issuer]);
+        validation.set_audience(&[client_id]);
+        validation.set_required_spec_claims(&["exp", "iss", "aud", "sub"]);
+        let claims = jsonwebtoken::decode::<IdTokenClaims>(id_token, &key, &validation)
+            .map_err(invalid)?
+            .claims;
+
+        if claims.nonce.as_deref() != Some(nonce) {
+            return Err(AppError::Unauthorized("ID token nonce mismatch".into()));
+        }
+        if claims.azp.as_ref().is_some_and(|azp| azp != client_id) {
+            return Err(AppError::Unauthorized(
+                "ID token issued to another client".into(),
+            ));
+        }
+        Ok(claims)
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> std::sync::MutexGuard<'_, T> {
+    mutex
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// Without a `kid`, a key set with a single key is unambiguous.
+fn find_key<'a>(keys: &'a JwkSet, kid: Option<&str>) -> Option<&'a jsonwebtoken::jwk::Jwk> {
+    match kid {
+        Some(kid) => keys.find(kid),
+        None if keys.keys.len() == 1 => keys.keys.first(),
+        None => None,
+    }
+}
+
+/// Start a sign-in with `provider_name`. Returns the provider URL to send the
+/// browser to, and a `Set-Cookie` value for the [`BROWSER_COOKIE`].
+pub async fn begin_login(state: &AppState, provider_name: &str) -> AppResult<(String, String)> {
+    let provider = state.oidc.get(provider_name)?;
+    let metadata = state.oidc.metadata(provider).await?;
+
+    let login_state = new_token();
+    let nonce = new_token();
+    let code_verifier = new_token();
+    let browser = new_token();
+    insert_oidc_login(
+        &state.db,
+        NewOidcLogin {
+            provider: &provider.config.name,
+            state_hash: &hash_token(&login_state),
+            browser_hash: &hash_token(&browser),
+            nonce: &nonce,
+            code_verifier: &code_verifier,
+            expires_at: Utc::now() + LOGIN_TTL,
+        },
+    )
+    .await?;
+
+    let config = &provider.config;
+    // The S256 challenge is base64url(SHA-256(verifier)), which is exactly
+    // how opaque tokens are hashed.
+    let code_challenge = hash_token(&code_verifier);
+    let url = Url::parse_with_params(
+        &metadata.authorization_endpoint,
+        [
+            ("response_type", "code"),
+            ("client_id", config.client_id.as_str()),
+            ("redirect_uri", config.redirect_uri.as_str()),
+            ("scope", config.scopes.as_str()),
+            ("state", login_state.as_str()),
+            ("nonce", nonce.as_str()),
+            ("code_challenge", code_challenge.as_str()),
+            ("code_challenge_method", "S256"),
+        ],
+    )
+    .context("invalid OIDC authorization endpoint")?;
+
+    // `SameSite=Lax` still sends the cookie on the provider's top-level
+    // redirect back to us.
+    let secure = if config.redirect_uri.starts_with("https://") {
+        "; Secure"
+    } else {
+        ""
+    };
+    let cookie = format!(
+        "{BROWSER_COOKIE}={browser}; Path={BROWSER_COOKIE_PATH}; Max-Age={}; HttpOnly; SameSite=Lax{secure}",
+        LOGIN_TTL.num_seconds()
+    );
+    Ok((url.into(), cookie))
+}
+
+/// `Set-Cookie` value that removes the [`BROWSER_COOKIE`].
+pub fn clear_browser_cookie() -> String {
+    format!("{BROWSER_COOKIE}=; Path={BROWSER_COOKIE_PATH}; Max-Age=0; HttpOnly; SameSite=Lax")
+}
+
+/// Handle the provider's redirect back. Returns a grant for the web client to
+/// exchange with [`redeem_grant`].
+pub async fn complete_login(
+    state: &AppState,
+    provider_name: &str,
+    code: &str,
+    login_state: &str,
+    browser: Option<&str>,
+) -> AppResult<String> {
+    let provider = state.oidc.get(provider_name)?;
+    let pending = claim_oidc_login(&state.db, &provider.config.name, &hash_token(login_state))
+        .await?
+        .ok_or_else(|| AppError::Unauthorized("invalid or expired sign-in".into()))?;
+    // Stops a victim from being signed in to an attacker's account through a
+    // callback link the attacker started.
+    if browser.map(hash_token).as_deref() != Some(pending.browser_hash.as_str()) {
+        return Err(AppError::Unauthorized(
+            "sign-in was started in another browser".into(),
+        ));
+    }
+
+    let metadata = state.oidc.metadata(provider).await?;
+    let id_token = state
+        .oidc
+        .exchange_code(provider, &metadata, code, &pending.code_verifier)
+        .await?;
+    let claims = state
+        .oidc
+        .verify_id_token(provider, &metadata, &id_token, &pending.nonce)
+        .await?;
+    let user_id = find_or_create_user(state, &provider.config.name, &claims).await?;
+
+    let grant = new_token();
+    complete_oidc_login(
+        &state.db,
+        pending.id,
+        user_id,
+        &hash_token(&grant),
+        Utc::now() + GRANT_TTL,
+    )
+    .await?;
+    Ok(grant)
+}
+
+/// Exchange a grant from [`complete_login`] for the user who signed in.
+pub async fn redeem_grant(state: &AppState, grant: &str) -> AppResult<Uuid> {
+    redeem_oidc_grant(&state.db, &hash_token(grant))
+        .await?
+        .ok_or_else(|| AppError::Unauthorized("invalid or expired sign-in grant".into()))
+}
+
+async fn find_or_create_user(
+    state: &AppState,
+    provider: &str,
+    claims: &IdTokenClaims,
+) -> AppResult<Uuid> {
+    let email = claims.verified_email();
+    if let Some(user_id) = touch_identity(&state.db, provider, &claims.sub, email).await? {
+        return Ok(user_id);
+    }
+
+    // Keep the address only if no other account uses it.
+    let account_email = match email {
+        Some(email) if get_user_by_email(&state.db, email).await?.is_none() => Some(email),
+        _ => None,
+    };
+    let base = username_base(state, claims);
+    for attempt in 0..5 {
+        let username = if attempt == 0 {
+            base.clone()
+        } else {
+            format!("{base}-{:04x}", OsRng.next_u32() as u16)
+        };
+        if let Some(user_id) =
+            create_user_with_identity(&state.db, &username, account_email, provider, &claims.sub)
+                .await?
+        {
+            tracing::info!(%user_id, provider, "created account from OIDC sign-in");
+            return Ok(user_id);
+        }
+    }
+    Err(AppError::Internal(anyhow::anyhow!(
+        "no free username for OIDC sign-in based on {base:?}"
+    )))
+}
+
+/// A username suggested by the provider's claims, reduced to a valid one.
+fn username_base(state: &AppState, claims: &IdTokenClaims) -> String {
+    let suggested = claims
+        .preferred_username
+        .as_deref()
+        .or_else(|| claims.email.as_deref().and_then(|e| e.split('@').next()))
+        .or(claims.name.as_deref())
+        .unwrap_or_default();
+    state.credential_policy.username_from_hint(suggested)
+}
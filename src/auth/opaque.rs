@@ -0,0 +1,18 @@
+//! Random bearer tokens stored only as hashes (refresh tokens, password reset
+//! tokens). A leaked database row cannot be turned back into a usable token.
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use rand_core::{OsRng, RngCore};
+use sha2::{Digest, Sha256};
+
+/// 256 random bits, URL-safe base64.
+pub fn new_token() -> String {
+    let mut bytes = [0u8; 32];
+    OsRng.fill_bytes(&mut bytes);
+    URL_SAFE_NO_PAD.encode(bytes)
+}
+
+/// The value to store and look tokens up by.
+pub fn hash_token(token: &str) -> String {
+    URL_SAFE_NO_PAD.encode(Sha256::digest(token.as_bytes()))
+}
@@ -1,3 +1,5 @@
+use std::sync::OnceLock;
+
 use argon2::{
     password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
     Argon2,
@@ -36,3 +38,13 @@ pub fn verify_password(hash: &str, password: &str) -> Result<bool, AppError> {
     Ok(result)
 }
 
+
+/// Run a verification that cannot succeed, so a login for an unknown user
+/// takes as long as one with a wrong password.
+pub fn verify_dummy_password(password: &str) {
+    static DUMMY_HASH: OnceLock<Option<String>> = OnceLock::new();
+    let hash = DUMMY_HASH.get_or_init(|| hash_password("not a real password").ok());
+    if let Some(hash) = hash {
+        let _ = verify_password(hash, password);
+    }
+}
@@ -0,0 +1,75 @@
+//! Password reset by emailed link.
+//!
+//! A reset token is single use, expires after `PASSWORD_RESET_TTL_MINUTES` and
+//! is stored hashed. Requesting a new one invalidates earlier ones. Whether an
+//! address is registered is never revealed to the requester.
+
+use chrono::Utc;
+use uuid::Uuid;
+
+use crate::{
+    auth::opaque::{hash_token, new_token},
+    db::{
+        password_resets::{
+            consume_password_reset_token, get_password_reset_user, insert_password_reset_token,
+        },
+        users::get_user_by_email,
+    },
+    error::AppResult,
+    mailer::Email,
+    models::user::User,
+    state::AppState,
+};
+
+/// Mail a reset link to the account registered with `email`, if any.
+///
+/// Runs in the background so the response time does not reveal whether the
+/// address is registered.
+pub fn request_reset(state: &AppState, email: String) {
+    let state = state.clone();
+    tokio::spawn(async move {
+        if let Err(err) = send_reset_link(&state, &email).await {
+            tracing::error!("failed to send password reset link: {err}");
+        }
+    });
+}
+
+async fn send_reset_link(state: &AppState, email: &str) -> AppResult<()> {
+    let Some(user) = get_user_by_email(&state.db, email).await? else {
+        tracing::debug!("password reset requested for unknown address");
+        return Ok(());
+    };
+    let Some(to) = user.email else {
+        return Ok(());
+    };
+
+    let token = new_token();
+    let expires_at = Utc::now() + state.password_reset_ttl;
+    insert_password_reset_token(&state.db, user.id, &hash_token(&token), expires_at).await?;
+
+    let separator = if state.password_reset_url.contains('?') { '&' } else { '?' };
+    let link = format!("{}{separator}token={token}", state.password_reset_url);
+    let email = Email {
+        to,
+        subject: "Reset your password".to_string(),
+        body: format!(
+            "Hi {},\n\nUse this link to choose a new password. It expires in {} minutes and works once:\n\n{link}\n\nIf you did not ask for this, you can ignore this message.\n",
+            user.username,
+            state.password_reset_ttl.num_minutes(),
+        ),
+    };
+
+    state.mailer.send(&email).await?;
+    Ok(())
+}
+
+/// The user a reset token belongs to, if it is still usable. Does not spend
+/// it, so the new password can be checked first.
+pub async fn reset_token_user(state: &AppState, token: &str) -> AppResult<Option<User>> {
+    get_password_reset_user(&state.db, &hash_token(token)).await
+}
+
+/// Spend a reset token. Returns the user whose password may now be set.
+pub async fn redeem_reset_token(state: &AppState, token: &str) -> AppResult<Option<Uuid>> {
+    consume_password_reset_token(&state.db, &hash_token(token)).await
+}
@@ -0,0 +1,52 @@
+//! Opaque refresh tokens.
+//!
+//! Clients get a random token; only its SHA-256 hash is stored. Each refresh
+//! spends the presented token and issues a new one in the same session, so a
+//! token that shows up twice must have been copied and the session is revoked.
+
+use chrono::Utc;
+use uuid::Uuid;
+
+use crate::{
+    auth::opaque::{hash_token, new_token},
+    db::refresh_tokens::{insert_refresh_token, rotate_refresh_token, RotateOutcome},
+    error::{AppError, AppResult},
+    state::AppState,
+};
+
+/// Issue the first refresh token of a new session.
+pub async fn issue_refresh_token(
+    state: &AppState,
+    user_id: Uuid,
+    session_id: Uuid,
+) -> AppResult<String> {
+    let token = new_token();
+    let expires_at = Utc::now() + state.refresh_token_ttl;
+    insert_refresh_token(&state.db, user_id, session_id, &hash_token(&token), expires_at).await?;
+    Ok(token)
+}
+
+/// Exchange `presented` for a new refresh token. Returns the owning user id,
+/// its session id and the new token.
+pub async fn rotate(state: &AppState, presented: &str) -> AppResult<(Uuid, Uuid, String)> {
+    let token = new_token();
+    let expires_at = Utc::now() + state.refresh_token_ttl;
+
+    match rotate_refresh_token(&state.db, &hash_token(presented), &hash_token(&token), expires_at)
+        .await?
+    {
+        RotateOutcome::Rotated { user_id, session_id } => Ok((user_id, session_id, token)),
+        RotateOutcome::Reused { user_id, session_id } => {
+            state.revocations.mark_revoked(session_id);
+            tracing::warn!(
+                %user_id,
+                %session_id,
+                "refresh token reuse detected; revoked session"
+            );
+            Err(AppError::Unauthorized("refresh token reuse detected".into()))
+        }
+        RotateOutcome::Revoked => Err(AppError::Unauthorized("refresh token revoked".into())),
+        RotateOutcome::Expired => Err(AppError::Unauthorized("refresh token expired".into())),
+        RotateOutcome::NotFound => Err(AppError::Unauthorized("invalid refresh token".into())),
+    }
+}
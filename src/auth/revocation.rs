@@ -0,0 +1,222 @@
+//! Revoked sessions and access tokens.
+//!
+//! Revocations are written to Postgres and mirrored in memory, so token
+//! validation never touches the database. A whole session is revoked through
+//! the `sessions` table; a single access token is revoked by its `jti` in
+//! `revoked_tokens`. Each instance picks up revocations made elsewhere by
+//! polling both tables; a revocation made on this instance takes effect here
+//! immediately and on other instances within one sync interval. Sockets can
+//! watch for the revocation of their session or token.
+//!
+//! A revocation only needs to be remembered until the last access token it
+//! covers has expired; after that the token is rejected anyway.
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex, MutexGuard},
+    time::Duration,
+};
+
+use chrono::{DateTime, TimeZone, Utc};
+use sqlx::PgPool;
+use tokio::sync::watch;
+use uuid::Uuid;
+
+use crate::{
+    auth::login_throttle::FAILURE_MEMORY,
+    db::{
+        login_failures::delete_stale_login_failures,
+        oidc::delete_expired_oidc_logins,
+        revoked_tokens::{
+            delete_expired_revoked_tokens, insert_revoked_token, list_revoked_tokens_since,
+        },
+        sessions::{delete_stale_sessions, list_sessions_revoked_since, revoke_idle_sessions},
+        two_factor::delete_expired_login_challenges,
+        ws_tickets::delete_expired_ws_tickets,
+    },
+    error::AppResult,
+    models::auth::Claims,
+};
+
+/// Revocations are re-read with this much overlap, so a row whose transaction
+/// committed after a later-stamped one is not missed.
+const SYNC_OVERLAP: chrono::Duration = chrono::Duration::seconds(30);
+
+/// How often idle sessions are revoked and unusable sessions, expired token
+/// revocations, login challenges, OIDC sign-ins, WebSocket tickets and old
+/// login failures are deleted.
+const STALE_SESSION_SWEEP_INTERVAL: Duration = Duration::from_secs(3600);
+
+#[derive(Default)]
+struct Inner {
+    /// Revoked session ids and when the last access token issued for each one
+    /// expires.
+    revoked: HashMap<Uuid, DateTime<Utc>>,
+    /// Revoked `jti`s and when each token expires.
+    revoked_tokens: HashMap<Uuid, DateTime<Utc>>,
+    /// Open sockets waiting on a revocation, by session id or `jti`.
+    watchers: HashMap<Uuid, watch::Sender<bool>>,
+}
+
+#[derive(Clone)]
+pub struct RevocationStore {
+    inner: Arc<Mutex<Inner>>,
+    access_token_ttl: chrono::Duration,
+}
+
+impl RevocationStore {
+    fn lock(&self) -> MutexGuard<'_, Inner> {
+        self.inner.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    pub fn is_revoked(&self, session_id: Uuid) -> bool {
+        self.lock().revoked.contains_key(&session_id)
+    }
+
+    pub fn is_token_revoked(&self, jti: Uuid) -> bool {
+        self.lock().revoked_tokens.contains_key(&jti)
+    }
+
+    /// Revoke the access token described by `claims`, everywhere.
+    pub async fn revoke_token(&self, db: &PgPool, claims: &Claims) -> AppResult<()> {
+        let expires_at = Utc.timestamp_opt(claims.exp, 0).single().unwrap_or_else(Utc::now);
+        insert_revoked_token(db, claims.jti, claims.sub, expires_at).await?;
+        self.mark_token(claims.jti, expires_at);
+        Ok(())
+    }
+
+    /// Record a revocation already written to the database, so it takes
+    /// effect on this instance without waiting for the next sync.
+    pub fn mark_revoked(&self, session_id: Uuid) {
+        self.mark(session_id, Utc::now());
+    }
+
+    /// Resolves to `true` once the session or token with id `id` is revoked.
+    pub fn watch(&self, id: Uuid) -> watch::Receiver<bool> {
+        let mut inner = self.lock();
+        if inner.revoked.contains_key(&id) || inner.revoked_tokens.contains_key(&id) {
+            return watch::channel(true).1;
+        }
+        inner
+            .watchers
+            .entry(id)
+            .or_insert_with(|| watch::channel(false).0)
+            .subscribe()
+    }
+
+    fn mark(&self, session_id: Uuid, revoked_at: DateTime<Utc>) {
+        let mut inner = self.lock();
+        inner
+            .revoked
+            .insert(session_id, revoked_at + self.access_token_ttl);
+        if let Some(tx) = inner.watchers.remove(&session_id) {
+            tx.send_replace(true);
+        }
+    }
+
+    fn mark_token(&self, jti: Uuid, expires_at: DateTime<Utc>) {
+        let mut inner = self.lock();
+        inner.revoked_tokens.insert(jti, expires_at);
+        if let Some(tx) = inner.watchers.remove(&jti) {
+            tx.send_replace(true);
+        }
+    }
+
+    /// Forget revocations that no live token can hit, and watchers whose
+    /// sockets have closed.
+    fn prune(&self) {
+        let now = Utc::now();
+        let mut inner = self.lock();
+        inner.revoked.retain(|_, until| *until > now);
+        inner.revoked_tokens.retain(|_, until| *until > now);
+        inner.watchers.retain(|_, tx| tx.receiver_count() > 0);
+    }
+
+    /// Load revocations recorded after `since`. Returns the newest revocation
+    /// time seen, to pass as `since` next time.
+    async fn sync(&self, db: &PgPool, since: DateTime<Utc>) -> AppResult<DateTime<Utc>> {
+        let not_before = Utc::now() - self.access_token_ttl;
+        let rows = list_sessions_revoked_since(db, since, not_before).await?;
+        let mut newest = since;
+        for (session_id, revoked_at) in rows {
+            self.mark(session_id, revoked_at);
+            newest = newest.max(revoked_at);
+        }
+        for row in list_revoked_tokens_since(db, since).await? {
+            self.mark_token(row.jti, row.expires_at);
+            newest = newest.max(row.revoked_at);
+        }
+        Ok(newest)
+    }
+
+    /// Build a store holding every revocation that is still in effect.
+    pub async fn load(db: &PgPool, access_token_ttl: chrono::Duration) -> AppResult<Self> {
+        let store = Self {
+            inner: Arc::default(),
+            access_token_ttl,
+        };
+        store.sync(db, DateTime::<Utc>::UNIX_EPOCH).await?;
+        Ok(store)
+    }
+}
+
+/// Spawn the background task that pulls revocations made by other instances,
+/// drops expired ones, revokes sessions idle for longer than `session_ttl` and
+/// deletes sessions revoked that long ago along with revocations of
+/// expired tokens, expired login challenges, OIDC sign-ins and WebSocket
+/// tickets.
+pub fn spawn_revocation_sync(
+    store: RevocationStore,
+    db: PgPool,
+    interval: Duration,
+    session_ttl: chrono::Duration,
+) {
+    tokio::spawn(async move {
+        let mut since = Utc::now();
+        let mut last_cleanup: Option<std::time::Instant> = None;
+        let mut ticker = tokio::time::interval(interval);
+        ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            ticker.tick().await;
+            match store.sync(&db, since - SYNC_OVERLAP).await {
+                Ok(newest) => since = since.max(newest),
+                Err(err) => tracing::warn!("failed to sync revocations: {err}"),
+            }
+            store.prune();
+
+            if last_cleanup.is_some_and(|at| at.elapsed() < STALE_SESSION_SWEEP_INTERVAL) {
+                continue;
+            }
+            last_cleanup = Some(std::time::Instant::now());
+            let cutoff = Utc::now() - session_ttl;
+            match revoke_idle_sessions(&db, cutoff).await {
+                Ok(ids) => {
+                    for session_id in ids {
+                        store.mark_revoked(session_id);
+                    }
+                }
+                Err(err) => tracing::warn!("failed to revoke idle sessions: {err}"),
+            }
+            match delete_stale_sessions(&db, cutoff).await {
+                Ok(0) => {}
+                Ok(n) => tracing::debug!("deleted {n} stale sessions"),
+                Err(err) => tracing::warn!("failed to delete stale sessions: {err}"),
+            }
+            if let Err(err) = delete_expired_revoked_tokens(&db).await {
+                tracing::warn!("failed to delete expired revoked tokens: {err}");
+            }
+            if let Err(err) = delete_expired_login_challenges(&db).await {
+                tracing::warn!("failed to delete expired login challenges: {err}");
+            }
+            if let Err(err) = delete_expired_oidc_logins(&db).await {
+                tracing::warn!("failed to delete expired OIDC sign-ins: {err}");
+            }
+            if let Err(err) = delete_expired_ws_tickets(&db).await {
+                tracing::warn!("failed to delete expired WebSocket tickets: {err}");
+            }
+            if let Err(err) = delete_stale_login_failures(&db, Utc::now() - FAILURE_MEMORY).await {
+                tracing::warn!("failed to delete stale login failures: {err}");
+            }
+        }
+    });
+}
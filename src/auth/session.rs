@@ -0,0 +1,108 @@
+//! Login sessions.
+//!
+//! Every login starts a session: a row describing the device plus the refresh
+//! tokens rotated from it. Access tokens carry the session id (`sid`), so
+//! revoking a session cuts off its refresh tokens, its access tokens and the
+//! WebSockets opened with them.
+
+use axum::{
+    extract::{ConnectInfo, FromRequestParts},
+    http::{header, request::Parts},
+};
+use std::{convert::Infallible, net::SocketAddr};
+use uuid::Uuid;
+
+use crate::{
+    auth::refresh::issue_refresh_token,
+    db::sessions::{create_session, revoke_session, revoke_user_sessions},
+    error::AppResult,
+    state::AppState,
+};
+
+/// Longest user agent stored with a session.
+const MAX_USER_AGENT_LEN: usize = 512;
+
+/// Where a request came from, as recorded on its session.
+#[derive(Debug, Clone, Default)]
+pub struct ClientInfo {
+    pub user_agent: Option<String>,
+    pub ip: Option<String>,
+}
+
+impl<S> FromRequestParts<S> for ClientInfo
+where
+    S: Send + Sync + AsRef<AppState>,
+{
+    type Rejection = Infallible;
+
+    fn from_request_parts(
+        parts: &mut Parts,
+        state: &S,
+    ) -> impl std::future::Future<Output = Result<Self, Self::Rejection>> + Send {
+        let user_agent = parts
+            .headers
+            .get(header::USER_AGENT)
+            .and_then(|h| h.to_str().ok())
+            .map(|ua| ua.chars().take(MAX_USER_AGENT_LEN).collect());
+
+        // Only trust X-Forwarded-For behind a proxy that sets it; otherwise
+        // clients could claim any address.
+        let forwarded = state
+            .as_ref()
+            .trust_forwarded_for
+            .then(|| parts.headers.get("x-forwarded-for"))
+            .flatten()
+            .and_then(|h| h.to_str().ok())
+            .and_then(|v| v.split(',').next())
+            .map(|ip| ip.trim().to_string())
+            .filter(|ip| !ip.is_empty());
+        let ip = forwarded.or_else(|| {
+            parts
+                .extensions
+                .get::<ConnectInfo<SocketAddr>>()
+                .map(|ConnectInfo(addr)| addr.ip().to_string())
+        });
+
+        std::future::ready(Ok(ClientInfo { user_agent, ip }))
+    }
+}
+
+/// Start a session for `user_id`. Returns the session id and its first
+/// refresh token.
+pub async fn start_session(
+    state: &AppState,
+    user_id: Uuid,
+    client: &ClientInfo,
+) -> AppResult<(Uuid, String)> {
+    let session = create_session(
+        &state.db,
+        user_id,
+        client.user_agent.as_deref(),
+        client.ip.as_deref(),
+    )
+    .await?;
+    let refresh_token = issue_refresh_token(state, user_id, session.id).await?;
+    Ok((session.id, refresh_token))
+}
+
+/// Revoke one of `user_id`'s sessions everywhere. Returns `false` if there was
+/// no such active session.
+pub async fn end_session(state: &AppState, user_id: Uuid, session_id: Uuid) -> AppResult<bool> {
+    let revoked = revoke_session(&state.db, user_id, session_id).await?;
+    if revoked {
+        state.revocations.mark_revoked(session_id);
+    }
+    Ok(revoked)
+}
+
+/// Revoke all of `user_id`'s sessions except `keep`.
+pub async fn end_other_sessions(
+    state: &AppState,
+    user_id: Uuid,
+    keep: Option<Uuid>,
+) -> AppResult<()> {
+    for session_id in revoke_user_sessions(&state.db, user_id, keep).await? {
+        state.revocations.mark_revoked(session_id);
+    }
+    Ok(())
+}
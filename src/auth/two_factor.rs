@@ -0,0 +1,156 @@
+//! Optional TOTP second factor.
+//!
+//! Enrollment stores a pending secret; it takes effect once the user confirms
+//! a code from their authenticator, at which point they get single-use
+//! recovery codes (stored hashed). With 2FA on, a correct password only earns
+//! a short-lived login challenge, which is exchanged together with a code for
+//! real tokens.
+
+use chrono::Utc;
+use data_encoding::BASE32_NOPAD;
+use rand_core::{OsRng, RngCore};
+use uuid::Uuid;
+
+use crate::{
+    auth::{
+        opaque::{hash_token, new_token},
+        totp,
+    },
+    db::two_factor::{
+        attempt_login_challenge, complete_login_challenge, disable_totp, enable_totp,
+        get_user_totp, insert_login_challenge, record_totp_step, set_pending_totp,
+        use_recovery_code, UserTotp,
+    },
+    error::{AppError, AppResult},
+    state::AppState,
+};
+
+const CHALLENGE_TTL: chrono::Duration = chrono::Duration::minutes(5);
+/// Wrong codes allowed per challenge before the password must be re-entered.
+const MAX_CHALLENGE_ATTEMPTS: i32 = 5;
+const RECOVERY_CODE_COUNT: usize = 10;
+
+pub async fn is_enabled(state: &AppState, user_id: Uuid) -> AppResult<bool> {
+    Ok(get_user_totp(&state.db, user_id)
+        .await?
+        .is_some_and(|totp| totp.enabled_at.is_some()))
+}
+
+/// Start (or restart) enrollment. Returns the secret and its `otpauth://` URI.
+pub async fn begin_enrollment(
+    state: &AppState,
+    user_id: Uuid,
+    username: &str,
+) -> AppResult<(String, String)> {
+    let secret = totp::new_secret();
+    if !set_pending_totp(&state.db, user_id, &secret).await? {
+        return Err(AppError::BadRequest("two-factor authentication is already enabled".into()));
+    }
+    let uri = totp::otpauth_uri(&state.totp_issuer, username, &secret);
+    Ok((secret, uri))
+}
+
+/// Enable 2FA once the user proves their authenticator produces valid codes.
+/// Returns the recovery codes; they are not retrievable later.
+pub async fn confirm_enrollment(state: &AppState, user_id: Uuid, code: &str) -> AppResult<Vec<String>> {
+    let pending = get_user_totp(&state.db, user_id)
+        .await?
+        .filter(|totp| totp.enabled_at.is_none())
+        .ok_or_else(|| AppError::BadRequest("no two-factor enrollment in progress".into()))?;
+
+    if !check_totp(state, user_id, &pending, code).await? {
+        return Err(AppError::BadRequest("invalid authentication code".into()));
+    }
+
+    let codes: Vec<String> = (0..RECOVERY_CODE_COUNT).map(|_| new_recovery_code()).collect();
+    let hashes: Vec<String> = codes.iter().map(|c| hash_recovery_code(c)).collect();
+    if !enable_totp(&state.db, user_id, &hashes).await? {
+        return Err(AppError::BadRequest("two-factor authentication is already enabled".into()));
+    }
+    Ok(codes)
+}
+
+/// Turn 2FA off after checking a current code or recovery code. `false` if
+/// the code is wrong.
+pub async fn disable(state: &AppState, user_id: Uuid, code: &str) -> AppResult<bool> {
+    if !verify_second_factor(state, user_id, code).await? {
+        return Ok(false);
+    }
+    disable_totp(&state.db, user_id).await?;
+    Ok(true)
+}
+
+/// Issue a login challenge for a user who passed the password check.
+pub async fn issue_challenge(state: &AppState, user_id: Uuid) -> AppResult<(String, i64)> {
+    let token = new_token();
+    insert_login_challenge(&state.db, user_id, &hash_token(&token), Utc::now() + CHALLENGE_TTL)
+        .await?;
+    Ok((token, CHALLENGE_TTL.num_seconds()))
+}
+
+/// The user a login challenge was issued to. Counts an attempt at it.
+pub async fn attempt_challenge(state: &AppState, challenge: &str) -> AppResult<Uuid> {
+    attempt_login_challenge(&state.db, &hash_token(challenge), MAX_CHALLENGE_ATTEMPTS)
+        .await?
+        .ok_or_else(|| AppError::Unauthorized("invalid or expired login challenge".into()))
+}
+
+/// Finish an attempted challenge with a TOTP or recovery code. `false` if the
+/// code is wrong; the challenge can then be tried again.
+pub async fn redeem_challenge(
+    state: &AppState,
+    challenge: &str,
+    user_id: Uuid,
+    code: &str,
+) -> AppResult<bool> {
+    if !verify_second_factor(state, user_id, code).await? {
+        return Ok(false);
+    }
+    if !complete_login_challenge(&state.db, &hash_token(challenge)).await? {
+        return Err(AppError::Unauthorized("invalid or expired login challenge".into()));
+    }
+    Ok(true)
+}
+
+/// A six-digit `code` is checked as TOTP, anything else as a recovery code.
+pub async fn verify_second_factor(state: &AppState, user_id: Uuid, code: &str) -> AppResult<bool> {
+    let Some(totp) = get_user_totp(&state.db, user_id)
+        .await?
+        .filter(|totp| totp.enabled_at.is_some())
+    else {
+        return Ok(false);
+    };
+
+    let code = code.trim();
+    if code.len() == 6 && code.bytes().all(|b| b.is_ascii_digit()) {
+        check_totp(state, user_id, &totp, code).await
+    } else {
+        use_recovery_code(&state.db, user_id, &hash_recovery_code(code)).await
+    }
+}
+
+async fn check_totp(state: &AppState, user_id: Uuid, totp: &UserTotp, code: &str) -> AppResult<bool> {
+    match totp::verify(&totp.secret, code, Utc::now().timestamp(), totp.last_used_step) {
+        // Recording the step also refuses it if a concurrent login used it.
+        Some(step) => record_totp_step(&state.db, user_id, step).await,
+        None => Ok(false),
+    }
+}
+
+/// Ten base32 characters (50 bits), shown as `XXXXX-XXXXX`.
+fn new_recovery_code() -> String {
+    let mut bytes = [0u8; 7];
+    OsRng.fill_bytes(&mut bytes);
+    let chars = BASE32_NOPAD.encode(&bytes);
+    format!("{}-{}", &chars[..5], &chars[5..10])
+}
+
+/// Case, spaces and dashes do not matter when typing a recovery code.
+fn hash_recovery_code(code: &str) -> String {
+    let normalized: String = code
+        .chars()
+        .filter(|c| !c.is_whitespace() && *c != '-')
+        .map(|c| c.to_ascii_uppercase())
+        .collect();
+    hash_token(&normalized)
+}
@@ -0,0 +1,56 @@
+//! WebSocket connection tickets.
+//!
+//! Browsers cannot set an `Authorization` header on a WebSocket, and an access
+//! token in the URL ends up in proxy and access logs. Instead the client trades
+//! its access token for a ticket that opens one socket within a few seconds,
+//! and is useless afterwards.
+
+use chrono::Utc;
+use uuid::Uuid;
+
+use crate::{
+    auth::{
+        extractor::AuthUser,
+        opaque::{hash_token, new_token},
+    },
+    db::ws_tickets::{insert_ws_ticket, take_ws_ticket, WsTicket},
+    error::{AppError, AppResult},
+    state::AppState,
+};
+
+pub const WS_TICKET_TTL: chrono::Duration = chrono::Duration::seconds(30);
+
+/// Issue a ticket for the caller's session, optionally limited to `room_id`.
+pub async fn issue_ticket(
+    state: &AppState,
+    auth: &AuthUser,
+    room_id: Option<Uuid>,
+) -> AppResult<String> {
+    let ticket = new_token();
+    insert_ws_ticket(
+        &state.db,
+        &hash_token(&ticket),
+        auth.user_id,
+        auth.session_id,
+        room_id,
+        Utc::now() + WS_TICKET_TTL,
+    )
+    .await?;
+    Ok(ticket)
+}
+
+/// Spend a ticket to open a socket for `room_id`.
+pub async fn redeem_ticket(state: &AppState, ticket: &str, room_id: Uuid) -> AppResult<WsTicket> {
+    let ticket = take_ws_ticket(&state.db, &hash_token(ticket))
+        .await?
+        .ok_or_else(|| AppError::Unauthorized("invalid or expired ticket".into()))?;
+    if ticket.room_id.is_some_and(|id| id != room_id) {
+        return Err(AppError::Unauthorized(
+            "ticket was issued for another room".into(),
+        ));
+    }
+    if state.revocations.is_revoked(ticket.session_id) {
+        return Err(AppError::Unauthorized("session revoked".into()));
+    }
+    Ok(ticket)
+}
@@ -3,15 +3,210 @@
 
 use std::net::SocketAddr;
 
+use chrono::{DateTime, Utc};
+
+/// Transport used to relay room events between instances.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PubSubBackendKind {
+    /// Single instance; events stay in process.
+    Local,
+    /// Postgres `LISTEN/NOTIFY` on the application database.
+    Postgres,
+    /// Redis pub/sub with one channel per room and presence kept in Redis.
+    #[cfg(feature = "redis")]
+    Redis { url: String, presence_ttl_secs: u64 },
+}
+
+/// What to do with a WebSocket client that falls behind its room's broadcast
+/// buffer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LagPolicy {
+    /// Close the socket with a dedicated close code; the client reconnects and
+    /// gets fresh history.
+    Disconnect,
+    /// Replay the missed messages from the database.
+    Resync,
+    /// Send a `gap` event with the range of missed message ids.
+    Gap,
+}
+
+/// What happens to a deleted account's messages.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeletedUserMessages {
+    /// Keep them, attributed to a shared "deleted user" account.
+    Anonymize,
+    /// Delete them with the account.
+    Delete,
+}
+
+/// Characters allowed in usernames (besides `_`, `-` and `.`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UsernameCharset {
+    /// ASCII letters and digits.
+    Ascii,
+    /// Letters and digits of any one script, so names can't mix lookalike
+    /// letters from different scripts.
+    Unicode,
+}
+
+/// How access tokens are signed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JwtAlgorithm {
+    /// HMAC with `JWT_SECRET`; only this service can verify tokens.
+    Hs256,
+    /// RSA key pair; other services verify with the published public keys.
+    Rs256,
+    /// Ed25519 key pair; other services verify with the published public keys.
+    EdDsa,
+}
+
+/// Where outgoing mail goes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MailerKind {
+    /// Log each message (local development).
+    Log,
+    /// Write each message to a `.eml` file in `dir`.
+    File { dir: String },
+}
+
+/// Where attachment files are kept.
+#[derive(Debug, Clone)]
+pub enum StorageKind {
+    /// Files under `dir` on the local filesystem (one instance, or a shared
+    /// volume).
+    Local { dir: String },
+    /// A bucket on S3 or an S3-compatible service such as MinIO.
+    S3(S3Config),
+}
+
+#[derive(Clone)]
+pub struct S3Config {
+    /// Base URL of the service, e.g. `https://s3.eu-west-1.amazonaws.com` or
+    /// `http://127.0.0.1:9000`. Objects are addressed path-style.
+    pub endpoint: String,
+    pub bucket: String,
+    pub region: String,
+    pub access_key_id: String,
+    pub secret_access_key: String,
+}
+
+impl std::fmt::Debug for S3Config {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("S3Config")
+            .field("endpoint", &self.endpoint)
+            .field("bucket", &self.bucket)
+            .field("region", &self.region)
+            .field("access_key_id", &self.access_key_id)
+            .finish_non_exhaustive()
+    }
+}
+
+/// An OpenID Connect provider users can sign in with.
+#[derive(Debug, Clone)]
+pub struct OidcProviderConfig {
+    /// Short name used in URLs and stored with linked identities.
+    pub name: String,
+    /// Label for the sign-in button.
+    pub display_name: String,
+    /// Issuer URL; the provider's metadata is discovered from it.
+    pub issuer: String,
+    pub client_id: String,
+    /// Absent for public clients, which rely on PKCE alone.
+    pub client_secret: Option<String>,
+    pub scopes: String,
+    /// Our callback URL, as registered with the provider.
+    pub redirect_uri: String,
+}
+
 /// Application configuration loaded from environment.
 #[derive(Clone)]
 pub struct Config {
     pub database_url: String,
     pub database_max_connections: u32,
-    pub jwt_secret: String,
+    /// Required for `HS256`, and with a key pair while
+    /// `jwt_legacy_hs256_until` is set.
+    pub jwt_secret: Option<String>,
+    /// With a key pair, tokens signed with `jwt_secret` (which carry no `kid`)
+    /// are still accepted until then, to ease the switch from `HS256`.
+    pub jwt_legacy_hs256_until: Option<DateTime<Utc>>,
     pub jwt_issuer: String,
-    pub jwt_exp_hours: i64,
+    pub jwt_algorithm: JwtAlgorithm,
+    /// PEM private key access tokens are signed with (`RS256`/`EdDSA`).
+    pub jwt_signing_key_file: Option<String>,
+    /// PEM public keys tokens are accepted from and which are published as
+    /// JWKS; must include the signing key's.
+    pub jwt_verification_key_files: Vec<String>,
+    /// Lifetime of access tokens (JWTs).
+    pub access_token_ttl_minutes: i64,
+    /// Lifetime of refresh tokens; each refresh issues a fresh one.
+    pub refresh_token_ttl_days: i64,
+    /// How often revocations made by other instances are pulled from the
+    /// database.
+    pub revocation_sync_interval_secs: u64,
+    /// Lifetime of password reset tokens.
+    pub password_reset_ttl_minutes: i64,
+    /// Page the reset link points to; the token is appended as `?token=`.
+    pub password_reset_url: String,
+    pub mailer: MailerKind,
+    /// Issuer name shown in authenticator apps.
+    pub totp_issuer: String,
+    pub mail_from: String,
+    pub oidc_providers: Vec<OidcProviderConfig>,
+    /// Web client page the browser returns to after signing in with a
+    /// provider.
+    pub oidc_login_redirect_url: String,
+    pub username_charset: UsernameCharset,
+    /// Username length in characters, after normalization.
+    pub username_min_length: usize,
+    pub username_max_length: usize,
+    pub password_min_length: usize,
+    /// Newline-separated list of known breached passwords to refuse.
+    pub breached_passwords_file: Option<String>,
+    pub deleted_user_messages: DeletedUserMessages,
+    pub storage: StorageKind,
+    /// Largest attachment accepted, in bytes.
+    pub attachment_max_bytes: usize,
+    /// Content types attachments may have; `type/*` allows a whole type.
+    pub attachment_types: Vec<String>,
+    /// Threads decoding uploaded images.
+    pub image_workers: usize,
+    /// Images allowed to wait for a worker before uploads are turned away.
+    pub image_queue_capacity: usize,
+    /// Longest edge of each thumbnail generated for uploaded images, in pixels.
+    pub thumbnail_sizes: Vec<u32>,
+    /// Fetch previews of links posted in messages.
+    pub link_previews: bool,
+    /// How long a link preview fetch may take, including redirects.
+    pub link_preview_timeout_secs: u64,
+    /// Most of a linked page read when looking for its metadata, in bytes.
+    pub link_preview_max_bytes: usize,
+    /// Let link previews fetch private and loopback addresses (local
+    /// development only).
+    pub link_preview_allow_private: bool,
+    /// Failed logins allowed per username before it is locked out.
+    pub login_max_failures: i32,
+    /// Failed logins allowed per client address before it is locked out.
+    pub login_max_failures_per_ip: i32,
+    /// First lockout; each further failure doubles it.
+    pub login_lockout_base_secs: i64,
+    /// Longest lockout.
+    pub login_lockout_max_secs: i64,
     pub server_addr: SocketAddr,
+    /// Take the client address from `X-Forwarded-For` (only behind a proxy
+    /// that sets it).
+    pub trust_forwarded_for: bool,
+    /// How often the background task looks for idle room channels.
+    pub room_sweep_interval_secs: u64,
+    /// How long a room channel may sit without receivers before it is dropped.
+    pub room_idle_timeout_secs: u64,
+    pub pubsub_backend: PubSubBackendKind,
+    /// Capacity of each room's broadcast buffer.
+    pub room_channel_capacity: usize,
+    pub ws_lag_policy: LagPolicy,
+    /// How often the server pings each WebSocket client.
+    pub ws_ping_interval_secs: u64,
+    /// How long to wait for any frame after a ping before closing the socket.
+    pub ws_pong_timeout_secs: u64,
 }
 
 impl Config {
@@ -23,13 +218,227 @@ impl Config {
             .ok()
             .and_then(|v| v.parse().ok())
             .unwrap_or(10);
-        let jwt_secret = std::env::var("JWT_SECRET")
-            .expect("JWT_SECRET must be set for signing authentication tokens");
+        let jwt_secret = std::env::var("JWT_SECRET").ok().filter(|s| !s.is_empty());
         let jwt_issuer = std::env::var("JWT_ISSUER").unwrap_or_else(|_| "axum-chat-service".to_string());
-        let jwt_exp_hours = std::env::var("JWT_EXP_HOURS")
+        let jwt_algorithm = match std::env::var("JWT_ALGORITHM")
+            .unwrap_or_else(|_| "HS256".to_string())
+            .to_ascii_uppercase()
+            .as_str()
+        {
+            "HS256" => JwtAlgorithm::Hs256,
+            "RS256" => JwtAlgorithm::Rs256,
+            "EDDSA" => JwtAlgorithm::EdDsa,
+            other => panic!("unknown JWT_ALGORITHM {other:?} (expected HS256, RS256 or EdDSA)"),
+        };
+        let jwt_signing_key_file = std::env::var("JWT_SIGNING_KEY_FILE").ok().filter(|s| !s.is_empty());
+        let jwt_verification_key_files: Vec<String> = std::env::var("JWT_VERIFICATION_KEY_FILES")
+            .unwrap_or_default()
+            .split(',')
+            .map(|path| path.trim().to_string())
+            .filter(|path| !path.is_empty())
+            .collect();
+        let jwt_legacy_hs256_until = std::env::var("JWT_LEGACY_HS256_UNTIL")
+            .ok()
+            .filter(|s| !s.is_empty())
+            .map(|v| {
+                DateTime::parse_from_rfc3339(&v)
+                    .unwrap_or_else(|_| panic!("JWT_LEGACY_HS256_UNTIL must be an RFC 3339 timestamp, got {v:?}"))
+                    .with_timezone(&Utc)
+            });
+        match jwt_algorithm {
+            JwtAlgorithm::Hs256 => {
+                if jwt_secret.is_none() {
+                    panic!("JWT_SECRET must be set for signing authentication tokens");
+                }
+            }
+            JwtAlgorithm::Rs256 | JwtAlgorithm::EdDsa => {
+                if jwt_signing_key_file.is_none() || jwt_verification_key_files.is_empty() {
+                    panic!("JWT_SIGNING_KEY_FILE and JWT_VERIFICATION_KEY_FILES must be set for RS256 and EdDSA");
+                }
+                if jwt_legacy_hs256_until.is_some() && jwt_secret.is_none() {
+                    panic!("JWT_SECRET must be set to accept HS256 tokens until JWT_LEGACY_HS256_UNTIL");
+                }
+            }
+        }
+        let access_token_ttl_minutes = std::env::var("ACCESS_TOKEN_TTL_MINUTES")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(15);
+        let refresh_token_ttl_days = std::env::var("REFRESH_TOKEN_TTL_DAYS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(30);
+        let revocation_sync_interval_secs = std::env::var("REVOCATION_SYNC_INTERVAL_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .filter(|&n: &u64| n > 0)
+            .unwrap_or(5);
+        let password_reset_ttl_minutes = std::env::var("PASSWORD_RESET_TTL_MINUTES")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .filter(|&n: &i64| n > 0)
+            .unwrap_or(30);
+        let password_reset_url = std::env::var("PASSWORD_RESET_URL")
+            .unwrap_or_else(|_| "http://localhost:5173/reset-password".to_string());
+        let mailer = match std::env::var("MAILER")
+            .unwrap_or_else(|_| "log".to_string())
+            .to_ascii_lowercase()
+            .as_str()
+        {
+            "log" => MailerKind::Log,
+            "file" => MailerKind::File {
+                dir: std::env::var("MAILER_FILE_DIR").unwrap_or_else(|_| "./mail".to_string()),
+            },
+            other => panic!("unknown MAILER {other:?} (expected log or file)"),
+        };
+        let totp_issuer = std::env::var("TOTP_ISSUER").unwrap_or_else(|_| "Axum Chat".to_string());
+        let mail_from =
+            std::env::var("MAIL_FROM").unwrap_or_else(|_| "no-reply@localhost".to_string());
+        let public_url = std::env::var("PUBLIC_URL")
+            .unwrap_or_else(|_| "http://localhost:8080".to_string());
+        let oidc_providers = std::env::var("OIDC_PROVIDERS")
+            .unwrap_or_default()
+            .split(',')
+            .map(|name| name.trim().to_ascii_lowercase())
+            .filter(|name| !name.is_empty())
+            .map(|name| oidc_provider_from_env(name, public_url.trim_end_matches('/')))
+            .collect();
+        let oidc_login_redirect_url = std::env::var("OIDC_LOGIN_REDIRECT_URL")
+            .unwrap_or_else(|_| "http://localhost:5173/".to_string());
+        let username_charset = match std::env::var("USERNAME_CHARSET")
+            .unwrap_or_else(|_| "ascii".to_string())
+            .to_ascii_lowercase()
+            .as_str()
+        {
+            "ascii" => UsernameCharset::Ascii,
+            "unicode" => UsernameCharset::Unicode,
+            other => panic!("unknown USERNAME_CHARSET {other:?} (expected ascii or unicode)"),
+        };
+        let username_min_length = std::env::var("USERNAME_MIN_LENGTH")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .filter(|&n: &usize| n > 0)
+            .unwrap_or(3);
+        let username_max_length = std::env::var("USERNAME_MAX_LENGTH")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(32)
+            .max(username_min_length);
+        let password_min_length = std::env::var("PASSWORD_MIN_LENGTH")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .filter(|&n: &usize| n > 0)
+            .unwrap_or(8);
+        let breached_passwords_file =
+            std::env::var("BREACHED_PASSWORDS_FILE").ok().filter(|s| !s.is_empty());
+        let deleted_user_messages = match std::env::var("DELETED_USER_MESSAGES")
+            .unwrap_or_else(|_| "anonymize".to_string())
+            .to_ascii_lowercase()
+            .as_str()
+        {
+            "anonymize" => DeletedUserMessages::Anonymize,
+            "delete" => DeletedUserMessages::Delete,
+            other => panic!("unknown DELETED_USER_MESSAGES {other:?} (expected anonymize or delete)"),
+        };
+        let storage = match std::env::var("STORAGE_BACKEND")
+            .unwrap_or_else(|_| "local".to_string())
+            .to_ascii_lowercase()
+            .as_str()
+        {
+            "local" => StorageKind::Local {
+                dir: std::env::var("STORAGE_DIR").unwrap_or_else(|_| "./data/attachments".to_string()),
+            },
+            "s3" => {
+                let var = |key: &str| {
+                    std::env::var(key)
+                        .ok()
+                        .filter(|v| !v.trim().is_empty())
+                        .unwrap_or_else(|| panic!("{key} must be set for STORAGE_BACKEND=s3"))
+                };
+                StorageKind::S3(S3Config {
+                    endpoint: var("S3_ENDPOINT").trim_end_matches('/').to_string(),
+                    bucket: var("S3_BUCKET"),
+                    region: std::env::var("S3_REGION").unwrap_or_else(|_| "us-east-1".to_string()),
+                    access_key_id: var("S3_ACCESS_KEY_ID"),
+                    secret_access_key: var("S3_SECRET_ACCESS_KEY"),
+                })
+            }
+            other => panic!("unknown STORAGE_BACKEND {other:?} (expected local or s3)"),
+        };
+        let attachment_max_bytes = std::env::var("ATTACHMENT_MAX_BYTES")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .filter(|&n: &usize| n > 0)
+            .unwrap_or(10 * 1024 * 1024);
+        let attachment_types: Vec<String> = std::env::var("ATTACHMENT_TYPES")
+            .unwrap_or_else(|_| {
+                "image/png,image/jpeg,image/gif,image/webp,application/pdf,text/plain".to_string()
+            })
+            .split(',')
+            .map(|t| t.trim().to_ascii_lowercase())
+            .filter(|t| !t.is_empty())
+            .collect();
+        let image_workers = std::env::var("IMAGE_WORKERS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .filter(|&n: &usize| n > 0)
+            .unwrap_or(2);
+        let image_queue_capacity = std::env::var("IMAGE_QUEUE_CAPACITY")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .filter(|&n: &usize| n > 0)
+            .unwrap_or(32);
+        let mut thumbnail_sizes: Vec<u32> = std::env::var("THUMBNAIL_SIZES")
+            .unwrap_or_else(|_| "160,640".to_string())
+            .split(',')
+            .filter(|s| !s.trim().is_empty())
+            .map(|s| {
+                s.trim()
+                    .parse()
+                    .ok()
+                    .filter(|&n: &u32| (16..=4096).contains(&n))
+                    .unwrap_or_else(|| panic!("invalid THUMBNAIL_SIZES entry {s:?} (expected 16 to 4096)"))
+            })
+            .collect();
+        thumbnail_sizes.sort_unstable();
+        thumbnail_sizes.dedup();
+        let link_previews = std::env::var("LINK_PREVIEWS")
+            .map(|v| matches!(v.to_ascii_lowercase().as_str(), "1" | "true" | "yes"))
+            .unwrap_or(true);
+        let link_preview_timeout_secs = std::env::var("LINK_PREVIEW_TIMEOUT_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .filter(|&n: &u64| n > 0)
+            .unwrap_or(5);
+        let link_preview_max_bytes = std::env::var("LINK_PREVIEW_MAX_BYTES")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .filter(|&n: &usize| n > 0)
+            .unwrap_or(512 * 1024);
+        let link_preview_allow_private = std::env::var("LINK_PREVIEW_ALLOW_PRIVATE")
+            .map(|v| matches!(v.to_ascii_lowercase().as_str(), "1" | "true" | "yes"))
+            .unwrap_or(false);
+        let login_max_failures = std::env::var("LOGIN_MAX_FAILURES")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .filter(|&n: &i32| n > 0)
+            .unwrap_or(5);
+        let login_max_failures_per_ip = std::env::var("LOGIN_MAX_FAILURES_PER_IP")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .filter(|&n: &i32| n > 0)
+            .unwrap_or(50);
+        let login_lockout_base_secs = std::env::var("LOGIN_LOCKOUT_BASE_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .filter(|&n: &i64| n > 0)
+            .unwrap_or(30);
+        let login_lockout_max_secs = std::env::var("LOGIN_LOCKOUT_MAX_SECS")
             .ok()
             .and_then(|v| v.parse().ok())
-            .unwrap_or(24);
+            .filter(|&n: &i64| n > 0)
+            .unwrap_or(900)
+            .max(login_lockout_base_secs);
         let host = std::env::var("SERVER_HOST").unwrap_or_else(|_| "0.0.0.0".to_string());
         let port: u16 = std::env::var("PORT")
             .ok()
@@ -39,14 +448,139 @@ impl Config {
         let server_addr = format!("{host}:{port}")
             .parse()
             .expect("invalid SERVER_HOST/SERVER_PORT combination");
+        let trust_forwarded_for = std::env::var("TRUST_FORWARDED_FOR")
+            .map(|v| matches!(v.to_ascii_lowercase().as_str(), "1" | "true" | "yes"))
+            .unwrap_or(false);
+        let room_sweep_interval_secs = std::env::var("ROOM_SWEEP_INTERVAL_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .filter(|&n: &u64| n > 0)
+            .unwrap_or(60);
+        let room_idle_timeout_secs = std::env::var("ROOM_IDLE_TIMEOUT_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .filter(|&n: &u64| n > 0)
+            .unwrap_or(300);
+        let pubsub_backend = match std::env::var("PUBSUB_BACKEND")
+            .unwrap_or_else(|_| "local".to_string())
+            .to_ascii_lowercase()
+            .as_str()
+        {
+            "local" => PubSubBackendKind::Local,
+            "postgres" => PubSubBackendKind::Postgres,
+            #[cfg(feature = "redis")]
+            "redis" => PubSubBackendKind::Redis {
+                url: std::env::var("REDIS_URL")
+                    .expect("REDIS_URL must be set when PUBSUB_BACKEND=redis"),
+                presence_ttl_secs: std::env::var("REDIS_PRESENCE_TTL_SECS")
+                    .ok()
+                    .and_then(|v| v.parse().ok())
+                    .filter(|&n: &u64| n > 0)
+                    .unwrap_or(60),
+            },
+            #[cfg(not(feature = "redis"))]
+            "redis" => panic!("PUBSUB_BACKEND=redis requires building with `--features redis`"),
+            other => panic!("unknown PUBSUB_BACKEND {other:?} (expected local, postgres or redis)"),
+        };
+        let room_channel_capacity = std::env::var("ROOM_CHANNEL_CAPACITY")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .filter(|&n: &usize| n > 0)
+            .unwrap_or(100);
+        let ws_lag_policy = match std::env::var("WS_LAG_POLICY")
+            .unwrap_or_else(|_| "gap".to_string())
+            .to_ascii_lowercase()
+            .as_str()
+        {
+            "disconnect" => LagPolicy::Disconnect,
+            "resync" => LagPolicy::Resync,
+            "gap" => LagPolicy::Gap,
+            other => panic!("unknown WS_LAG_POLICY {other:?} (expected disconnect, resync or gap)"),
+        };
+        let ws_ping_interval_secs = std::env::var("WS_PING_INTERVAL_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .filter(|&n: &u64| n > 0)
+            .unwrap_or(30);
+        let ws_pong_timeout_secs = std::env::var("WS_PONG_TIMEOUT_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .filter(|&n: &u64| n > 0)
+            .unwrap_or(10);
 
         Self {
             database_url,
             database_max_connections,
             jwt_secret,
+            jwt_legacy_hs256_until,
             jwt_issuer,
-            jwt_exp_hours,
+            jwt_algorithm,
+            jwt_signing_key_file,
+            jwt_verification_key_files,
+            access_token_ttl_minutes,
+            refresh_token_ttl_days,
+            revocation_sync_interval_secs,
+            password_reset_ttl_minutes,
+            password_reset_url,
+            mailer,
+            totp_issuer,
+            mail_from,
+            oidc_providers,
+            oidc_login_redirect_url,
+            username_charset,
+            username_min_length,
+            username_max_length,
+            password_min_length,
+            breached_passwords_file,
+            deleted_user_messages,
+            storage,
+            attachment_max_bytes,
+            attachment_types,
+            image_workers,
+            image_queue_capacity,
+            thumbnail_sizes,
+            link_previews,
+            link_preview_timeout_secs,
+            link_preview_max_bytes,
+            link_preview_allow_private,
+            login_max_failures,
+            login_max_failures_per_ip,
+            login_lockout_base_secs,
+            login_lockout_max_secs,
             server_addr,
+            trust_forwarded_for,
+            room_sweep_interval_secs,
+            room_idle_timeout_secs,
+            pubsub_backend,
+            room_channel_capacity,
+            ws_lag_policy,
+            ws_ping_interval_secs,
+            ws_pong_timeout_secs,
         }
     }
 }
+
+/// Read `OIDC_<NAME>_*` for the provider called `name`.
+fn oidc_provider_from_env(name: String, public_url: &str) -> OidcProviderConfig {
+    if !name.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_') {
+        panic!("invalid OIDC provider name {name:?} (use letters, digits, - and _)");
+    }
+    let prefix = format!("OIDC_{}_", name.to_ascii_uppercase().replace('-', "_"));
+    let var = |key: &str| {
+        std::env::var(format!("{prefix}{key}"))
+            .ok()
+            .filter(|v| !v.trim().is_empty())
+    };
+
+    OidcProviderConfig {
+        display_name: var("DISPLAY_NAME").unwrap_or_else(|| name.clone()),
+        issuer: var("ISSUER")
+            .unwrap_or_else(|| panic!("{prefix}ISSUER must be set for OIDC provider {name:?}")),
+        client_id: var("CLIENT_ID")
+            .unwrap_or_else(|| panic!("{prefix}CLIENT_ID must be set for OIDC provider {name:?}")),
+        client_secret: var("CLIENT_SECRET"),
+        scopes: var("SCOPES").unwrap_or_else(|| "openid email profile".to_string()),
+        redirect_uri: format!("{public_url}/api/auth/oidc/{name}/callback"),
+        name,
+    }
+}
@@ -0,0 +1,259 @@
+use std::collections::HashMap;
+
+use chrono::{DateTime, Utc};
+use sqlx::{PgConnection, PgExecutor, PgPool};
+use uuid::Uuid;
+
+use crate::{
+    error::{AppError, AppResult},
+    models::{
+        attachment::{Attachment, AttachmentInfo, Thumbnail, ThumbnailInfo},
+        message::MessageWithUsername,
+    },
+};
+
+/// Store an upload and its thumbnails.
+pub async fn insert_attachment(
+    pool: &PgPool,
+    attachment: &Attachment,
+    thumbnails: &[Thumbnail],
+) -> AppResult<()> {
+    let mut tx = pool.begin().await?;
+
+    sqlx::query(
+        r#"
+        INSERT INTO attachments
+            (id, room_id, uploader_id, filename, content_type, size_bytes, storage_key, width, height)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+        "#,
+    )
+    .bind(attachment.id)
+    .bind(attachment.room_id)
+    .bind(attachment.uploader_id)
+    .bind(&attachment.filename)
+    .bind(&attachment.content_type)
+    .bind(attachment.size_bytes)
+    .bind(&attachment.storage_key)
+    .bind(attachment.width)
+    .bind(attachment.height)
+    .execute(&mut *tx)
+    .await?;
+
+    for thumbnail in thumbnails {
+        sqlx::query(
+            r#"
+            INSERT INTO attachment_thumbnails
+                (attachment_id, size, width, height, content_type, storage_key)
+            VALUES ($1, $2, $3, $4, $5, $6)
+            "#,
+        )
+        .bind(thumbnail.attachment_id)
+        .bind(thumbnail.size)
+        .bind(thumbnail.width)
+        .bind(thumbnail.height)
+        .bind(&thumbnail.content_type)
+        .bind(&thumbnail.storage_key)
+        .execute(&mut *tx)
+        .await?;
+    }
+
+    tx.commit().await?;
+    Ok(())
+}
+
+pub async fn get_attachment(pool: &PgPool, id: Uuid) -> AppResult<Option<Attachment>> {
+    let attachment = sqlx::query_as::<_, Attachment>(
+        r#"
+        SELECT id, room_id, message_id, uploader_id, filename, content_type, size_bytes,
+               storage_key, width, height
+        FROM attachments
+        WHERE id = $1
+        "#,
+    )
+    .bind(id)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(attachment)
+}
+
+pub async fn get_thumbnail(
+    pool: &PgPool,
+    attachment_id: Uuid,
+    size: i32,
+) -> AppResult<Option<Thumbnail>> {
+    let thumbnail = sqlx::query_as::<_, Thumbnail>(
+        r#"
+        SELECT attachment_id, size, width, height, content_type, storage_key
+        FROM attachment_thumbnails
+        WHERE attachment_id = $1 AND size = $2
+        "#,
+    )
+    .bind(attachment_id)
+    .bind(size)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(thumbnail)
+}
+
+/// The thumbnails of each of `attachment_ids` that has any, smallest first.
+async fn thumbnails_for<'e>(
+    executor: impl PgExecutor<'e>,
+    attachment_ids: &[Uuid],
+) -> AppResult<HashMap<Uuid, Vec<ThumbnailInfo>>> {
+    let thumbnails = sqlx::query_as::<_, Thumbnail>(
+        r#"
+        SELECT attachment_id, size, width, height, content_type, storage_key
+        FROM attachment_thumbnails
+        WHERE attachment_id = ANY($1)
+        ORDER BY size
+        "#,
+    )
+    .bind(attachment_ids)
+    .fetch_all(executor)
+    .await?;
+
+    let mut by_attachment: HashMap<Uuid, Vec<ThumbnailInfo>> = HashMap::new();
+    for thumbnail in &thumbnails {
+        by_attachment
+            .entry(thumbnail.attachment_id)
+            .or_default()
+            .push(thumbnail.into());
+    }
+    Ok(by_attachment)
+}
+
+/// Attach `attachment_ids` to a new message. They must all have been uploaded
+/// by `uploader_id` to `room_id` and not be attached to anything yet; otherwise
+/// nothing is linked and a validation error is returned. Run it in the
+/// transaction that inserts the message.
+pub async fn link_attachments(
+    conn: &mut PgConnection,
+    message_id: Uuid,
+    room_id: Uuid,
+    uploader_id: Uuid,
+    attachment_ids: &[Uuid],
+) -> AppResult<Vec<AttachmentInfo>> {
+    if attachment_ids.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let mut linked = sqlx::query_as::<_, AttachmentInfo>(
+        r#"
+        WITH linked AS (
+            UPDATE attachments
+            SET message_id = $1
+            WHERE id = ANY($4) AND room_id = $2 AND uploader_id = $3 AND message_id IS NULL
+            RETURNING id, filename, content_type, size_bytes, width, height, created_at
+        )
+        SELECT id, filename, content_type, size_bytes, width, height
+        FROM linked
+        ORDER BY created_at, id
+        "#,
+    )
+    .bind(message_id)
+    .bind(room_id)
+    .bind(uploader_id)
+    .bind(attachment_ids)
+    .fetch_all(&mut *conn)
+    .await?;
+
+    let mut wanted = attachment_ids.to_vec();
+    wanted.sort_unstable();
+    wanted.dedup();
+    if linked.len() != wanted.len() {
+        return Err(AppError::validation(
+            "attachment_ids",
+            "unknown attachment, or one that is already attached to a message",
+        ));
+    }
+
+    let mut thumbnails = thumbnails_for(&mut *conn, &wanted).await?;
+    for attachment in &mut linked {
+        attachment.thumbnails = thumbnails.remove(&attachment.id).unwrap_or_default();
+    }
+    Ok(linked)
+}
+
+/// Fill in the `attachments` of each message.
+pub async fn load_message_attachments(
+    pool: &PgPool,
+    messages: &mut [MessageWithUsername],
+) -> AppResult<()> {
+    if messages.is_empty() {
+        return Ok(());
+    }
+    let ids: Vec<Uuid> = messages.iter().map(|m| m.id).collect();
+
+    let rows = sqlx::query_as::<_, (Uuid, Uuid, String, String, i64, Option<i32>, Option<i32>)>(
+        r#"
+        SELECT message_id, id, filename, content_type, size_bytes, width, height
+        FROM attachments
+        WHERE message_id = ANY($1)
+        ORDER BY created_at, id
+        "#,
+    )
+    .bind(&ids)
+    .fetch_all(pool)
+    .await?;
+
+    let attachment_ids: Vec<Uuid> = rows.iter().map(|row| row.1).collect();
+    let mut thumbnails = thumbnails_for(pool, &attachment_ids).await?;
+
+    let mut by_message: HashMap<Uuid, Vec<AttachmentInfo>> = HashMap::new();
+    for (message_id, id, filename, content_type, size_bytes, width, height) in rows {
+        by_message.entry(message_id).or_default().push(AttachmentInfo {
+            id,
+            filename,
+            content_type,
+            size_bytes,
+            width,
+            height,
+            thumbnails: thumbnails.remove(&id).unwrap_or_default(),
+        });
+    }
+    for message in messages {
+        message.attachments = by_message.remove(&message.id).unwrap_or_default();
+    }
+
+    Ok(())
+}
+
+/// Delete up to `limit` attachments that are not attached to a message and
+/// were uploaded before `cutoff`. Returns the storage keys of their files and
+/// thumbnails, so those can be removed too.
+pub async fn delete_unlinked_attachments(
+    pool: &PgPool,
+    cutoff: DateTime<Utc>,
+    limit: i64,
+) -> AppResult<Vec<String>> {
+    // The outer `message_id IS NULL` is re-checked on rows a concurrent send
+    // just linked, so those are left alone.
+    // The thumbnail rows go by cascade, but the final SELECT still sees them.
+    let keys = sqlx::query_scalar::<_, String>(
+        r#"
+        WITH deleted AS (
+            DELETE FROM attachments
+            WHERE message_id IS NULL
+              AND id IN (
+                  SELECT id FROM attachments
+                  WHERE message_id IS NULL AND created_at < $1
+                  LIMIT $2
+              )
+            RETURNING id, storage_key
+        )
+        SELECT storage_key FROM deleted
+        UNION ALL
+        SELECT t.storage_key
+        FROM attachment_thumbnails t
+        JOIN deleted d ON d.id = t.attachment_id
+        "#,
+    )
+    .bind(cutoff)
+    .bind(limit)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(keys)
+}
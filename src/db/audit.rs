@@ -0,0 +1,28 @@
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::error::AppResult;
+
+pub async fn insert_audit_event(
+    pool: &PgPool,
+    event: &str,
+    user_id: Option<Uuid>,
+    ip: Option<&str>,
+    details: serde_json::Value,
+) -> AppResult<()> {
+    sqlx::query(
+        r#"
+        INSERT INTO audit_events (id, event, user_id, ip, details)
+        VALUES ($1, $2, $3, $4, $5)
+        "#,
+    )
+    .bind(Uuid::new_v4())
+    .bind(event)
+    .bind(user_id)
+    .bind(ip)
+    .bind(details)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
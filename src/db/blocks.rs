@@ -0,0 +1,81 @@
+use std::collections::HashSet;
+
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{error::AppResult, models::user::BlockedUser};
+
+/// Whether `blocker_id` has blocked `blocked_id`.
+pub async fn has_blocked(pool: &PgPool, blocker_id: Uuid, blocked_id: Uuid) -> AppResult<bool> {
+    let blocked = sqlx::query_scalar::<_, bool>(
+        r#"
+        SELECT EXISTS (
+            SELECT 1 FROM user_blocks WHERE blocker_id = $1 AND blocked_id = $2
+        )
+        "#,
+    )
+    .bind(blocker_id)
+    .bind(blocked_id)
+    .fetch_one(pool)
+    .await?;
+
+    Ok(blocked)
+}
+
+/// Returns `false` if it was already blocked.
+pub async fn block_user(pool: &PgPool, blocker_id: Uuid, blocked_id: Uuid) -> AppResult<bool> {
+    let result = sqlx::query(
+        r#"
+        INSERT INTO user_blocks (blocker_id, blocked_id)
+        VALUES ($1, $2)
+        ON CONFLICT (blocker_id, blocked_id) DO NOTHING
+        "#,
+    )
+    .bind(blocker_id)
+    .bind(blocked_id)
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+/// Returns `false` if it was not blocked.
+pub async fn unblock_user(pool: &PgPool, blocker_id: Uuid, blocked_id: Uuid) -> AppResult<bool> {
+    let result =
+        sqlx::query(r#"DELETE FROM user_blocks WHERE blocker_id = $1 AND blocked_id = $2"#)
+            .bind(blocker_id)
+            .bind(blocked_id)
+            .execute(pool)
+            .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+/// The users `blocker_id` has blocked, most recently blocked first.
+pub async fn list_blocked_users(pool: &PgPool, blocker_id: Uuid) -> AppResult<Vec<BlockedUser>> {
+    let users = sqlx::query_as::<_, BlockedUser>(
+        r#"
+        SELECT u.id, u.username, u.display_name, u.avatar_url, b.created_at AS blocked_at
+        FROM user_blocks b
+        JOIN users u ON u.id = b.blocked_id
+        WHERE b.blocker_id = $1
+        ORDER BY b.created_at DESC
+        "#,
+    )
+    .bind(blocker_id)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(users)
+}
+
+pub async fn list_blocked_user_ids(pool: &PgPool, blocker_id: Uuid) -> AppResult<HashSet<Uuid>> {
+    let ids = sqlx::query_scalar::<_, Uuid>(
+        r#"SELECT blocked_id FROM user_blocks WHERE blocker_id = $1"#,
+    )
+    .bind(blocker_id)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(ids.into_iter().collect())
+}
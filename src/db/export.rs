@@ -0,0 +1,65 @@
+//! Queries for the account export.
+
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{
+    error::AppResult,
+    models::export::{ExportedIdentity, ExportedMembership, ExportedMessage},
+};
+
+pub async fn list_user_identities(
+    pool: &PgPool,
+    user_id: Uuid,
+) -> AppResult<Vec<ExportedIdentity>> {
+    let identities = sqlx::query_as::<_, ExportedIdentity>(
+        r#"
+        SELECT provider, subject, email, created_at, last_login_at
+        FROM user_identities
+        WHERE user_id = $1
+        ORDER BY created_at
+        "#,
+    )
+    .bind(user_id)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(identities)
+}
+
+pub async fn list_user_memberships(
+    pool: &PgPool,
+    user_id: Uuid,
+) -> AppResult<Vec<ExportedMembership>> {
+    let memberships = sqlx::query_as::<_, ExportedMembership>(
+        r#"
+        SELECT r.id AS room_id, r.name AS room_name, rm.role, rm.created_at AS joined_at
+        FROM room_members rm
+        JOIN rooms r ON r.id = rm.room_id
+        WHERE rm.user_id = $1
+        ORDER BY rm.created_at
+        "#,
+    )
+    .bind(user_id)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(memberships)
+}
+
+/// Every message `user_id` has written, oldest first.
+pub async fn list_user_messages(pool: &PgPool, user_id: Uuid) -> AppResult<Vec<ExportedMessage>> {
+    let messages = sqlx::query_as::<_, ExportedMessage>(
+        r#"
+        SELECT id, room_id, content, created_at
+        FROM messages
+        WHERE user_id = $1
+        ORDER BY created_at
+        "#,
+    )
+    .bind(user_id)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(messages)
+}
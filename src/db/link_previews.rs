@@ -0,0 +1,135 @@
+use std::collections::HashMap;
+
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{
+    error::AppResult,
+    models::{link_preview::LinkPreview, message::MessageWithUsername},
+};
+
+/// The cached preview of `url`, if it was fetched after `fresh_after`, or
+/// after `failed_after` for a fetch that found nothing.
+pub async fn get_cached_preview(
+    pool: &PgPool,
+    url: &str,
+    fresh_after: DateTime<Utc>,
+    failed_after: DateTime<Utc>,
+) -> AppResult<Option<LinkPreview>> {
+    let preview = sqlx::query_as::<_, LinkPreview>(
+        r#"
+        SELECT url, title, description, image_url, site_name
+        FROM link_previews
+        WHERE url = $1
+          AND fetched_at > CASE
+              WHEN title IS NULL AND description IS NULL AND image_url IS NULL THEN $3
+              ELSE $2
+          END
+        "#,
+    )
+    .bind(url)
+    .bind(fresh_after)
+    .bind(failed_after)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(preview)
+}
+
+/// Cache a freshly fetched preview, replacing any older one.
+pub async fn upsert_preview(pool: &PgPool, preview: &LinkPreview) -> AppResult<()> {
+    sqlx::query(
+        r#"
+        INSERT INTO link_previews (url, title, description, image_url, site_name, fetched_at)
+        VALUES ($1, $2, $3, $4, $5, NOW())
+        ON CONFLICT (url) DO UPDATE
+        SET title = EXCLUDED.title,
+            description = EXCLUDED.description,
+            image_url = EXCLUDED.image_url,
+            site_name = EXCLUDED.site_name,
+            fetched_at = EXCLUDED.fetched_at
+        "#,
+    )
+    .bind(&preview.url)
+    .bind(&preview.title)
+    .bind(&preview.description)
+    .bind(&preview.image_url)
+    .bind(&preview.site_name)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+/// Show the cached previews of `urls` under a message, in that order.
+/// Returns `false` if the message no longer exists.
+pub async fn link_previews(pool: &PgPool, message_id: Uuid, urls: &[String]) -> AppResult<bool> {
+    let positions: Vec<i32> = (0..urls.len() as i32).collect();
+    let result = sqlx::query(
+        r#"
+        INSERT INTO message_link_previews (message_id, url, position)
+        SELECT m.id, u.url, u.position
+        FROM messages m, UNNEST($2::TEXT[], $3::INT[]) AS u(url, position)
+        WHERE m.id = $1
+        ON CONFLICT DO NOTHING
+        "#,
+    )
+    .bind(message_id)
+    .bind(urls)
+    .bind(&positions)
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+/// Fill in `previews` on each of `messages`.
+pub async fn load_message_previews(
+    pool: &PgPool,
+    messages: &mut [MessageWithUsername],
+) -> AppResult<()> {
+    if messages.is_empty() {
+        return Ok(());
+    }
+    let ids: Vec<Uuid> = messages.iter().map(|m| m.id).collect();
+
+    let rows = sqlx::query_as::<
+        _,
+        (
+            Uuid,
+            String,
+            Option<String>,
+            Option<String>,
+            Option<String>,
+            Option<String>,
+        ),
+    >(
+        r#"
+        SELECT mp.message_id, p.url, p.title, p.description, p.image_url, p.site_name
+        FROM message_link_previews mp
+        JOIN link_previews p ON p.url = mp.url
+        WHERE mp.message_id = ANY($1)
+        ORDER BY mp.position
+        "#,
+    )
+    .bind(&ids)
+    .fetch_all(pool)
+    .await?;
+
+    let mut by_message: HashMap<Uuid, Vec<LinkPreview>> = HashMap::new();
+    for (message_id, url, title, description, image_url, site_name) in rows {
+        by_message.entry(message_id).or_default().push(LinkPreview {
+            url,
+            title,
+            description,
+            image_url,
+            site_name,
+        });
+    }
+    for message in messages {
+        message.previews = by_message.remove(&message.id).unwrap_or_default();
+    }
+
+    Ok(())
+}
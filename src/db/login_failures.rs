@@ -0,0 +1,108 @@
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+
+use crate::error::AppResult;
+
+/// When the lockout on `username` or `ip` ends, whichever is later, or `None`
+/// if neither is locked.
+pub async fn login_locked_until(
+    pool: &PgPool,
+    username: &str,
+    ip: Option<&str>,
+) -> AppResult<Option<DateTime<Utc>>> {
+    let locked_until = sqlx::query_scalar::<_, Option<DateTime<Utc>>>(
+        r#"
+        SELECT MAX(locked_until)
+        FROM login_failures
+        WHERE locked_until > NOW()
+          AND ((scope = 'username' AND key = $1) OR (scope = 'ip' AND key = $2))
+        "#,
+    )
+    .bind(username)
+    .bind(ip)
+    .fetch_one(pool)
+    .await?;
+
+    Ok(locked_until)
+}
+
+/// Count a failed login against `key`, starting over if the last failure was
+/// before `forget_before`. Returns the number of failures so far.
+pub async fn record_login_failure(
+    pool: &PgPool,
+    scope: &str,
+    key: &str,
+    forget_before: DateTime<Utc>,
+) -> AppResult<i32> {
+    let failures = sqlx::query_scalar::<_, i32>(
+        r#"
+        INSERT INTO login_failures (scope, key, failures, last_failure_at)
+        VALUES ($1, $2, 1, NOW())
+        ON CONFLICT (scope, key) DO UPDATE
+        SET failures = CASE
+                WHEN login_failures.last_failure_at < $3 THEN 1
+                ELSE login_failures.failures + 1
+            END,
+            last_failure_at = NOW()
+        RETURNING failures
+        "#,
+    )
+    .bind(scope)
+    .bind(key)
+    .bind(forget_before)
+    .fetch_one(pool)
+    .await?;
+
+    Ok(failures)
+}
+
+pub async fn lock_login(
+    pool: &PgPool,
+    scope: &str,
+    key: &str,
+    locked_until: DateTime<Utc>,
+) -> AppResult<()> {
+    sqlx::query(
+        r#"
+        UPDATE login_failures
+        SET locked_until = $3
+        WHERE scope = $1 AND key = $2
+        "#,
+    )
+    .bind(scope)
+    .bind(key)
+    .bind(locked_until)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+pub async fn clear_login_failures(pool: &PgPool, scope: &str, key: &str) -> AppResult<()> {
+    sqlx::query("DELETE FROM login_failures WHERE scope = $1 AND key = $2")
+        .bind(scope)
+        .bind(key)
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
+
+/// Remove counters whose last failure was before `forget_before` and which
+/// are not locked.
+pub async fn delete_stale_login_failures(
+    pool: &PgPool,
+    forget_before: DateTime<Utc>,
+) -> AppResult<u64> {
+    let result = sqlx::query(
+        r#"
+        DELETE FROM login_failures
+        WHERE last_failure_at < $1 AND (locked_until IS NULL OR locked_until <= NOW())
+        "#,
+    )
+    .bind(forget_before)
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected())
+}
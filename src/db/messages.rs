@@ -2,32 +2,49 @@ use sqlx::PgPool;
 use uuid::Uuid;
 
 use crate::{
+    db::{
+        attachments::{link_attachments, load_message_attachments},
+        link_previews::load_message_previews,
+    },
     error::AppResult,
     models::message::{Message, MessageWithUsername},
 };
 
+/// Store a message with the given uploads attached (see
+/// [`link_attachments`]). Returns it with the sender's current username and
+/// profile.
 pub async fn create_message(
     pool: &PgPool,
     room_id: Uuid,
     user_id: Uuid,
     content: &str,
-) -> AppResult<Message> {
+    attachment_ids: &[Uuid],
+) -> AppResult<MessageWithUsername> {
     let id = Uuid::new_v4();
+    let mut tx = pool.begin().await?;
 
-    let message = sqlx::query_as::<_, Message>(
+    let mut message = sqlx::query_as::<_, MessageWithUsername>(
         r#"
-        INSERT INTO messages (id, room_id, user_id, content)
-        VALUES ($1, $2, $3, $4)
-        RETURNING id, room_id, user_id, content, created_at
+        WITH m AS (
+            INSERT INTO messages (id, room_id, user_id, content)
+            VALUES ($1, $2, $3, $4)
+            RETURNING id, room_id, user_id, content, created_at
+        )
+        SELECT m.id, m.room_id, m.user_id, u.username, u.display_name, u.avatar_url,
+               m.content, m.created_at
+        FROM m
+        JOIN users u ON m.user_id = u.id
         "#,
     )
     .bind(id)
     .bind(room_id)
     .bind(user_id)
     .bind(content)
-    .fetch_one(pool)
+    .fetch_one(&mut *tx)
     .await?;
+    message.attachments = link_attachments(&mut tx, id, room_id, user_id, attachment_ids).await?;
 
+    tx.commit().await?;
     Ok(message)
 }
 
@@ -54,51 +71,133 @@ pub async fn list_recent_messages(
     Ok(messages)
 }
 
+/// The newest messages in a room, newest first, leaving out those from users
+/// `viewer_id` has blocked.
 pub async fn list_recent_messages_with_usernames(
     pool: &PgPool,
     room_id: Uuid,
+    viewer_id: Uuid,
     limit: i64,
 ) -> AppResult<Vec<MessageWithUsername>> {
-    let messages = sqlx::query_as::<_, MessageWithUsername>(
+    let mut messages = sqlx::query_as::<_, MessageWithUsername>(
         r#"
-        SELECT m.id, m.room_id, m.user_id, u.username, m.content, m.created_at
+        SELECT m.id, m.room_id, m.user_id, u.username, u.display_name, u.avatar_url,
+               m.content, m.created_at
         FROM messages m
         JOIN users u ON m.user_id = u.id
         WHERE m.room_id = $1
+          AND NOT EXISTS (
+              SELECT 1 FROM user_blocks b WHERE b.blocker_id = $2 AND b.blocked_id = m.user_id
+          )
         ORDER BY m.created_at DESC
-        LIMIT $2
+        LIMIT $3
         "#,
     )
     .bind(room_id)
+    .bind(viewer_id)
     .bind(limit)
     .fetch_all(pool)
     .await?;
+    load_extras(pool, &mut messages).await?;
 
     Ok(messages)
 }
 
+pub async fn get_message_with_username(
+    pool: &PgPool,
+    id: Uuid,
+) -> AppResult<Option<MessageWithUsername>> {
+    let mut message = sqlx::query_as::<_, MessageWithUsername>(
+        r#"
+        SELECT m.id, m.room_id, m.user_id, u.username, u.display_name, u.avatar_url,
+               m.content, m.created_at
+        FROM messages m
+        JOIN users u ON m.user_id = u.id
+        WHERE m.id = $1
+        "#,
+    )
+    .bind(id)
+    .fetch_optional(pool)
+    .await?;
+    if let Some(message) = &mut message {
+        load_extras(pool, std::slice::from_mut(message)).await?;
+    }
+
+    Ok(message)
+}
+
+/// Messages older than `before`, newest first, leaving out those from users
+/// `viewer_id` has blocked.
 pub async fn list_messages_before(
     pool: &PgPool,
     room_id: Uuid,
+    viewer_id: Uuid,
     before: Uuid,
     limit: i64,
 ) -> AppResult<Vec<MessageWithUsername>> {
-    let messages = sqlx::query_as::<_, MessageWithUsername>(
+    let mut messages = sqlx::query_as::<_, MessageWithUsername>(
         r#"
-        SELECT m.id, m.room_id, m.user_id, u.username, m.content, m.created_at
+        SELECT m.id, m.room_id, m.user_id, u.username, u.display_name, u.avatar_url,
+               m.content, m.created_at
         FROM messages m
         JOIN users u ON m.user_id = u.id
-        WHERE m.room_id = $1 AND m.created_at < (SELECT created_at FROM messages WHERE id = $2)
+        WHERE m.room_id = $1 AND m.created_at < (SELECT created_at FROM messages WHERE id = $3)
+          AND NOT EXISTS (
+              SELECT 1 FROM user_blocks b WHERE b.blocker_id = $2 AND b.blocked_id = m.user_id
+          )
         ORDER BY m.created_at DESC
-        LIMIT $3
+        LIMIT $4
         "#,
     )
     .bind(room_id)
+    .bind(viewer_id)
     .bind(before)
     .bind(limit)
     .fetch_all(pool)
     .await?;
+    load_extras(pool, &mut messages).await?;
 
     Ok(messages)
 }
 
+
+/// Messages newer than `after`, oldest first, leaving out those from users
+/// `viewer_id` has blocked.
+pub async fn list_messages_after(
+    pool: &PgPool,
+    room_id: Uuid,
+    viewer_id: Uuid,
+    after: Uuid,
+    limit: i64,
+) -> AppResult<Vec<MessageWithUsername>> {
+    let mut messages = sqlx::query_as::<_, MessageWithUsername>(
+        r#"
+        SELECT m.id, m.room_id, m.user_id, u.username, u.display_name, u.avatar_url,
+               m.content, m.created_at
+        FROM messages m
+        JOIN users u ON m.user_id = u.id
+        WHERE m.room_id = $1 AND m.created_at > (SELECT created_at FROM messages WHERE id = $3)
+          AND NOT EXISTS (
+              SELECT 1 FROM user_blocks b WHERE b.blocker_id = $2 AND b.blocked_id = m.user_id
+          )
+        ORDER BY m.created_at ASC
+        LIMIT $4
+        "#,
+    )
+    .bind(room_id)
+    .bind(viewer_id)
+    .bind(after)
+    .bind(limit)
+    .fetch_all(pool)
+    .await?;
+    load_extras(pool, &mut messages).await?;
+
+    Ok(messages)
+}
+
+/// Fill in what is stored apart from the message row: attachments and link
+/// previews.
+async fn load_extras(pool: &PgPool, messages: &mut [MessageWithUsername]) -> AppResult<()> {
+    load_message_attachments(pool, messages).await?;
+    load_message_previews(pool, messages).await
+}
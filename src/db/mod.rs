@@ -1,4 +1,16 @@
+pub mod attachments;
+pub mod audit;
+pub mod blocks;
+pub mod export;
+pub mod link_previews;
+pub mod login_failures;
 pub mod messages;
+pub mod oidc;
+pub mod password_resets;
+pub mod refresh_tokens;
+pub mod revoked_tokens;
 pub mod rooms;
+pub mod sessions;
+pub mod two_factor;
 pub mod users;
-
+pub mod ws_tickets;
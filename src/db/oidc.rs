@@ -0,0 +1,195 @@
+use chrono::{DateTime, Utc};
+use sqlx::{FromRow, PgPool};
+use uuid::Uuid;
+
+use crate::error::AppResult;
+
+/// A sign-in waiting for the provider to redirect back.
+#[derive(Debug, FromRow)]
+pub struct PendingOidcLogin {
+    pub id: Uuid,
+    pub browser_hash: String,
+    pub nonce: String,
+    pub code_verifier: String,
+}
+
+pub struct NewOidcLogin<'a> {
+    pub provider: &'a str,
+    pub state_hash: &'a str,
+    pub browser_hash: &'a str,
+    pub nonce: &'a str,
+    pub code_verifier: &'a str,
+    pub expires_at: DateTime<Utc>,
+}
+
+pub async fn insert_oidc_login(pool: &PgPool, login: NewOidcLogin<'_>) -> AppResult<()> {
+    sqlx::query(
+        r#"
+        INSERT INTO oidc_logins
+            (id, provider, state_hash, browser_hash, nonce, code_verifier, expires_at)
+        VALUES ($1, $2, $3, $4, $5, $6, $7)
+        "#,
+    )
+    .bind(Uuid::new_v4())
+    .bind(login.provider)
+    .bind(login.state_hash)
+    .bind(login.browser_hash)
+    .bind(login.nonce)
+    .bind(login.code_verifier)
+    .bind(login.expires_at)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+/// Claim the pending sign-in for `state_hash`, so the provider's response can
+/// only be processed once. Returns `None` if there is no such live sign-in.
+pub async fn claim_oidc_login(
+    pool: &PgPool,
+    provider: &str,
+    state_hash: &str,
+) -> AppResult<Option<PendingOidcLogin>> {
+    let login = sqlx::query_as::<_, PendingOidcLogin>(
+        r#"
+        UPDATE oidc_logins
+        SET expires_at = NOW()
+        WHERE state_hash = $1 AND provider = $2 AND expires_at > NOW() AND user_id IS NULL
+        RETURNING id, browser_hash, nonce, code_verifier
+        "#,
+    )
+    .bind(state_hash)
+    .bind(provider)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(login)
+}
+
+/// Record who signed in and the grant the web client will exchange.
+pub async fn complete_oidc_login(
+    pool: &PgPool,
+    id: Uuid,
+    user_id: Uuid,
+    grant_hash: &str,
+    grant_expires_at: DateTime<Utc>,
+) -> AppResult<()> {
+    sqlx::query(
+        r#"
+        UPDATE oidc_logins
+        SET user_id = $2, grant_hash = $3, grant_expires_at = $4
+        WHERE id = $1
+        "#,
+    )
+    .bind(id)
+    .bind(user_id)
+    .bind(grant_hash)
+    .bind(grant_expires_at)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+/// Spend a grant. Returns the user it was issued to, or `None` if it is
+/// unknown, spent or expired.
+pub async fn redeem_oidc_grant(pool: &PgPool, grant_hash: &str) -> AppResult<Option<Uuid>> {
+    let user_id = sqlx::query_scalar::<_, Uuid>(
+        r#"
+        UPDATE oidc_logins
+        SET used_at = NOW()
+        WHERE grant_hash = $1 AND used_at IS NULL AND grant_expires_at > NOW()
+        RETURNING user_id
+        "#,
+    )
+    .bind(grant_hash)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(user_id)
+}
+
+/// Remove sign-ins that can no longer be completed or exchanged.
+pub async fn delete_expired_oidc_logins(pool: &PgPool) -> AppResult<u64> {
+    let result = sqlx::query(
+        r#"
+        DELETE FROM oidc_logins
+        WHERE expires_at <= NOW() AND (grant_expires_at IS NULL OR grant_expires_at <= NOW())
+        "#,
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected())
+}
+
+/// The local user linked to `subject` at `provider`, bumping the identity's
+/// last sign-in time.
+pub async fn touch_identity(
+    pool: &PgPool,
+    provider: &str,
+    subject: &str,
+    email: Option<&str>,
+) -> AppResult<Option<Uuid>> {
+    let user_id = sqlx::query_scalar::<_, Uuid>(
+        r#"
+        UPDATE user_identities
+        SET last_login_at = NOW(), email = COALESCE($3, email)
+        WHERE provider = $1 AND subject = $2
+        RETURNING user_id
+        "#,
+    )
+    .bind(provider)
+    .bind(subject)
+    .bind(email)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(user_id)
+}
+
+/// Create a password-less user linked to `subject` at `provider`. Returns
+/// `None` if `username` is taken.
+pub async fn create_user_with_identity(
+    pool: &PgPool,
+    username: &str,
+    email: Option<&str>,
+    provider: &str,
+    subject: &str,
+) -> AppResult<Option<Uuid>> {
+    let mut tx = pool.begin().await?;
+
+    let user_id = sqlx::query_scalar::<_, Uuid>(
+        r#"
+        INSERT INTO users (id, username, email, password_hash)
+        VALUES ($1, $2, $3, NULL)
+        ON CONFLICT DO NOTHING
+        RETURNING id
+        "#,
+    )
+    .bind(Uuid::new_v4())
+    .bind(username)
+    .bind(email)
+    .fetch_optional(&mut *tx)
+    .await?;
+    let Some(user_id) = user_id else {
+        return Ok(None);
+    };
+
+    sqlx::query(
+        r#"
+        INSERT INTO user_identities (id, user_id, provider, subject, email)
+        VALUES ($1, $2, $3, $4, $5)
+        "#,
+    )
+    .bind(Uuid::new_v4())
+    .bind(user_id)
+    .bind(provider)
+    .bind(subject)
+    .bind(email)
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+    Ok(Some(user_id))
+}
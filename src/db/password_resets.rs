@@ -0,0 +1,81 @@
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{error::AppResult, models::user::User};
+
+/// Store a new reset token for `user_id`, invalidating any earlier unused ones.
+pub async fn insert_password_reset_token(
+    pool: &PgPool,
+    user_id: Uuid,
+    token_hash: &str,
+    expires_at: DateTime<Utc>,
+) -> AppResult<()> {
+    let mut tx = pool.begin().await?;
+
+    sqlx::query(
+        r#"
+        UPDATE password_reset_tokens
+        SET used_at = NOW()
+        WHERE user_id = $1 AND used_at IS NULL
+        "#,
+    )
+    .bind(user_id)
+    .execute(&mut *tx)
+    .await?;
+
+    sqlx::query(
+        r#"
+        INSERT INTO password_reset_tokens (id, user_id, token_hash, expires_at)
+        VALUES ($1, $2, $3, $4)
+        "#,
+    )
+    .bind(Uuid::new_v4())
+    .bind(user_id)
+    .bind(token_hash)
+    .bind(expires_at)
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+    Ok(())
+}
+
+/// The user an unused, unexpired reset token was issued to, without spending
+/// the token.
+pub async fn get_password_reset_user(pool: &PgPool, token_hash: &str) -> AppResult<Option<User>> {
+    let user = sqlx::query_as::<_, User>(
+        r#"
+        SELECT u.id, u.username, u.email, u.password_hash, u.created_at
+        FROM password_reset_tokens t
+        JOIN users u ON u.id = t.user_id
+        WHERE t.token_hash = $1 AND t.used_at IS NULL AND t.expires_at > NOW()
+        "#,
+    )
+    .bind(token_hash)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(user)
+}
+
+/// Spend the reset token with `token_hash` if it is unused and unexpired.
+/// Returns the user it was issued to.
+pub async fn consume_password_reset_token(
+    pool: &PgPool,
+    token_hash: &str,
+) -> AppResult<Option<Uuid>> {
+    let user_id = sqlx::query_scalar::<_, Uuid>(
+        r#"
+        UPDATE password_reset_tokens
+        SET used_at = NOW()
+        WHERE token_hash = $1 AND used_at IS NULL AND expires_at > NOW()
+        RETURNING user_id
+        "#,
+    )
+    .bind(token_hash)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(user_id)
+}
@@ -0,0 +1,136 @@
+use chrono::{DateTime, Utc};
+use sqlx::{FromRow, PgPool};
+use uuid::Uuid;
+
+use crate::error::AppResult;
+
+#[derive(Debug, FromRow)]
+struct RefreshTokenRow {
+    id: Uuid,
+    user_id: Uuid,
+    session_id: Uuid,
+    expires_at: DateTime<Utc>,
+    rotated_at: Option<DateTime<Utc>>,
+    revoked_at: Option<DateTime<Utc>>,
+}
+
+/// Result of presenting a refresh token for rotation.
+#[derive(Debug)]
+pub enum RotateOutcome {
+    /// The token was current; it is now spent and `new_hash` replaces it.
+    Rotated { user_id: Uuid, session_id: Uuid },
+    /// The token had already been rotated. Its session and every token in it
+    /// have been revoked.
+    Reused { user_id: Uuid, session_id: Uuid },
+    /// The token was revoked (its session was revoked or signed out).
+    Revoked,
+    Expired,
+    NotFound,
+}
+
+pub async fn insert_refresh_token(
+    pool: &PgPool,
+    user_id: Uuid,
+    session_id: Uuid,
+    token_hash: &str,
+    expires_at: DateTime<Utc>,
+) -> AppResult<()> {
+    sqlx::query(
+        r#"
+        INSERT INTO refresh_tokens (id, user_id, session_id, token_hash, expires_at)
+        VALUES ($1, $2, $3, $4, $5)
+        "#,
+    )
+    .bind(Uuid::new_v4())
+    .bind(user_id)
+    .bind(session_id)
+    .bind(token_hash)
+    .bind(expires_at)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+/// Spend the token with `token_hash` and store `new_hash` in the same session,
+/// atomically. Presenting a spent token revokes the session.
+pub async fn rotate_refresh_token(
+    pool: &PgPool,
+    token_hash: &str,
+    new_hash: &str,
+    new_expires_at: DateTime<Utc>,
+) -> AppResult<RotateOutcome> {
+    let mut tx = pool.begin().await?;
+
+    let row = sqlx::query_as::<_, RefreshTokenRow>(
+        r#"
+        SELECT id, user_id, session_id, expires_at, rotated_at, revoked_at
+        FROM refresh_tokens
+        WHERE token_hash = $1
+        FOR UPDATE
+        "#,
+    )
+    .bind(token_hash)
+    .fetch_optional(&mut *tx)
+    .await?;
+
+    let Some(row) = row else {
+        return Ok(RotateOutcome::NotFound);
+    };
+
+    if row.rotated_at.is_none() && row.revoked_at.is_some() {
+        return Ok(RotateOutcome::Revoked);
+    }
+
+    if row.rotated_at.is_some() {
+        sqlx::query(
+            r#"
+            UPDATE refresh_tokens
+            SET revoked_at = NOW()
+            WHERE session_id = $1 AND revoked_at IS NULL
+            "#,
+        )
+        .bind(row.session_id)
+        .execute(&mut *tx)
+        .await?;
+        sqlx::query(r#"UPDATE sessions SET revoked_at = NOW() WHERE id = $1 AND revoked_at IS NULL"#)
+            .bind(row.session_id)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+        return Ok(RotateOutcome::Reused {
+            user_id: row.user_id,
+            session_id: row.session_id,
+        });
+    }
+
+    if row.expires_at <= Utc::now() {
+        return Ok(RotateOutcome::Expired);
+    }
+
+    sqlx::query(r#"UPDATE refresh_tokens SET rotated_at = NOW() WHERE id = $1"#)
+        .bind(row.id)
+        .execute(&mut *tx)
+        .await?;
+
+    sqlx::query(
+        r#"
+        INSERT INTO refresh_tokens (id, user_id, session_id, token_hash, expires_at)
+        VALUES ($1, $2, $3, $4, $5)
+        "#,
+    )
+    .bind(Uuid::new_v4())
+    .bind(row.user_id)
+    .bind(row.session_id)
+    .bind(new_hash)
+    .bind(new_expires_at)
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(RotateOutcome::Rotated {
+        user_id: row.user_id,
+        session_id: row.session_id,
+    })
+}
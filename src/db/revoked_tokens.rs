@@ -0,0 +1,63 @@
+use chrono::{DateTime, Utc};
+use sqlx::{FromRow, PgPool};
+use uuid::Uuid;
+
+use crate::error::AppResult;
+
+#[derive(Debug, FromRow)]
+pub struct RevokedToken {
+    pub jti: Uuid,
+    pub expires_at: DateTime<Utc>,
+    pub revoked_at: DateTime<Utc>,
+}
+
+pub async fn insert_revoked_token(
+    pool: &PgPool,
+    jti: Uuid,
+    user_id: Uuid,
+    expires_at: DateTime<Utc>,
+) -> AppResult<()> {
+    sqlx::query(
+        r#"
+        INSERT INTO revoked_tokens (jti, user_id, expires_at)
+        VALUES ($1, $2, $3)
+        ON CONFLICT (jti) DO NOTHING
+        "#,
+    )
+    .bind(jti)
+    .bind(user_id)
+    .bind(expires_at)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+/// Unexpired revocations recorded after `since`, oldest first.
+pub async fn list_revoked_tokens_since(
+    pool: &PgPool,
+    since: DateTime<Utc>,
+) -> AppResult<Vec<RevokedToken>> {
+    let rows = sqlx::query_as::<_, RevokedToken>(
+        r#"
+        SELECT jti, expires_at, revoked_at
+        FROM revoked_tokens
+        WHERE revoked_at > $1 AND expires_at > NOW()
+        ORDER BY revoked_at ASC
+        "#,
+    )
+    .bind(since)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(rows)
+}
+
+/// Remove revocations for tokens that have expired anyway.
+pub async fn delete_expired_revoked_tokens(pool: &PgPool) -> AppResult<u64> {
+    let result = sqlx::query(r#"DELETE FROM revoked_tokens WHERE expires_at <= NOW()"#)
+        .execute(pool)
+        .await?;
+
+    Ok(result.rows_affected())
+}
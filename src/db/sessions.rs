@@ -0,0 +1,224 @@
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{error::AppResult, models::session::Session};
+
+pub async fn create_session(
+    pool: &PgPool,
+    user_id: Uuid,
+    user_agent: Option<&str>,
+    ip: Option<&str>,
+) -> AppResult<Session> {
+    let session = sqlx::query_as::<_, Session>(
+        r#"
+        INSERT INTO sessions (id, user_id, user_agent, ip)
+        VALUES ($1, $2, $3, $4)
+        RETURNING id, user_agent, ip, created_at, last_seen_at
+        "#,
+    )
+    .bind(Uuid::new_v4())
+    .bind(user_id)
+    .bind(user_agent)
+    .bind(ip)
+    .fetch_one(pool)
+    .await?;
+
+    Ok(session)
+}
+
+/// When a session was started (the user signed in), if it exists.
+pub async fn session_started_at(pool: &PgPool, session_id: Uuid) -> AppResult<Option<DateTime<Utc>>> {
+    let started_at = sqlx::query_scalar::<_, DateTime<Utc>>(
+        r#"SELECT created_at FROM sessions WHERE id = $1"#,
+    )
+    .bind(session_id)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(started_at)
+}
+
+/// Record activity on a session. `ip` replaces the stored address when given.
+pub async fn touch_session(pool: &PgPool, session_id: Uuid, ip: Option<&str>) -> AppResult<()> {
+    sqlx::query(
+        r#"
+        UPDATE sessions
+        SET last_seen_at = NOW(), ip = COALESCE($2, ip)
+        WHERE id = $1
+        "#,
+    )
+    .bind(session_id)
+    .bind(ip)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+/// Sessions of `user_id` that are not revoked and were seen after
+/// `active_since`, most recently seen first.
+pub async fn list_active_sessions(
+    pool: &PgPool,
+    user_id: Uuid,
+    active_since: DateTime<Utc>,
+) -> AppResult<Vec<Session>> {
+    let sessions = sqlx::query_as::<_, Session>(
+        r#"
+        SELECT id, user_agent, ip, created_at, last_seen_at
+        FROM sessions
+        WHERE user_id = $1 AND revoked_at IS NULL AND last_seen_at > $2
+        ORDER BY last_seen_at DESC
+        "#,
+    )
+    .bind(user_id)
+    .bind(active_since)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(sessions)
+}
+
+/// Revoke a session of `user_id` and its refresh tokens. Returns `false` if
+/// the session does not exist, belongs to someone else or is already revoked.
+pub async fn revoke_session(pool: &PgPool, user_id: Uuid, session_id: Uuid) -> AppResult<bool> {
+    let mut tx = pool.begin().await?;
+
+    let revoked = sqlx::query(
+        r#"
+        UPDATE sessions
+        SET revoked_at = NOW()
+        WHERE id = $1 AND user_id = $2 AND revoked_at IS NULL
+        "#,
+    )
+    .bind(session_id)
+    .bind(user_id)
+    .execute(&mut *tx)
+    .await?
+    .rows_affected()
+        > 0;
+
+    if revoked {
+        sqlx::query(
+            r#"
+            UPDATE refresh_tokens
+            SET revoked_at = NOW()
+            WHERE session_id = $1 AND revoked_at IS NULL
+            "#,
+        )
+        .bind(session_id)
+        .execute(&mut *tx)
+        .await?;
+    }
+
+    tx.commit().await?;
+    Ok(revoked)
+}
+
+/// Revoke every active session of `user_id` except `keep`, with their refresh
+/// tokens. Returns the ids of the sessions revoked.
+pub async fn revoke_user_sessions(
+    pool: &PgPool,
+    user_id: Uuid,
+    keep: Option<Uuid>,
+) -> AppResult<Vec<Uuid>> {
+    let mut tx = pool.begin().await?;
+
+    let revoked = sqlx::query_scalar::<_, Uuid>(
+        r#"
+        UPDATE sessions
+        SET revoked_at = NOW()
+        WHERE user_id = $1 AND revoked_at IS NULL AND id IS DISTINCT FROM $2
+        RETURNING id
+        "#,
+    )
+    .bind(user_id)
+    .bind(keep)
+    .fetch_all(&mut *tx)
+    .await?;
+
+    sqlx::query(
+        r#"
+        UPDATE refresh_tokens
+        SET revoked_at = NOW()
+        WHERE session_id = ANY($1) AND revoked_at IS NULL
+        "#,
+    )
+    .bind(&revoked)
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+    Ok(revoked)
+}
+
+/// Ids and revocation times of sessions revoked after `since` and no earlier
+/// than `not_before`, oldest first.
+pub async fn list_sessions_revoked_since(
+    pool: &PgPool,
+    since: DateTime<Utc>,
+    not_before: DateTime<Utc>,
+) -> AppResult<Vec<(Uuid, DateTime<Utc>)>> {
+    let rows = sqlx::query_as::<_, (Uuid, DateTime<Utc>)>(
+        r#"
+        SELECT id, revoked_at
+        FROM sessions
+        WHERE revoked_at > $1 AND revoked_at > $2
+        ORDER BY revoked_at ASC
+        "#,
+    )
+    .bind(since)
+    .bind(not_before)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(rows)
+}
+
+/// Revoke every active session last seen before `cutoff`, with its refresh
+/// tokens. Returns the ids of the sessions revoked.
+pub async fn revoke_idle_sessions(pool: &PgPool, cutoff: DateTime<Utc>) -> AppResult<Vec<Uuid>> {
+    let mut tx = pool.begin().await?;
+
+    let revoked = sqlx::query_scalar::<_, Uuid>(
+        r#"
+        UPDATE sessions
+        SET revoked_at = NOW()
+        WHERE revoked_at IS NULL AND last_seen_at < $1
+        RETURNING id
+        "#,
+    )
+    .bind(cutoff)
+    .fetch_all(&mut *tx)
+    .await?;
+
+    sqlx::query(
+        r#"
+        UPDATE refresh_tokens
+        SET revoked_at = NOW()
+        WHERE session_id = ANY($1) AND revoked_at IS NULL
+        "#,
+    )
+    .bind(&revoked)
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+    Ok(revoked)
+}
+
+/// Delete sessions revoked before `cutoff`. Idle sessions must be revoked
+/// first (see `revoke_idle_sessions`), so every instance learns of them.
+pub async fn delete_stale_sessions(pool: &PgPool, cutoff: DateTime<Utc>) -> AppResult<u64> {
+    let result = sqlx::query(
+        r#"
+        DELETE FROM sessions
+        WHERE revoked_at < $1
+        "#,
+    )
+    .bind(cutoff)
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected())
+}
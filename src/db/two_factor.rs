@@ -0,0 +1,205 @@
+use chrono::{DateTime, Utc};
+use sqlx::{FromRow, PgPool};
+use uuid::Uuid;
+
+use crate::error::AppResult;
+
+#[derive(Debug, FromRow)]
+pub struct UserTotp {
+    pub secret: String,
+    pub enabled_at: Option<DateTime<Utc>>,
+    /// Time step of the last accepted code.
+    pub last_used_step: Option<i64>,
+}
+
+pub async fn get_user_totp(pool: &PgPool, user_id: Uuid) -> AppResult<Option<UserTotp>> {
+    let totp = sqlx::query_as::<_, UserTotp>(
+        r#"SELECT secret, enabled_at, last_used_step FROM user_totp WHERE user_id = $1"#,
+    )
+    .bind(user_id)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(totp)
+}
+
+/// Store a new, not yet enabled, secret. Returns `false` if 2FA is already
+/// enabled for `user_id`.
+pub async fn set_pending_totp(pool: &PgPool, user_id: Uuid, secret: &str) -> AppResult<bool> {
+    let result = sqlx::query(
+        r#"
+        INSERT INTO user_totp (user_id, secret)
+        VALUES ($1, $2)
+        ON CONFLICT (user_id) DO UPDATE
+        SET secret = EXCLUDED.secret, created_at = NOW(), last_used_step = NULL
+        WHERE user_totp.enabled_at IS NULL
+        "#,
+    )
+    .bind(user_id)
+    .bind(secret)
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+/// Record that the code for `step` was used. Returns `false` if that step (or
+/// a later one) was already used, i.e. the code is being replayed.
+pub async fn record_totp_step(pool: &PgPool, user_id: Uuid, step: i64) -> AppResult<bool> {
+    let result = sqlx::query(
+        r#"
+        UPDATE user_totp
+        SET last_used_step = $2
+        WHERE user_id = $1 AND (last_used_step IS NULL OR last_used_step < $2)
+        "#,
+    )
+    .bind(user_id)
+    .bind(step)
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+/// Turn on 2FA and replace the user's recovery codes. Returns `false` if it
+/// was already on.
+pub async fn enable_totp(
+    pool: &PgPool,
+    user_id: Uuid,
+    recovery_code_hashes: &[String],
+) -> AppResult<bool> {
+    let mut tx = pool.begin().await?;
+
+    let enabled = sqlx::query(
+        r#"UPDATE user_totp SET enabled_at = NOW() WHERE user_id = $1 AND enabled_at IS NULL"#,
+    )
+    .bind(user_id)
+    .execute(&mut *tx)
+    .await?
+    .rows_affected()
+        > 0;
+    if !enabled {
+        return Ok(false);
+    }
+
+    sqlx::query(r#"DELETE FROM recovery_codes WHERE user_id = $1"#)
+        .bind(user_id)
+        .execute(&mut *tx)
+        .await?;
+    sqlx::query(
+        r#"
+        INSERT INTO recovery_codes (id, user_id, code_hash)
+        SELECT gen_random_uuid(), $1, code_hash
+        FROM UNNEST($2::text[]) AS t(code_hash)
+        "#,
+    )
+    .bind(user_id)
+    .bind(recovery_code_hashes)
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+    Ok(true)
+}
+
+pub async fn disable_totp(pool: &PgPool, user_id: Uuid) -> AppResult<()> {
+    let mut tx = pool.begin().await?;
+    sqlx::query(r#"DELETE FROM user_totp WHERE user_id = $1"#)
+        .bind(user_id)
+        .execute(&mut *tx)
+        .await?;
+    sqlx::query(r#"DELETE FROM recovery_codes WHERE user_id = $1"#)
+        .bind(user_id)
+        .execute(&mut *tx)
+        .await?;
+    tx.commit().await?;
+    Ok(())
+}
+
+/// Spend one of the user's recovery codes. Returns `false` if it does not
+/// match an unused code.
+pub async fn use_recovery_code(pool: &PgPool, user_id: Uuid, code_hash: &str) -> AppResult<bool> {
+    let result = sqlx::query(
+        r#"
+        UPDATE recovery_codes
+        SET used_at = NOW()
+        WHERE id = (
+            SELECT id FROM recovery_codes
+            WHERE user_id = $1 AND code_hash = $2 AND used_at IS NULL
+            LIMIT 1
+        )
+        "#,
+    )
+    .bind(user_id)
+    .bind(code_hash)
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+pub async fn insert_login_challenge(
+    pool: &PgPool,
+    user_id: Uuid,
+    token_hash: &str,
+    expires_at: DateTime<Utc>,
+) -> AppResult<()> {
+    sqlx::query(
+        r#"
+        INSERT INTO login_challenges (id, user_id, token_hash, expires_at)
+        VALUES ($1, $2, $3, $4)
+        "#,
+    )
+    .bind(Uuid::new_v4())
+    .bind(user_id)
+    .bind(token_hash)
+    .bind(expires_at)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+/// Count an attempt against a live challenge. Returns its user, or `None` if
+/// the challenge is unknown, spent, expired or out of attempts.
+pub async fn attempt_login_challenge(
+    pool: &PgPool,
+    token_hash: &str,
+    max_attempts: i32,
+) -> AppResult<Option<Uuid>> {
+    let user_id = sqlx::query_scalar::<_, Uuid>(
+        r#"
+        UPDATE login_challenges
+        SET attempts = attempts + 1
+        WHERE token_hash = $1 AND used_at IS NULL AND expires_at > NOW() AND attempts < $2
+        RETURNING user_id
+        "#,
+    )
+    .bind(token_hash)
+    .bind(max_attempts)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(user_id)
+}
+
+/// Mark a challenge as spent. Returns `false` if it already was.
+pub async fn complete_login_challenge(pool: &PgPool, token_hash: &str) -> AppResult<bool> {
+    let result = sqlx::query(
+        r#"UPDATE login_challenges SET used_at = NOW() WHERE token_hash = $1 AND used_at IS NULL"#,
+    )
+    .bind(token_hash)
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+/// Remove challenges that can no longer be used.
+pub async fn delete_expired_login_challenges(pool: &PgPool) -> AppResult<u64> {
+    let result = sqlx::query(r#"DELETE FROM login_challenges WHERE expires_at <= NOW()"#)
+        .execute(pool)
+        .await?;
+
+    Ok(result.rows_affected())
+}
@@ -2,26 +2,29 @@ use sqlx::PgPool;
 use uuid::Uuid;
 
 use crate::{
+    config::DeletedUserMessages,
     error::AppResult,
-    models::user::User,
+    models::user::{UpdateProfileRequest, User, UserProfile, UserSummary, DELETED_USER_ID},
 };
 
 pub async fn create_user(
     pool: &PgPool,
     username: &str,
+    email: Option<&str>,
     password_hash: &str,
 ) -> AppResult<User> {
     let id = Uuid::new_v4();
 
     let user = sqlx::query_as::<_, User>(
         r#"
-        INSERT INTO users (id, username, password_hash)
-        VALUES ($1, $2, $3)
-        RETURNING id, username, password_hash, created_at
+        INSERT INTO users (id, username, email, password_hash)
+        VALUES ($1, $2, $3, $4)
+        RETURNING id, username, email, password_hash, created_at
         "#,
     )
     .bind(id)
     .bind(username)
+    .bind(email)
     .bind(password_hash)
     .fetch_one(pool)
     .await?;
@@ -29,15 +32,16 @@ pub async fn create_user(
     Ok(user)
 }
 
+/// Case-insensitive lookup by (normalized) username.
 pub async fn get_user_by_username(
     pool: &PgPool,
     username: &str,
 ) -> AppResult<Option<User>> {
     let user = sqlx::query_as::<_, User>(
         r#"
-        SELECT id, username, password_hash, created_at
+        SELECT id, username, email, password_hash, created_at
         FROM users
-        WHERE username = $1
+        WHERE lower(username) = lower($1)
         "#,
     )
     .bind(username)
@@ -47,11 +51,10 @@ pub async fn get_user_by_username(
     Ok(user)
 }
 
-#[allow(dead_code)]
 pub async fn get_user_by_id(pool: &PgPool, id: Uuid) -> AppResult<Option<User>> {
     let user = sqlx::query_as::<_, User>(
         r#"
-        SELECT id, username, password_hash, created_at
+        SELECT id, username, email, password_hash, created_at
         FROM users
         WHERE id = $1
         "#,
@@ -63,3 +66,166 @@ pub async fn get_user_by_id(pool: &PgPool, id: Uuid) -> AppResult<Option<User>>
     Ok(user)
 }
 
+
+/// Case-insensitive lookup by email address.
+pub async fn get_user_by_email(pool: &PgPool, email: &str) -> AppResult<Option<User>> {
+    let user = sqlx::query_as::<_, User>(
+        r#"
+        SELECT id, username, email, password_hash, created_at
+        FROM users
+        WHERE lower(email) = lower($1)
+        "#,
+    )
+    .bind(email)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(user)
+}
+
+pub async fn update_password_hash(pool: &PgPool, id: Uuid, password_hash: &str) -> AppResult<()> {
+    sqlx::query(r#"UPDATE users SET password_hash = $2 WHERE id = $1"#)
+        .bind(id)
+        .bind(password_hash)
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
+
+pub async fn get_user_profile(pool: &PgPool, id: Uuid) -> AppResult<Option<UserProfile>> {
+    let profile = sqlx::query_as::<_, UserProfile>(
+        r#"
+        SELECT id, username, display_name, avatar_url, bio, status, created_at
+        FROM users
+        WHERE id = $1
+        "#,
+    )
+    .bind(id)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(profile)
+}
+
+/// Apply a validated profile update; fields that are `None` keep their value.
+pub async fn update_user_profile(
+    pool: &PgPool,
+    id: Uuid,
+    update: &UpdateProfileRequest,
+) -> AppResult<UserProfile> {
+    let profile = sqlx::query_as::<_, UserProfile>(
+        r#"
+        UPDATE users SET
+            display_name = CASE WHEN $2 THEN $3 ELSE display_name END,
+            avatar_url = CASE WHEN $4 THEN $5 ELSE avatar_url END,
+            bio = CASE WHEN $6 THEN $7 ELSE bio END,
+            status = CASE WHEN $8 THEN $9 ELSE status END
+        WHERE id = $1
+        RETURNING id, username, display_name, avatar_url, bio, status, created_at
+        "#,
+    )
+    .bind(id)
+    .bind(update.display_name.is_some())
+    .bind(update.display_name.clone().flatten())
+    .bind(update.avatar_url.is_some())
+    .bind(update.avatar_url.clone().flatten())
+    .bind(update.bio.is_some())
+    .bind(update.bio.clone().flatten())
+    .bind(update.status.is_some())
+    .bind(update.status.clone().flatten())
+    .fetch_one(pool)
+    .await?;
+
+    Ok(profile)
+}
+
+/// Users whose username or display name starts with `prefix` (ignoring case),
+/// ordered by username and starting after the username `after`. Leaves out
+/// the searcher, anyone who has blocked them and the deleted user account.
+pub async fn search_users(
+    pool: &PgPool,
+    searcher_id: Uuid,
+    prefix: &str,
+    after: Option<&str>,
+    limit: i64,
+) -> AppResult<Vec<UserSummary>> {
+    // Match `%`, `_` and `\` in the prefix literally.
+    let escaped = prefix
+        .replace('\\', r"\\")
+        .replace('%', r"\%")
+        .replace('_', r"\_");
+    let users = sqlx::query_as::<_, UserSummary>(
+        r#"
+        SELECT u.id, u.username, u.display_name, u.avatar_url
+        FROM users u
+        WHERE (lower(u.username) LIKE lower($2) || '%'
+               OR lower(u.display_name) LIKE lower($2) || '%')
+          AND u.id <> $1 AND u.id <> $5
+          AND ($3::text IS NULL OR lower(u.username) > lower($3))
+          AND NOT EXISTS (
+              SELECT 1 FROM user_blocks b WHERE b.blocker_id = u.id AND b.blocked_id = $1
+          )
+        ORDER BY lower(u.username)
+        LIMIT $4
+        "#,
+    )
+    .bind(searcher_id)
+    .bind(escaped)
+    .bind(after)
+    .bind(limit)
+    .bind(DELETED_USER_ID)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(users)
+}
+
+/// Delete an account, handling its messages per `messages`. Its sessions are
+/// revoked and kept without the user, device or address until the stale
+/// session sweep, so every instance learns of the revocation. Returns the
+/// revoked session ids and the number of messages anonymized or deleted.
+pub async fn delete_user(
+    pool: &PgPool,
+    id: Uuid,
+    messages: DeletedUserMessages,
+) -> AppResult<(Vec<Uuid>, u64)> {
+    let mut tx = pool.begin().await?;
+
+    let affected = match messages {
+        DeletedUserMessages::Anonymize => {
+            sqlx::query(r#"UPDATE messages SET user_id = $2 WHERE user_id = $1"#)
+                .bind(id)
+                .bind(DELETED_USER_ID)
+                .execute(&mut *tx)
+                .await?
+        }
+        DeletedUserMessages::Delete => {
+            sqlx::query(r#"DELETE FROM messages WHERE user_id = $1"#)
+                .bind(id)
+                .execute(&mut *tx)
+                .await?
+        }
+    }
+    .rows_affected();
+
+    let sessions = sqlx::query_scalar::<_, Uuid>(
+        r#"
+        UPDATE sessions
+        SET revoked_at = COALESCE(revoked_at, NOW()), user_agent = NULL, ip = NULL
+        WHERE user_id = $1
+        RETURNING id
+        "#,
+    )
+    .bind(id)
+    .fetch_all(&mut *tx)
+    .await?;
+
+    sqlx::query(r#"DELETE FROM users WHERE id = $1"#)
+        .bind(id)
+        .execute(&mut *tx)
+        .await?;
+
+    tx.commit().await?;
+    Ok((sessions, affected))
+}
@@ -0,0 +1,67 @@
+use chrono::{DateTime, Utc};
+use sqlx::{FromRow, PgPool};
+use uuid::Uuid;
+
+use crate::error::AppResult;
+
+#[derive(Debug, FromRow)]
+pub struct WsTicket {
+    pub user_id: Uuid,
+    pub username: String,
+    pub session_id: Uuid,
+    pub room_id: Option<Uuid>,
+}
+
+pub async fn insert_ws_ticket(
+    pool: &PgPool,
+    ticket_hash: &str,
+    user_id: Uuid,
+    session_id: Uuid,
+    room_id: Option<Uuid>,
+    expires_at: DateTime<Utc>,
+) -> AppResult<()> {
+    sqlx::query(
+        r#"
+        INSERT INTO ws_tickets (ticket_hash, user_id, session_id, room_id, expires_at)
+        VALUES ($1, $2, $3, $4, $5)
+        "#,
+    )
+    .bind(ticket_hash)
+    .bind(user_id)
+    .bind(session_id)
+    .bind(room_id)
+    .bind(expires_at)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+/// Delete and return the live ticket with `ticket_hash`, so it works once.
+pub async fn take_ws_ticket(pool: &PgPool, ticket_hash: &str) -> AppResult<Option<WsTicket>> {
+    let ticket = sqlx::query_as::<_, WsTicket>(
+        r#"
+        WITH taken AS (
+            DELETE FROM ws_tickets
+            WHERE ticket_hash = $1 AND expires_at > NOW()
+            RETURNING user_id, session_id, room_id
+        )
+        SELECT taken.user_id, u.username, taken.session_id, taken.room_id
+        FROM taken
+        JOIN users u ON u.id = taken.user_id
+        "#,
+    )
+    .bind(ticket_hash)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(ticket)
+}
+
+pub async fn delete_expired_ws_tickets(pool: &PgPool) -> AppResult<u64> {
+    let result = sqlx::query(r#"DELETE FROM ws_tickets WHERE expires_at <= NOW()"#)
+        .execute(pool)
+        .await?;
+
+    Ok(result.rows_affected())
+}
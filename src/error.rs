@@ -1,14 +1,27 @@
 use axum::{
-    http::StatusCode,
+    extract::rejection::{JsonRejection, PathRejection, QueryRejection},
+    http::{header, HeaderValue, StatusCode},
     response::{IntoResponse, Response},
     Json,
 };
 use serde::Serialize;
+use std::collections::BTreeMap;
 use thiserror::Error;
 
+use crate::request_id;
+
 #[derive(Debug, Serialize)]
 pub struct ErrorResponse {
+    /// Human-readable; may change.
     pub error: String,
+    /// Stable reason clients can switch on.
+    pub code: &'static str,
+    /// Problems with individual request fields, by field name.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub details: Option<BTreeMap<&'static str, String>>,
+    /// Quote this when reporting a problem; it is in the server logs.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
 }
 
 #[derive(Debug, Error)]
@@ -19,14 +32,31 @@ pub enum AppError {
     #[error("bad request: {0}")]
     BadRequest(String),
 
+    /// A request field has an unacceptable value.
+    #[error("bad request: {message}")]
+    Validation { field: &'static str, message: String },
+
     #[error("not found: {0}")]
     NotFound(String),
 
     #[error("forbidden: {0}")]
     Forbidden(String),
 
+    /// Something unique (a username, a room name, ...) is already taken.
+    #[error("conflict: {message}")]
+    Conflict { code: &'static str, message: String },
+
+    #[error("payload too large: {0}")]
+    PayloadTooLarge(String),
+
+    #[error("unsupported media type: {0}")]
+    UnsupportedMediaType(String),
+
+    #[error("too many requests: {message}")]
+    TooManyRequests { message: String, retry_after_secs: u64 },
+
     #[error("database error: {0}")]
-    Database(#[from] sqlx::Error),
+    Database(sqlx::Error),
 
     #[error("jwt error: {0}")]
     Jwt(#[from] jsonwebtoken::errors::Error),
@@ -40,23 +70,305 @@ pub enum AppError {
 
 pub type AppResult<T> = Result<T, AppError>;
 
+impl AppError {
+    pub fn validation(field: &'static str, message: impl Into<String>) -> Self {
+        AppError::Validation {
+            field,
+            message: message.into(),
+        }
+    }
+
+    /// Attribute a validation error to another request field, for rules
+    /// shared by requests that name the field differently.
+    pub fn for_field(self, field: &'static str) -> Self {
+        match self {
+            AppError::Validation { message, .. } => AppError::Validation { field, message },
+            other => other,
+        }
+    }
+
+    /// Stable code for the response body.
+    pub fn code(&self) -> &'static str {
+        match self {
+            AppError::Unauthorized(_) => "unauthorized",
+            AppError::BadRequest(_) => "bad_request",
+            AppError::Validation { .. } => "validation_failed",
+            AppError::NotFound(_) => "not_found",
+            AppError::Forbidden(_) => "forbidden",
+            AppError::Conflict { code, .. } => code,
+            AppError::PayloadTooLarge(_) => "payload_too_large",
+            AppError::UnsupportedMediaType(_) => "unsupported_media_type",
+            AppError::TooManyRequests { .. } => "too_many_requests",
+            AppError::Database(_)
+            | AppError::Jwt(_)
+            | AppError::Password(_)
+            | AppError::Internal(_) => "internal_error",
+        }
+    }
+}
+
+impl From<sqlx::Error> for AppError {
+    /// Unique violations become conflicts, so a racing duplicate insert gets a
+    /// `409` rather than a `500`.
+    fn from(err: sqlx::Error) -> Self {
+        match &err {
+            sqlx::Error::Database(db_err) if db_err.is_unique_violation() => {
+                let (code, message) = unique_violation(db_err.constraint());
+                AppError::Conflict {
+                    code,
+                    message: message.into(),
+                }
+            }
+            _ => AppError::Database(err),
+        }
+    }
+}
+
+/// Extractor rejections (see [`crate::extract`]) keep their status where
+/// there is a matching variant; the rest are bad requests, or internal errors
+/// for a route that does not match its handler.
+fn rejection(status: StatusCode, message: String) -> AppError {
+    match status {
+        StatusCode::PAYLOAD_TOO_LARGE => AppError::PayloadTooLarge(message),
+        StatusCode::UNSUPPORTED_MEDIA_TYPE => AppError::UnsupportedMediaType(message),
+        status if status.is_server_error() => AppError::Internal(anyhow::Error::msg(message)),
+        _ => AppError::BadRequest(message),
+    }
+}
+
+impl From<JsonRejection> for AppError {
+    fn from(rejection: JsonRejection) -> Self {
+        self::rejection(rejection.status(), rejection.body_text())
+    }
+}
+
+impl From<QueryRejection> for AppError {
+    fn from(rejection: QueryRejection) -> Self {
+        self::rejection(rejection.status(), rejection.body_text())
+    }
+}
+
+impl From<PathRejection> for AppError {
+    fn from(rejection: PathRejection) -> Self {
+        self::rejection(rejection.status(), rejection.body_text())
+    }
+}
+
+/// Error code and message for a violation of the unique constraint or index
+/// called `constraint`.
+fn unique_violation(constraint: Option<&str>) -> (&'static str, &'static str) {
+    match constraint {
+        Some("idx_users_username_lower") => ("username_taken", "username already taken"),
+        Some("idx_users_email_lower") => ("email_taken", "email already registered"),
+        Some("rooms_name_key") => ("room_name_taken", "room name already taken"),
+        Some("user_identities_provider_subject_key") => {
+            ("identity_linked", "provider account already linked")
+        }
+        _ => ("conflict", "already exists"),
+    }
+}
+
 impl IntoResponse for AppError {
     fn into_response(self) -> Response {
         let status = match self {
             AppError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
-            AppError::BadRequest(_) => StatusCode::BAD_REQUEST,
+            AppError::BadRequest(_) | AppError::Validation { .. } => StatusCode::BAD_REQUEST,
             AppError::NotFound(_) => StatusCode::NOT_FOUND,
             AppError::Forbidden(_) => StatusCode::FORBIDDEN,
+            AppError::Conflict { .. } => StatusCode::CONFLICT,
+            AppError::PayloadTooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
+            AppError::UnsupportedMediaType(_) => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            AppError::TooManyRequests { .. } => StatusCode::TOO_MANY_REQUESTS,
             AppError::Database(_) | AppError::Jwt(_) | AppError::Password(_) | AppError::Internal(_) => {
                 StatusCode::INTERNAL_SERVER_ERROR
             }
         };
 
+        // Server-side failures are logged in full (with the request id, from
+        // the request's span) but not described to the client.
+        let error = if status.is_server_error() {
+            tracing::error!("{self}");
+            "internal server error".to_string()
+        } else {
+            self.to_string()
+        };
+        let details = match &self {
+            AppError::Validation { field, message } => {
+                Some(BTreeMap::from([(*field, message.clone())]))
+            }
+            _ => None,
+        };
+        let retry_after = match &self {
+            AppError::TooManyRequests { retry_after_secs, .. } => Some(*retry_after_secs),
+            _ => None,
+        };
+
         let body = Json(ErrorResponse {
-            error: self.to_string(),
+            error,
+            code: self.code(),
+            details,
+            request_id: request_id::current(),
         });
 
-        (status, body).into_response()
+        let mut response = (status, body).into_response();
+        if let Some(secs) = retry_after {
+            response
+                .headers_mut()
+                .insert(header::RETRY_AFTER, HeaderValue::from(secs));
+        }
+        response
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{body::Body, http::Request, routing::get, Router};
+    use sqlx::error::{DatabaseError, ErrorKind};
+    use tower::ServiceExt;
+
+    /// A database error as a driver would report it.
+    #[derive(Debug)]
+    struct MockDatabaseError {
+        /// `ErrorKind` is neither `Copy` nor `Clone`.
+        kind: fn() -> ErrorKind,
+        constraint: Option<&'static str>,
+    }
+
+    impl std::fmt::Display for MockDatabaseError {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            write!(f, "mock database error")
+        }
+    }
+
+    impl std::error::Error for MockDatabaseError {}
+
+    impl DatabaseError for MockDatabaseError {
+        fn message(&self) -> &str {
+            "mock database error"
+        }
+
+        fn as_error(&self) -> &(dyn std::error::Error + Send + Sync + 'static) {
+            self
+        }
+
+        fn as_error_mut(&mut self) -> &mut (dyn std::error::Error + Send + Sync + 'static) {
+            self
+        }
+
+        fn into_error(self: Box<Self>) -> Box<dyn std::error::Error + Send + Sync + 'static> {
+            self
+        }
+
+        fn constraint(&self) -> Option<&str> {
+            self.constraint
+        }
+
+        fn kind(&self) -> ErrorKind {
+            (self.kind)()
+        }
+    }
+
+    fn database_error(kind: fn() -> ErrorKind, constraint: Option<&'static str>) -> AppError {
+        sqlx::Error::Database(Box::new(MockDatabaseError { kind, constraint })).into()
+    }
+
+    #[test]
+    fn unique_violations_are_conflicts_with_stable_codes() {
+        let cases = [
+            (Some("idx_users_username_lower"), "username_taken"),
+            (Some("idx_users_email_lower"), "email_taken"),
+            (Some("rooms_name_key"), "room_name_taken"),
+            (Some("user_identities_provider_subject_key"), "identity_linked"),
+            (Some("some_new_index"), "conflict"),
+            (None, "conflict"),
+        ];
+        for (constraint, code) in cases {
+            let err = database_error(|| ErrorKind::UniqueViolation, constraint);
+            assert_eq!(err.code(), code, "{constraint:?}");
+            assert_eq!(err.into_response().status(), StatusCode::CONFLICT, "{constraint:?}");
+        }
+    }
+
+    #[test]
+    fn other_database_errors_are_internal() {
+        let constraint = Some("idx_users_username_lower");
+        let err = database_error(|| ErrorKind::ForeignKeyViolation, constraint);
+        assert!(matches!(err, AppError::Database(_)));
+        assert_eq!(err.into_response().status(), StatusCode::INTERNAL_SERVER_ERROR);
+    }
+
+    async fn body_json(response: Response) -> serde_json::Value {
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        serde_json::from_slice(&body).unwrap()
+    }
+
+    #[tokio::test]
+    async fn server_errors_are_not_described_to_the_client() {
+        let errors = [
+            AppError::Internal(anyhow::anyhow!("disk /var/secret is full")),
+            AppError::Password("argon2 parameters rejected".into()),
+            database_error(|| ErrorKind::ForeignKeyViolation, Some("messages_room_id_fkey")),
+        ];
+        for err in errors {
+            let response = err.into_response();
+            assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+            let body = body_json(response).await;
+            assert_eq!(body["error"], "internal server error");
+            assert_eq!(body["code"], "internal_error");
+        }
+    }
+
+    #[tokio::test]
+    async fn client_errors_are_described() {
+        let response = AppError::validation("username", "username is too short").into_response();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let body = body_json(response).await;
+        assert_eq!(body["error"], "bad request: username is too short");
+        assert_eq!(body["code"], "validation_failed");
+        assert_eq!(body["details"]["username"], "username is too short");
+        // Outside a request there is no id to quote.
+        assert!(body.get("request_id").is_none());
+
+        let response = AppError::TooManyRequests {
+            message: "slow down".into(),
+            retry_after_secs: 30,
+        }
+        .into_response();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(response.headers()[header::RETRY_AFTER], "30");
+    }
+
+    #[tokio::test]
+    async fn error_bodies_echo_the_request_id() {
+        async fn fail() -> AppResult<()> {
+            Err(AppError::Internal(anyhow::anyhow!("boom")))
+        }
+        let app = Router::new()
+            .route("/fail", get(fail))
+            .layer(axum::middleware::from_fn(request_id::assign));
+
+        let request = Request::get("/fail")
+            .header(&request_id::REQUEST_ID_HEADER, "client-id-42")
+            .body(Body::empty())
+            .unwrap();
+        let response = app.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.headers()[&request_id::REQUEST_ID_HEADER], "client-id-42");
+        let body = body_json(response).await;
+        assert_eq!(body["request_id"], "client-id-42");
+        assert_eq!(body["error"], "internal server error");
+
+        // An unusable incoming id is replaced with a generated one.
+        let request = Request::get("/fail")
+            .header(&request_id::REQUEST_ID_HEADER, "not an id")
+            .body(Body::empty())
+            .unwrap();
+        let response = app.oneshot(request).await.unwrap();
+        let echoed = response.headers()[&request_id::REQUEST_ID_HEADER]
+            .to_str()
+            .unwrap()
+            .to_owned();
+        assert_ne!(echoed, "not an id");
+        assert_eq!(body_json(response).await["request_id"], echoed.as_str());
+    }
+}
@@ -0,0 +1,155 @@
+//! `Json`, `Query` and `Path` extractors that reject with [`AppError`], so a
+//! malformed body, query string or path gets the same JSON error body as any
+//! other failure instead of axum's plain-text one. Use these instead of
+//! axum's in handlers; `Json` also works as a response.
+
+use axum::{
+    extract::{
+        rejection::JsonRejection, FromRequest, FromRequestParts, OptionalFromRequest, Request,
+    },
+    response::{IntoResponse, Response},
+};
+use serde::Serialize;
+
+use crate::error::AppError;
+
+#[derive(Debug, Clone, Copy, Default, FromRequest)]
+#[from_request(via(axum::Json), rejection(AppError))]
+pub struct Json<T>(pub T);
+
+/// An optional body: `None` without a JSON content type, rejected if the
+/// body is there but malformed.
+impl<T, S> OptionalFromRequest<S> for Json<T>
+where
+    axum::Json<T>: OptionalFromRequest<S, Rejection = JsonRejection>,
+    S: Send + Sync,
+{
+    type Rejection = AppError;
+
+    async fn from_request(req: Request, state: &S) -> Result<Option<Self>, Self::Rejection> {
+        let json = <axum::Json<T> as OptionalFromRequest<S>>::from_request(req, state).await?;
+        Ok(json.map(|axum::Json(value)| Json(value)))
+    }
+}
+
+impl<T: Serialize> IntoResponse for Json<T> {
+    fn into_response(self) -> Response {
+        axum::Json(self.0).into_response()
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, FromRequestParts)]
+#[from_request(via(axum::extract::Query), rejection(AppError))]
+pub struct Query<T>(pub T);
+
+#[derive(Debug, Clone, Copy, Default, FromRequestParts)]
+#[from_request(via(axum::extract::Path), rejection(AppError))]
+pub struct Path<T>(pub T);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{
+        body::Body,
+        http::{header, StatusCode},
+        routing::{get, post},
+        Router,
+    };
+    use serde::Deserialize;
+    use tower::ServiceExt;
+    use uuid::Uuid;
+
+    #[derive(Deserialize)]
+    struct NewRoom {
+        name: String,
+    }
+
+    #[derive(Deserialize)]
+    struct Page {
+        limit: u32,
+    }
+
+    async fn create(Json(room): Json<NewRoom>) -> String {
+        room.name
+    }
+
+    async fn create_optional(room: Option<Json<NewRoom>>) -> String {
+        room.map(|Json(room)| room.name).unwrap_or_default()
+    }
+
+    async fn list(Query(page): Query<Page>) -> String {
+        page.limit.to_string()
+    }
+
+    async fn show(Path(id): Path<Uuid>) -> String {
+        id.to_string()
+    }
+
+    fn app() -> Router {
+        Router::new()
+            .route("/json", post(create))
+            .route("/optional", post(create_optional))
+            .route("/query", get(list))
+            .route("/path/{id}", get(show))
+    }
+
+    async fn send(request: Request) -> (StatusCode, serde_json::Value) {
+        let response = app().oneshot(request).await.unwrap();
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status, serde_json::from_slice(&body).unwrap_or_default())
+    }
+
+    fn json(body: &'static str) -> Request {
+        json_to("/json", body)
+    }
+
+    fn json_to(uri: &str, body: &'static str) -> Request {
+        Request::post(uri)
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body))
+            .unwrap()
+    }
+
+    fn get_request(uri: &str) -> Request {
+        Request::get(uri).body(Body::empty()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn rejections_are_error_responses() {
+        let no_content_type = Request::post("/json").body(Body::from("{}")).unwrap();
+        let cases = [
+            (json("{\"name\":"), StatusCode::BAD_REQUEST, "bad_request"),
+            (json("{\"name\":7}"), StatusCode::BAD_REQUEST, "bad_request"),
+            (json("{}"), StatusCode::BAD_REQUEST, "bad_request"),
+            (no_content_type, StatusCode::UNSUPPORTED_MEDIA_TYPE, "unsupported_media_type"),
+            (json_to("/optional", "{\"name\":"), StatusCode::BAD_REQUEST, "bad_request"),
+            (get_request("/query"), StatusCode::BAD_REQUEST, "bad_request"),
+            (get_request("/query?limit=many"), StatusCode::BAD_REQUEST, "bad_request"),
+            (get_request("/path/not-a-uuid"), StatusCode::BAD_REQUEST, "bad_request"),
+        ];
+        for (request, status, code) in cases {
+            let uri = request.uri().clone();
+            let (actual, body) = send(request).await;
+            assert_eq!(actual, status, "{uri}");
+            assert_eq!(body["code"], code, "{uri}");
+            assert!(body["error"].as_str().is_some_and(|e| !e.is_empty()), "{uri}");
+        }
+    }
+
+    #[tokio::test]
+    async fn well_formed_requests_are_extracted() {
+        let id = Uuid::new_v4();
+        for request in [
+            json("{\"name\":\"general\"}"),
+            json_to("/optional", "{\"name\":\"general\"}"),
+            Request::post("/optional").body(Body::empty()).unwrap(),
+            get_request("/query?limit=20"),
+            get_request(&format!("/path/{id}")),
+        ] {
+            let uri = request.uri().clone();
+            let response = app().oneshot(request).await.unwrap();
+            assert_eq!(response.status(), StatusCode::OK, "{uri}");
+        }
+    }
+}
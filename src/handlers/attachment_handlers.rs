@@ -0,0 +1,242 @@
+use axum::{
+    body::Bytes,
+    extract::{multipart::MultipartError, Multipart, State},
+    http::{header, HeaderName, StatusCode},
+    response::IntoResponse,
+};
+use uuid::Uuid;
+
+use crate::{
+    attachments::{
+        content_disposition, images::is_processed_image, is_allowed_type, sanitize_filename,
+        sniff_content_type, storage_key, thumbnail_storage_key,
+    },
+    auth::extractor::AuthUser,
+    db::{
+        attachments::{get_attachment, get_thumbnail, insert_attachment},
+        rooms::get_room_if_member,
+    },
+    error::{AppError, AppResult},
+    extract::{Json, Path},
+    models::attachment::{Attachment, AttachmentInfo, Thumbnail, ThumbnailInfo},
+    state::AppState,
+};
+
+/// Upload a file to a room, as `multipart/form-data` with a single `file`
+/// field. It is unattached until a message is sent with its id.
+pub async fn upload_attachment_handler(
+    State(state): State<AppState>,
+    Path(room_id): Path<Uuid>,
+    auth: AuthUser,
+    mut multipart: Multipart,
+) -> AppResult<(StatusCode, Json<AttachmentInfo>)> {
+    get_room_if_member(&state.db, room_id, auth.user_id)
+        .await?
+        .ok_or_else(|| AppError::NotFound("room not found".into()))?;
+
+    let max_bytes = state.attachment_limits.max_bytes;
+    let Some(mut field) = multipart.next_field().await.map_err(multipart_error)? else {
+        return Err(AppError::validation("file", "no file uploaded"));
+    };
+    if field.name() != Some("file") {
+        return Err(AppError::validation(
+            "file",
+            "expected a single field named file",
+        ));
+    }
+    let filename = sanitize_filename(field.file_name());
+
+    let mut data = Vec::new();
+    while let Some(chunk) = field.chunk().await.map_err(multipart_error)? {
+        if data.len() + chunk.len() > max_bytes {
+            return Err(AppError::PayloadTooLarge(format!(
+                "attachments may be at most {max_bytes} bytes"
+            )));
+        }
+        data.extend_from_slice(&chunk);
+    }
+    drop(field);
+    if multipart
+        .next_field()
+        .await
+        .map_err(multipart_error)?
+        .is_some()
+    {
+        return Err(AppError::validation(
+            "file",
+            "expected a single field named file",
+        ));
+    }
+    if data.is_empty() {
+        return Err(AppError::validation("file", "file is empty"));
+    }
+
+    let content_type = sniff_content_type(&data);
+    if !is_allowed_type(content_type, &state.attachment_limits.types) {
+        return Err(AppError::UnsupportedMediaType(format!(
+            "{content_type} files are not allowed"
+        )));
+    }
+
+    let id = Uuid::new_v4();
+    let key = storage_key(room_id, id);
+    let (data, width, height, images) = if is_processed_image(content_type) {
+        let image = state
+            .images
+            .process(Bytes::from(data), content_type)
+            .await?;
+        let (width, height) = (image.width as i32, image.height as i32);
+        (image.data, Some(width), Some(height), image.thumbnails)
+    } else {
+        (Bytes::from(data), None, None, Vec::new())
+    };
+
+    let attachment = Attachment {
+        id,
+        room_id,
+        message_id: None,
+        uploader_id: Some(auth.user_id),
+        filename,
+        content_type: content_type.to_string(),
+        size_bytes: data.len() as i64,
+        storage_key: key.clone(),
+        width,
+        height,
+    };
+    let thumbnails: Vec<Thumbnail> = images
+        .iter()
+        .map(|t| Thumbnail {
+            attachment_id: id,
+            size: t.size as i32,
+            width: t.width as i32,
+            height: t.height as i32,
+            content_type: t.content_type.to_string(),
+            storage_key: thumbnail_storage_key(&key, t.size),
+        })
+        .collect();
+
+    // On failure, remove whatever files were already stored.
+    let mut stored = Vec::new();
+    let result: AppResult<()> = async {
+        state.storage.put(&key, data, content_type).await?;
+        stored.push(key.clone());
+        for (image, thumbnail) in images.into_iter().zip(&thumbnails) {
+            state
+                .storage
+                .put(&thumbnail.storage_key, image.data, image.content_type)
+                .await?;
+            stored.push(thumbnail.storage_key.clone());
+        }
+        insert_attachment(&state.db, &attachment, &thumbnails).await
+    }
+    .await;
+    if let Err(err) = result {
+        for key in &stored {
+            if let Err(cleanup) = state.storage.delete(key).await {
+                tracing::warn!("failed to remove attachment file {key}: {cleanup}");
+            }
+        }
+        return Err(err);
+    }
+
+    let mut info = AttachmentInfo::from(attachment);
+    info.thumbnails = thumbnails.iter().map(ThumbnailInfo::from).collect();
+    Ok((StatusCode::CREATED, Json(info)))
+}
+
+/// The attachment `attachment_id` if the user may see it: members of its room
+/// can see attachments of sent messages, and an unsent upload is only visible
+/// to its uploader.
+async fn visible_attachment(
+    state: &AppState,
+    attachment_id: Uuid,
+    user_id: Uuid,
+) -> AppResult<Attachment> {
+    let not_found = || AppError::NotFound("attachment not found".into());
+    let attachment = get_attachment(&state.db, attachment_id)
+        .await?
+        .ok_or_else(not_found)?;
+    let visible = match attachment.message_id {
+        Some(_) => get_room_if_member(&state.db, attachment.room_id, user_id)
+            .await?
+            .is_some(),
+        None => attachment.uploader_id == Some(user_id),
+    };
+    if !visible {
+        return Err(not_found());
+    }
+    Ok(attachment)
+}
+
+/// Read a stored file; a missing one is logged and reported as not found.
+async fn stored_file(state: &AppState, key: &str) -> AppResult<Bytes> {
+    match state.storage.get(key).await? {
+        Some(data) => Ok(data),
+        None => {
+            tracing::warn!("attachment file {key} is missing from storage");
+            Err(AppError::NotFound("attachment not found".into()))
+        }
+    }
+}
+
+/// Headers for serving a stored file.
+fn file_headers(content_type: &str, filename: &str) -> [(HeaderName, String); 5] {
+    let served_type = if content_type == "text/plain" {
+        "text/plain; charset=utf-8".to_string()
+    } else {
+        content_type.to_string()
+    };
+    [
+        (header::CONTENT_TYPE, served_type),
+        (
+            header::CONTENT_DISPOSITION,
+            content_disposition(content_type, filename),
+        ),
+        (header::X_CONTENT_TYPE_OPTIONS, "nosniff".to_string()),
+        (
+            header::CONTENT_SECURITY_POLICY,
+            "default-src 'none'; sandbox".to_string(),
+        ),
+        (header::CACHE_CONTROL, "private, max-age=86400".to_string()),
+    ]
+}
+
+/// Download an attachment.
+pub async fn download_attachment_handler(
+    State(state): State<AppState>,
+    Path(attachment_id): Path<Uuid>,
+    auth: AuthUser,
+) -> AppResult<impl IntoResponse> {
+    let attachment = visible_attachment(&state, attachment_id, auth.user_id).await?;
+    let data = stored_file(&state, &attachment.storage_key).await?;
+    Ok((
+        file_headers(&attachment.content_type, &attachment.filename),
+        data,
+    ))
+}
+
+/// Download a thumbnail of an image attachment. `size` is one of those listed
+/// in the attachment's `thumbnails`.
+pub async fn download_thumbnail_handler(
+    State(state): State<AppState>,
+    Path((attachment_id, size)): Path<(Uuid, i32)>,
+    auth: AuthUser,
+) -> AppResult<impl IntoResponse> {
+    let attachment = visible_attachment(&state, attachment_id, auth.user_id).await?;
+    let thumbnail = get_thumbnail(&state.db, attachment.id, size)
+        .await?
+        .ok_or_else(|| AppError::NotFound("thumbnail not found".into()))?;
+    let data = stored_file(&state, &thumbnail.storage_key).await?;
+    Ok((
+        file_headers(&thumbnail.content_type, &attachment.filename),
+        data,
+    ))
+}
+
+fn multipart_error(err: MultipartError) -> AppError {
+    if err.status() == StatusCode::PAYLOAD_TOO_LARGE {
+        AppError::PayloadTooLarge(err.body_text())
+    } else {
+        AppError::BadRequest(err.body_text())
+    }
+}
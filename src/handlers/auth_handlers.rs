@@ -1,66 +1,317 @@
 use axum::{
     extract::State,
-    Json,
+    http::StatusCode,
 };
 
 use chrono::{TimeZone, Utc};
+use uuid::Uuid;
 
 use crate::{
-    auth::{extractor::AuthUser, jwt::generate_token, password::{hash_password, verify_password}},
-    db::users::{create_user, get_user_by_username},
+    auth::{
+        extractor::AuthUser,
+        jwt::generate_token,
+        login_throttle,
+        password::{hash_password, verify_dummy_password, verify_password},
+        password_reset::{redeem_reset_token, request_reset, reset_token_user},
+        refresh::rotate,
+        session::{end_other_sessions, end_session, start_session, ClientInfo},
+        two_factor,
+    },
+    db::{
+        sessions::touch_session,
+        users::{create_user, get_user_by_id, get_user_by_username, update_password_hash},
+    },
     error::{AppError, AppResult},
-    models::auth::{LoginRequest, LoginResponse, MeResponse, RegisterRequest},
+    extract::Json,
+    models::auth::{
+        ChangePasswordRequest, DisableTwoFactorRequest, LoginOutcome, LoginRequest, LoginResponse,
+        MeResponse, PasswordResetConfirmRequest, PasswordResetRequest, RecoveryCodesResponse,
+        RefreshRequest, RegisterRequest, TotpCodeRequest, TotpEnrollResponse, TwoFactorChallenge,
+        TwoFactorLoginRequest,
+    },
     state::AppState,
+    validation::normalize_username,
 };
 
 pub async fn register(
     State(state): State<AppState>,
+    client: ClientInfo,
     Json(payload): Json<RegisterRequest>,
 ) -> AppResult<Json<LoginResponse>> {
-    if payload.username.trim().is_empty() || payload.password.trim().is_empty() {
-        return Err(AppError::BadRequest(
-            "username and password must not be empty".into(),
-        ));
-    }
+    let policy = &state.credential_policy;
+    let username = policy.validate_username(&payload.username)?;
+    policy.validate_password(&payload.password, &username)?;
 
-    if let Some(_) = get_user_by_username(&state.db, &payload.username).await? {
-        return Err(AppError::BadRequest("username already taken".into()));
+    let email = payload
+        .email
+        .as_deref()
+        .map(str::trim)
+        .filter(|e| !e.is_empty());
+    if email.is_some_and(|e| !e.contains('@')) {
+        return Err(AppError::validation("email", "invalid email address"));
     }
 
+    // A taken username or email surfaces as a conflict from the insert.
     let password_hash = hash_password(&payload.password)?;
-    let user = create_user(&state.db, &payload.username, &password_hash).await?;
+    let user = create_user(&state.db, &username, email, &password_hash).await?;
 
-    let token = generate_token(&state, user.id, &user.username)?;
-
-    Ok(Json(LoginResponse {
-        token,
-        user_id: user.id,
-        username: user.username,
-    }))
+    let (session_id, refresh_token) = start_session(&state, user.id, &client).await?;
+    login_response(&state, user.id, user.username, session_id, refresh_token).map(Json)
 }
 
+/// Check the password. Accounts with 2FA get a login challenge instead of
+/// tokens.
 pub async fn login(
     State(state): State<AppState>,
+    client: ClientInfo,
     Json(payload): Json<LoginRequest>,
+) -> AppResult<Json<LoginOutcome>> {
+    let username = normalize_username(&payload.username);
+    login_throttle::check(&state, &username, &client).await?;
+    let user = get_user_by_username(&state.db, &username).await?;
+
+    // Accounts created through single sign-on have no password. Unknown
+    // usernames still cost a verification, so timing doesn't reveal them.
+    let valid = match user.as_ref().and_then(|u| u.password_hash.as_deref()) {
+        Some(hash) => verify_password(hash, &payload.password)?,
+        None => {
+            verify_dummy_password(&payload.password);
+            false
+        }
+    };
+    let user = match user {
+        Some(user) if valid => user,
+        user => {
+            let user_id = user.map(|u| u.id);
+            login_throttle::record_failure(&state, &username, user_id, &client).await?;
+            return Err(AppError::Unauthorized("invalid credentials".into()));
+        }
+    };
+
+    let outcome = sign_in(&state, user.id, user.username, &client).await?;
+    // With 2FA, failures are cleared once the second factor passes too.
+    if matches!(outcome, LoginOutcome::Authenticated(_)) {
+        login_throttle::record_success(&state, &username).await?;
+    }
+    Ok(Json(outcome))
+}
+
+/// Finish a login whose first factor (password or single sign-on) passed:
+/// accounts with 2FA get a login challenge, others a new session.
+pub(crate) async fn sign_in(
+    state: &AppState,
+    user_id: Uuid,
+    username: String,
+    client: &ClientInfo,
+) -> AppResult<LoginOutcome> {
+    if two_factor::is_enabled(state, user_id).await? {
+        let (challenge_token, expires_in) = two_factor::issue_challenge(state, user_id).await?;
+        return Ok(LoginOutcome::TwoFactorRequired(TwoFactorChallenge {
+            two_factor_required: true,
+            challenge_token,
+            expires_in,
+        }));
+    }
+
+    let (session_id, refresh_token) = start_session(state, user_id, client).await?;
+    login_response(state, user_id, username, session_id, refresh_token)
+        .map(LoginOutcome::Authenticated)
+}
+
+/// Second login step for accounts with 2FA. Wrong codes count as failed
+/// logins, so guessing them is throttled like guessing passwords.
+pub async fn login_two_factor(
+    State(state): State<AppState>,
+    client: ClientInfo,
+    Json(payload): Json<TwoFactorLoginRequest>,
 ) -> AppResult<Json<LoginResponse>> {
-    let user = get_user_by_username(&state.db, &payload.username)
+    let user_id = two_factor::attempt_challenge(&state, &payload.challenge_token).await?;
+    let user = get_user_by_id(&state.db, user_id)
         .await?
         .ok_or_else(|| AppError::Unauthorized("invalid credentials".into()))?;
+    login_throttle::check(&state, &user.username, &client).await?;
 
-    let valid = verify_password(&user.password_hash, &payload.password)?;
-    if !valid {
-        return Err(AppError::Unauthorized("invalid credentials".into()));
+    if !two_factor::redeem_challenge(&state, &payload.challenge_token, user.id, &payload.code)
+        .await?
+    {
+        login_throttle::record_failure(&state, &user.username, Some(user.id), &client).await?;
+        return Err(AppError::Unauthorized("invalid authentication code".into()));
     }
+    login_throttle::record_success(&state, &user.username).await?;
 
-    let token = generate_token(&state, user.id, &user.username)?;
+    let (session_id, refresh_token) = start_session(&state, user.id, &client).await?;
+    login_response(&state, user.id, user.username, session_id, refresh_token).map(Json)
+}
 
-    Ok(Json(LoginResponse {
-        token,
-        user_id: user.id,
-        username: user.username,
+/// Start 2FA enrollment: returns a new secret to add to an authenticator app.
+pub async fn enroll_two_factor(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+) -> AppResult<Json<TotpEnrollResponse>> {
+    let (secret, otpauth_uri) =
+        two_factor::begin_enrollment(&state, auth_user.user_id, &auth_user.username).await?;
+    Ok(Json(TotpEnrollResponse {
+        secret,
+        otpauth_uri,
     }))
 }
 
+/// Finish enrollment with a code from the authenticator app. Other sessions
+/// were signed in without a second factor, so they are signed out.
+pub async fn confirm_two_factor(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+    Json(payload): Json<TotpCodeRequest>,
+) -> AppResult<Json<RecoveryCodesResponse>> {
+    let recovery_codes =
+        two_factor::confirm_enrollment(&state, auth_user.user_id, &payload.code).await?;
+    end_other_sessions(&state, auth_user.user_id, Some(auth_user.session_id)).await?;
+    Ok(Json(RecoveryCodesResponse { recovery_codes }))
+}
+
+/// Turn 2FA off. Wrong passwords and codes count as failed logins, so a
+/// stolen access token can't be used to guess them.
+pub async fn disable_two_factor(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+    client: ClientInfo,
+    Json(payload): Json<DisableTwoFactorRequest>,
+) -> AppResult<StatusCode> {
+    let user = get_user_by_id(&state.db, auth_user.user_id)
+        .await?
+        .ok_or_else(|| AppError::Unauthorized("user no longer exists".into()))?;
+    login_throttle::check(&state, &user.username, &client).await?;
+    // Single sign-on accounts have no password; the code alone must do.
+    if let Some(hash) = &user.password_hash {
+        let password = payload.password.as_deref().unwrap_or_default();
+        if !verify_password(hash, password)? {
+            login_throttle::record_failure(&state, &user.username, Some(user.id), &client).await?;
+            return Err(AppError::Unauthorized("password is incorrect".into()));
+        }
+    }
+
+    if !two_factor::disable(&state, user.id, &payload.code).await? {
+        login_throttle::record_failure(&state, &user.username, Some(user.id), &client).await?;
+        return Err(AppError::Unauthorized("invalid authentication code".into()));
+    }
+    login_throttle::record_success(&state, &user.username).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Exchange a refresh token for a new access token and refresh token.
+pub async fn refresh(
+    State(state): State<AppState>,
+    client: ClientInfo,
+    Json(payload): Json<RefreshRequest>,
+) -> AppResult<Json<LoginResponse>> {
+    let (user_id, session_id, refresh_token) = rotate(&state, &payload.refresh_token).await?;
+    let user = get_user_by_id(&state.db, user_id)
+        .await?
+        .ok_or_else(|| AppError::Unauthorized("invalid refresh token".into()))?;
+    touch_session(&state.db, session_id, client.ip.as_deref()).await?;
+
+    login_response(&state, user.id, user.username, session_id, refresh_token).map(Json)
+}
+
+/// Revoke the caller's access token and end its session: the session's
+/// refresh and access tokens stop working and WebSockets opened with them are
+/// closed.
+pub async fn logout(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+) -> AppResult<StatusCode> {
+    state.revocations.revoke_token(&state.db, &auth_user.claims).await?;
+    end_session(&state, auth_user.user_id, auth_user.session_id).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Change the caller's password. Every other session is signed out. Wrong
+/// current passwords count as failed logins.
+pub async fn change_password(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+    client: ClientInfo,
+    Json(payload): Json<ChangePasswordRequest>,
+) -> AppResult<StatusCode> {
+    let user = get_user_by_id(&state.db, auth_user.user_id)
+        .await?
+        .ok_or_else(|| AppError::Unauthorized("user no longer exists".into()))?;
+    state
+        .credential_policy
+        .validate_password(&payload.new_password, &user.username)
+        .map_err(|e| e.for_field("new_password"))?;
+    let Some(current_hash) = &user.password_hash else {
+        return Err(AppError::BadRequest(
+            "account has no password; set one with a password reset".into(),
+        ));
+    };
+    login_throttle::check(&state, &user.username, &client).await?;
+    if !verify_password(current_hash, &payload.current_password)? {
+        login_throttle::record_failure(&state, &user.username, Some(user.id), &client).await?;
+        return Err(AppError::Unauthorized("current password is incorrect".into()));
+    }
+    login_throttle::record_success(&state, &user.username).await?;
+
+    let password_hash = hash_password(&payload.new_password)?;
+    update_password_hash(&state.db, user.id, &password_hash).await?;
+    end_other_sessions(&state, user.id, Some(auth_user.session_id)).await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Email a password reset link. Always succeeds, so the response does not
+/// reveal whether the address is registered.
+pub async fn request_password_reset(
+    State(state): State<AppState>,
+    Json(payload): Json<PasswordResetRequest>,
+) -> StatusCode {
+    request_reset(&state, payload.email.trim().to_string());
+    StatusCode::ACCEPTED
+}
+
+/// Set a new password with a reset token. Every session is signed out.
+pub async fn confirm_password_reset(
+    State(state): State<AppState>,
+    Json(payload): Json<PasswordResetConfirmRequest>,
+) -> AppResult<StatusCode> {
+    // Check the new password before the token is spent.
+    let user = reset_token_user(&state, &payload.token)
+        .await?
+        .ok_or_else(|| AppError::BadRequest("invalid or expired reset token".into()))?;
+    state
+        .credential_policy
+        .validate_password(&payload.new_password, &user.username)
+        .map_err(|e| e.for_field("new_password"))?;
+
+    let user_id = redeem_reset_token(&state, &payload.token)
+        .await?
+        .ok_or_else(|| AppError::BadRequest("invalid or expired reset token".into()))?;
+
+    let password_hash = hash_password(&payload.new_password)?;
+    update_password_hash(&state.db, user_id, &password_hash).await?;
+    end_other_sessions(&state, user_id, None).await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+fn login_response(
+    state: &AppState,
+    user_id: Uuid,
+    username: String,
+    session_id: Uuid,
+    refresh_token: String,
+) -> AppResult<LoginResponse> {
+    let token = generate_token(state, user_id, &username, session_id)?;
+
+    Ok(LoginResponse {
+        token,
+        expires_in: state.access_token_ttl.num_seconds(),
+        refresh_token,
+        user_id,
+        username,
+    })
+}
+
 pub async fn me(
     auth_user: AuthUser,
 ) -> AppResult<Json<MeResponse>> {
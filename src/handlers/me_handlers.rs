@@ -0,0 +1,224 @@
+use axum::{
+    extract::State,
+    http::{header, StatusCode},
+    response::IntoResponse,
+};
+use chrono::Utc;
+use serde_json::json;
+use uuid::Uuid;
+
+use crate::{
+    auth::{
+        extractor::AuthUser,
+        password::verify_password,
+        session::{end_session, ClientInfo},
+        two_factor,
+    },
+    config::DeletedUserMessages,
+    db::{
+        audit::insert_audit_event,
+        blocks::{block_user, list_blocked_users, unblock_user},
+        export::{list_user_identities, list_user_memberships, list_user_messages},
+        sessions::{list_active_sessions, session_started_at},
+        users::{delete_user, get_user_by_id, get_user_profile, update_user_profile},
+    },
+    error::{AppError, AppResult},
+    extract::{Json, Path},
+    models::{
+        export::AccountExport,
+        session::SessionResponse,
+        user::{BlockedUser, DeleteAccountRequest, UpdateProfileRequest, UserProfile},
+    },
+    state::AppState,
+    validation::validate_profile_update,
+};
+
+/// How recently an account with neither a password nor 2FA must have signed
+/// in to be deleted.
+const RECENT_SIGN_IN: chrono::Duration = chrono::Duration::minutes(10);
+
+/// The caller's signed-in devices, most recently active first.
+pub async fn list_sessions_handler(
+    State(state): State<AppState>,
+    auth: AuthUser,
+) -> AppResult<Json<Vec<SessionResponse>>> {
+    // A session idle for longer than the refresh token lifetime has no usable
+    // refresh token left.
+    let active_since = Utc::now() - state.refresh_token_ttl;
+    let sessions = list_active_sessions(&state.db, auth.user_id, active_since).await?;
+
+    let sessions = sessions
+        .into_iter()
+        .map(|s| SessionResponse {
+            current: s.id == auth.session_id,
+            id: s.id,
+            user_agent: s.user_agent,
+            ip: s.ip,
+            created_at: s.created_at,
+            last_seen_at: s.last_seen_at,
+        })
+        .collect();
+    Ok(Json(sessions))
+}
+
+/// Sign a device out remotely, closing its WebSockets.
+pub async fn revoke_session_handler(
+    State(state): State<AppState>,
+    Path(session_id): Path<Uuid>,
+    auth: AuthUser,
+) -> AppResult<StatusCode> {
+    if !end_session(&state, auth.user_id, session_id).await? {
+        return Err(AppError::NotFound("session not found".into()));
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// The caller's own profile.
+pub async fn get_profile_handler(
+    State(state): State<AppState>,
+    auth: AuthUser,
+) -> AppResult<Json<UserProfile>> {
+    let profile = get_user_profile(&state.db, auth.user_id)
+        .await?
+        .ok_or_else(|| AppError::NotFound("user not found".into()))?;
+    Ok(Json(profile))
+}
+
+/// Change some of the caller's profile fields.
+pub async fn update_profile_handler(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Json(payload): Json<UpdateProfileRequest>,
+) -> AppResult<Json<UserProfile>> {
+    let update = validate_profile_update(payload)?;
+    let profile = update_user_profile(&state.db, auth.user_id, &update).await?;
+    Ok(Json(profile))
+}
+
+/// Users the caller has blocked.
+pub async fn list_blocks_handler(
+    State(state): State<AppState>,
+    auth: AuthUser,
+) -> AppResult<Json<Vec<BlockedUser>>> {
+    let users = list_blocked_users(&state.db, auth.user_id).await?;
+    Ok(Json(users))
+}
+
+/// Block a user: their messages are hidden from the caller. Idempotent.
+pub async fn block_user_handler(
+    State(state): State<AppState>,
+    Path(user_id): Path<Uuid>,
+    auth: AuthUser,
+) -> AppResult<StatusCode> {
+    if user_id == auth.user_id {
+        return Err(AppError::BadRequest("cannot block yourself".into()));
+    }
+    if get_user_by_id(&state.db, user_id).await?.is_none() {
+        return Err(AppError::NotFound("user not found".into()));
+    }
+    if block_user(&state.db, auth.user_id, user_id).await? {
+        state.broadcaster.publish_blocks_changed(auth.user_id).await;
+    }
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub async fn unblock_user_handler(
+    State(state): State<AppState>,
+    Path(user_id): Path<Uuid>,
+    auth: AuthUser,
+) -> AppResult<StatusCode> {
+    if !unblock_user(&state.db, auth.user_id, user_id).await? {
+        return Err(AppError::NotFound("user is not blocked".into()));
+    }
+    state.broadcaster.publish_blocks_changed(auth.user_id).await;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Delete the caller's account after confirming it is really them. Their
+/// messages are anonymized or deleted per `DELETED_USER_MESSAGES`, and every
+/// session is signed out.
+pub async fn delete_account_handler(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    client: ClientInfo,
+    Json(payload): Json<DeleteAccountRequest>,
+) -> AppResult<StatusCode> {
+    let user = get_user_by_id(&state.db, auth.user_id)
+        .await?
+        .ok_or_else(|| AppError::Unauthorized("user no longer exists".into()))?;
+
+    let mut confirmed = false;
+    if let Some(hash) = &user.password_hash {
+        let password = payload.password.as_deref().unwrap_or_default();
+        if !verify_password(hash, password)? {
+            return Err(AppError::Unauthorized("password is incorrect".into()));
+        }
+        confirmed = true;
+    }
+    if two_factor::is_enabled(&state, user.id).await? {
+        let code = payload.code.as_deref().unwrap_or_default();
+        if !two_factor::verify_second_factor(&state, user.id, code).await? {
+            return Err(AppError::Unauthorized("invalid authentication code".into()));
+        }
+        confirmed = true;
+    }
+    // Single sign-on accounts without 2FA have nothing to re-enter; ask for a
+    // fresh sign-in instead.
+    if !confirmed {
+        let started_at = session_started_at(&state.db, auth.session_id).await?;
+        if !matches!(started_at, Some(at) if at > Utc::now() - RECENT_SIGN_IN) {
+            return Err(AppError::Unauthorized(format!(
+                "sign in again within {} minutes before deleting the account",
+                RECENT_SIGN_IN.num_minutes()
+            )));
+        }
+    }
+
+    let (sessions, messages) = delete_user(&state.db, user.id, state.deleted_user_messages).await?;
+    for session_id in sessions {
+        state.revocations.mark_revoked(session_id);
+    }
+
+    let policy = match state.deleted_user_messages {
+        DeletedUserMessages::Anonymize => "anonymize",
+        DeletedUserMessages::Delete => "delete",
+    };
+    tracing::info!(user_id = %user.id, messages, policy, "account deleted");
+    insert_audit_event(
+        &state.db,
+        "account_deleted",
+        None,
+        client.ip.as_deref(),
+        json!({ "user_id": user.id, "messages": messages, "policy": policy }),
+    )
+    .await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Everything stored about the caller, as a JSON download.
+pub async fn export_account_handler(
+    State(state): State<AppState>,
+    auth: AuthUser,
+) -> AppResult<impl IntoResponse> {
+    let user = get_user_by_id(&state.db, auth.user_id)
+        .await?
+        .ok_or_else(|| AppError::Unauthorized("user no longer exists".into()))?;
+    let profile = get_user_profile(&state.db, user.id)
+        .await?
+        .ok_or_else(|| AppError::Unauthorized("user no longer exists".into()))?;
+
+    let export = AccountExport {
+        exported_at: Utc::now(),
+        profile,
+        email: user.email,
+        two_factor_enabled: two_factor::is_enabled(&state, user.id).await?,
+        identities: list_user_identities(&state.db, user.id).await?,
+        sessions: list_active_sessions(&state.db, user.id, Utc::now() - state.refresh_token_ttl)
+            .await?,
+        memberships: list_user_memberships(&state.db, user.id).await?,
+        blocked_users: list_blocked_users(&state.db, user.id).await?,
+        messages: list_user_messages(&state.db, user.id).await?,
+    };
+    let disposition = format!("attachment; filename=\"chat-export-{}.json\"", user.id);
+    Ok(([(header::CONTENT_DISPOSITION, disposition)], Json(export)))
+}
@@ -1,3 +1,7 @@
+pub mod attachment_handlers;
 pub mod auth_handlers;
+pub mod me_handlers;
+pub mod oidc_handlers;
 pub mod room_handlers;
-
+pub mod user_handlers;
+pub mod ws_handlers;
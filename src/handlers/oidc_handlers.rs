@@ -0,0 +1,109 @@
+use axum::{
+    extract::State,
+    http::{header, HeaderMap},
+    response::{IntoResponse, Redirect, Response},
+};
+
+use crate::{
+    auth::{
+        oidc::{self, BROWSER_COOKIE},
+        session::ClientInfo,
+    },
+    db::users::get_user_by_id,
+    error::{AppError, AppResult},
+    extract::{Json, Path, Query},
+    handlers::auth_handlers::sign_in,
+    models::auth::{LoginOutcome, OidcCallbackQuery, OidcGrantRequest, OidcProviderResponse},
+    state::AppState,
+};
+
+/// Providers to offer on the sign-in screen.
+pub async fn list_oidc_providers(State(state): State<AppState>) -> Json<Vec<OidcProviderResponse>> {
+    let providers = state
+        .oidc
+        .configs()
+        .map(|p| OidcProviderResponse {
+            name: p.name.clone(),
+            display_name: p.display_name.clone(),
+        })
+        .collect();
+    Json(providers)
+}
+
+/// Send the browser to the provider to sign in.
+pub async fn authorize_oidc(
+    State(state): State<AppState>,
+    Path(provider): Path<String>,
+) -> AppResult<Response> {
+    let (url, cookie) = oidc::begin_login(&state, &provider).await?;
+    Ok(([(header::SET_COOKIE, cookie)], Redirect::to(&url)).into_response())
+}
+
+/// The provider's redirect back. Always sends the browser on to the web
+/// client, with `#oidc_grant=<grant>` on success and `#oidc_error=<reason>`
+/// otherwise.
+pub async fn oidc_callback(
+    State(state): State<AppState>,
+    Path(provider): Path<String>,
+    Query(query): Query<OidcCallbackQuery>,
+    headers: HeaderMap,
+) -> Response {
+    let fragment = match (&query.error, &query.code, &query.state) {
+        // Only pass on codes the client knows; the rest is free text.
+        (Some(error), _, _) => {
+            tracing::info!(provider, error, "OIDC provider returned an error");
+            let reason = if error == "access_denied" {
+                "access_denied"
+            } else {
+                "login_failed"
+            };
+            format!("oidc_error={reason}")
+        }
+        (None, Some(code), Some(login_state)) => {
+            let browser = cookie(&headers, BROWSER_COOKIE);
+            match oidc::complete_login(&state, &provider, code, login_state, browser).await {
+                Ok(grant) => format!("oidc_grant={grant}"),
+                Err(err) => {
+                    tracing::warn!(provider, "OIDC sign-in failed: {err}");
+                    "oidc_error=login_failed".to_string()
+                }
+            }
+        }
+        _ => "oidc_error=login_failed".to_string(),
+    };
+
+    let target = format!("{}#{fragment}", state.oidc.login_redirect_url());
+    (
+        [(header::SET_COOKIE, oidc::clear_browser_cookie())],
+        Redirect::to(&target),
+    )
+        .into_response()
+}
+
+/// Exchange the grant from a provider sign-in for tokens, or a 2FA challenge
+/// if the account has 2FA enabled.
+pub async fn exchange_oidc_grant(
+    State(state): State<AppState>,
+    client: ClientInfo,
+    Json(payload): Json<OidcGrantRequest>,
+) -> AppResult<Json<LoginOutcome>> {
+    let user_id = oidc::redeem_grant(&state, &payload.grant).await?;
+    let user = get_user_by_id(&state.db, user_id)
+        .await?
+        .ok_or_else(|| AppError::Unauthorized("invalid credentials".into()))?;
+
+    sign_in(&state, user.id, user.username, &client)
+        .await
+        .map(Json)
+}
+
+fn cookie<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|h| h.to_str().ok())
+        .flat_map(|h| h.split(';'))
+        .filter_map(|pair| pair.trim().split_once('='))
+        .find(|(key, _)| *key == name)
+        .map(|(_, value)| value)
+}
@@ -1,7 +1,4 @@
-use axum::{
-    extract::{Path, Query, State},
-    Json,
-};
+use axum::extract::State;
 use serde::Deserialize;
 use uuid::Uuid;
 
@@ -12,6 +9,7 @@ use crate::{
         rooms::{create_room, get_room_if_member, list_rooms_for_user},
     },
     error::{AppError, AppResult},
+    extract::{Json, Path, Query},
     models::room::{CreateRoomRequest, Room},
     state::AppState,
 };
@@ -41,7 +39,7 @@ pub async fn create_room_handler(
     Json(payload): Json<CreateRoomRequest>,
 ) -> AppResult<Json<Room>> {
     if payload.name.trim().is_empty() {
-        return Err(AppError::BadRequest("room name must not be empty".into()));
+        return Err(AppError::validation("name", "room name must not be empty"));
     }
 
     let room = create_room(&state.db, auth.user_id, &payload.name).await?;
@@ -87,9 +85,23 @@ pub async fn list_room_messages_handler(
 
     let limit = q.limit.clamp(1, 100);
     let messages = match q.before {
-        Some(before) => list_messages_before(&state.db, room_id, before, limit).await?,
-        None => list_recent_messages_with_usernames(&state.db, room_id, limit).await?,
+        Some(before) => {
+            list_messages_before(&state.db, room_id, auth.user_id, before, limit).await?
+        }
+        None => list_recent_messages_with_usernames(&state.db, room_id, auth.user_id, limit).await?,
     };
     Ok(Json(messages))
 }
 
+
+pub async fn list_online_users_handler(
+    State(state): State<AppState>,
+    Path(room_id): Path<Uuid>,
+    auth: AuthUser,
+) -> AppResult<Json<Vec<Uuid>>> {
+    let _room = get_room_if_member(&state.db, room_id, auth.user_id)
+        .await?
+        .ok_or_else(|| AppError::NotFound("room not found".into()))?;
+
+    Ok(Json(state.broadcaster.online_users(room_id).await))
+}
@@ -0,0 +1,72 @@
+use axum::extract::State;
+use serde::Deserialize;
+use uuid::Uuid;
+
+use crate::{
+    auth::extractor::AuthUser,
+    db::{
+        blocks::has_blocked,
+        users::{get_user_profile, search_users},
+    },
+    error::{AppError, AppResult},
+    extract::{Json, Path, Query},
+    models::user::{UserProfile, UserSummary},
+    state::AppState,
+    validation::normalize_username,
+};
+
+/// Longest search prefix accepted.
+const MAX_QUERY_LEN: usize = 64;
+
+#[derive(Debug, Deserialize)]
+pub struct SearchUsersQuery {
+    #[serde(default)]
+    pub q: String,
+    /// Username of the last result of the previous page.
+    pub after: Option<String>,
+    #[serde(default = "default_limit")]
+    pub limit: i64,
+}
+
+fn default_limit() -> i64 {
+    20
+}
+
+/// Find users by username or display name prefix.
+pub async fn search_users_handler(
+    State(state): State<AppState>,
+    Query(q): Query<SearchUsersQuery>,
+    auth: AuthUser,
+) -> AppResult<Json<Vec<UserSummary>>> {
+    let prefix = normalize_username(&q.q);
+    if prefix.is_empty() {
+        return Err(AppError::validation("q", "q must not be empty"));
+    }
+    if prefix.chars().count() > MAX_QUERY_LEN {
+        return Err(AppError::validation(
+            "q",
+            format!("q must be at most {MAX_QUERY_LEN} characters"),
+        ));
+    }
+
+    let limit = q.limit.clamp(1, 50);
+    let users = search_users(&state.db, auth.user_id, &prefix, q.after.as_deref(), limit).await?;
+    Ok(Json(users))
+}
+
+/// Another user's public profile. Users who have blocked the caller are not
+/// found.
+pub async fn get_user_handler(
+    State(state): State<AppState>,
+    Path(user_id): Path<Uuid>,
+    auth: AuthUser,
+) -> AppResult<Json<UserProfile>> {
+    let not_found = || AppError::NotFound("user not found".into());
+    if has_blocked(&state.db, user_id, auth.user_id).await? {
+        return Err(not_found());
+    }
+    let profile = get_user_profile(&state.db, user_id)
+        .await?
+        .ok_or_else(not_found)?;
+    Ok(Json(profile))
+}
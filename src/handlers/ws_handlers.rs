@@ -0,0 +1,37 @@
+use axum::extract::State;
+
+use crate::{
+    auth::{
+        extractor::AuthUser,
+        ws_ticket::{issue_ticket, WS_TICKET_TTL},
+    },
+    db::rooms::get_room_if_member,
+    error::{AppError, AppResult},
+    extract::Json,
+    models::auth::{WsTicketRequest, WsTicketResponse},
+    state::AppState,
+};
+
+/// Trade the caller's access token for a short-lived, single-use ticket to
+/// open a WebSocket with. The body is optional.
+pub async fn create_ws_ticket(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    payload: Option<Json<WsTicketRequest>>,
+) -> AppResult<Json<WsTicketResponse>> {
+    let Json(payload) = payload.unwrap_or_default();
+    if let Some(room_id) = payload.room_id {
+        if get_room_if_member(&state.db, room_id, auth.user_id)
+            .await?
+            .is_none()
+        {
+            return Err(AppError::NotFound("room not found".into()));
+        }
+    }
+
+    let ticket = issue_ticket(&state, &auth, payload.room_id).await?;
+    Ok(Json(WsTicketResponse {
+        ticket,
+        expires_in: WS_TICKET_TTL.num_seconds(),
+    }))
+}
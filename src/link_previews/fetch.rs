@@ -0,0 +1,388 @@
+//! HTTP client for link previews that only reaches the public internet.
+//!
+//! The URL comes from a chat message, so without care the server could be
+//! made to request internal services on a user's behalf (SSRF). Every
+//! address the client connects to must be public: host names are checked
+//! after resolution, in the client's own resolver, so a name cannot pass a
+//! check and then resolve elsewhere at connect time, and IP literals are
+//! checked before the request and on every redirect. Proxies from the
+//! environment are ignored, since they would do the resolving instead.
+
+use std::{
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    sync::Arc,
+    time::Duration,
+};
+
+use anyhow::{bail, Context};
+use reqwest::{
+    dns::{Addrs, Name, Resolve, Resolving},
+    header, redirect, Url,
+};
+
+/// Redirects followed before giving up.
+const MAX_REDIRECTS: usize = 3;
+
+const USER_AGENT: &str = concat!(
+    "axum-chat-service/",
+    env!("CARGO_PKG_VERSION"),
+    " (link preview)"
+);
+
+/// A page fetched for a preview: its final URL and the first bytes of its
+/// HTML.
+pub struct Page {
+    pub url: Url,
+    pub html: String,
+}
+
+/// Which addresses may be connected to.
+type AddressFilter = fn(IpAddr) -> bool;
+
+pub struct Fetcher {
+    http: reqwest::Client,
+    max_bytes: usize,
+    allowed: AddressFilter,
+}
+
+impl Fetcher {
+    pub fn new(timeout: Duration, max_bytes: usize, allow_private: bool) -> anyhow::Result<Self> {
+        let allowed: AddressFilter = if allow_private { |_| true } else { is_public };
+        Self::with_filter(timeout, max_bytes, allowed)
+    }
+
+    fn with_filter(
+        timeout: Duration,
+        max_bytes: usize,
+        allowed: AddressFilter,
+    ) -> anyhow::Result<Self> {
+        let http = reqwest::Client::builder()
+            .timeout(timeout)
+            .connect_timeout(timeout.min(Duration::from_secs(3)))
+            .no_proxy()
+            .user_agent(USER_AGENT)
+            .dns_resolver(Arc::new(PublicResolver { allowed }))
+            .redirect(redirect::Policy::custom(move |attempt| {
+                if attempt.previous().len() > MAX_REDIRECTS {
+                    attempt.error("too many redirects")
+                } else if let Err(err) = check_url(attempt.url(), allowed) {
+                    attempt.error(err)
+                } else {
+                    attempt.follow()
+                }
+            }))
+            .build()
+            .context("failed to build HTTP client for link previews")?;
+
+        Ok(Self {
+            http,
+            max_bytes,
+            allowed,
+        })
+    }
+
+    /// Fetch `url` if it is an HTML page, reading at most `max_bytes` of it.
+    pub async fn fetch(&self, url: &Url) -> anyhow::Result<Page> {
+        check_url(url, self.allowed).map_err(anyhow::Error::msg)?;
+
+        let mut response = self
+            .http
+            .get(url.clone())
+            .header(header::ACCEPT, "text/html,application/xhtml+xml")
+            .send()
+            .await?
+            .error_for_status()?;
+        let content_type = response
+            .headers()
+            .get(header::CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or_default()
+            .to_ascii_lowercase();
+        if !(content_type.starts_with("text/html")
+            || content_type.starts_with("application/xhtml+xml"))
+        {
+            bail!("not an HTML page ({content_type:?})");
+        }
+
+        let mut body = Vec::new();
+        while body.len() < self.max_bytes {
+            let Some(chunk) = response.chunk().await? else {
+                break;
+            };
+            body.extend_from_slice(&chunk);
+        }
+        body.truncate(self.max_bytes);
+
+        Ok(Page {
+            url: response.url().clone(),
+            html: String::from_utf8_lossy(&body).into_owned(),
+        })
+    }
+}
+
+/// Refuse anything but http(s) URLs, and IP literals that are not allowed.
+/// Host names are checked when they are resolved (see [`PublicResolver`]).
+fn check_url(url: &Url, allowed: AddressFilter) -> Result<(), &'static str> {
+    if !matches!(url.scheme(), "http" | "https") {
+        return Err("only http and https links are fetched");
+    }
+    let Some(host) = url.host_str() else {
+        return Err("link has no host");
+    };
+    // `Url` normalises IPv4 hosts to dotted decimal and brackets IPv6 ones.
+    let Ok(ip) = host
+        .trim_start_matches('[')
+        .trim_end_matches(']')
+        .parse::<IpAddr>()
+    else {
+        return Ok(());
+    };
+    if allowed(ip) {
+        Ok(())
+    } else {
+        Err("link points to a non-public address")
+    }
+}
+
+/// Resolves host names with the system resolver and drops every address that
+/// is not allowed (not public, unless private ones are); a name left with
+/// none fails to resolve.
+struct PublicResolver {
+    allowed: AddressFilter,
+}
+
+impl Resolve for PublicResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        let allowed = self.allowed;
+        Box::pin(async move {
+            let addrs: Vec<SocketAddr> = tokio::net::lookup_host((name.as_str(), 0))
+                .await?
+                .filter(|addr| allowed(addr.ip()))
+                .collect();
+            if addrs.is_empty() {
+                return Err(format!("{} has no public address", name.as_str()).into());
+            }
+            let addrs: Addrs = Box::new(addrs.into_iter());
+            Ok(addrs)
+        })
+    }
+}
+
+/// Whether `ip` is globally routable: not loopback, private, link-local,
+/// shared (CGNAT), multicast, reserved or set aside for documentation.
+fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => is_public_v4(ip),
+        IpAddr::V6(ip) => is_public_v6(ip),
+    }
+}
+
+fn is_public_v4(ip: Ipv4Addr) -> bool {
+    let [a, b, c, _] = ip.octets();
+    !(a == 0
+        || a == 10
+        || a == 127
+        || (a == 100 && (64..128).contains(&b))
+        || (a == 169 && b == 254)
+        || (a == 172 && (16..32).contains(&b))
+        || (a == 192 && b == 0 && (c == 0 || c == 2))
+        || (a == 192 && b == 168)
+        || (a == 198 && (b == 18 || b == 19))
+        || (a == 198 && b == 51 && c == 100)
+        || (a == 203 && b == 0 && c == 113)
+        || a >= 224)
+}
+
+fn is_public_v6(ip: Ipv6Addr) -> bool {
+    if let Some(v4) = ip.to_ipv4_mapped() {
+        return is_public_v4(v4);
+    }
+    let s = ip.segments();
+    !(ip.is_unspecified()
+        || ip.is_loopback()
+        // IPv4-compatible (deprecated) and NAT64 addresses embed an IPv4 one.
+        || (s[..6] == [0; 6])
+        || (s[0] == 0x64 && s[1] == 0xff9b && s[2..6] == [0; 4])
+        // Discard-only.
+        || (s[0] == 0x100 && s[1..4] == [0; 3])
+        || (s[0] == 0x2001 && s[1] == 0xdb8)
+        || (s[0] & 0xfe00) == 0xfc00
+        || (s[0] & 0xffc0) == 0xfe80
+        || (s[0] & 0xff00) == 0xff00)
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+    };
+
+    use super::*;
+
+    fn v4(ip: &str) -> bool {
+        is_public_v4(ip.parse().unwrap())
+    }
+
+    fn v6(ip: &str) -> bool {
+        is_public_v6(ip.parse().unwrap())
+    }
+
+    fn check(url: &str) -> Result<(), &'static str> {
+        check_url(&Url::parse(url).unwrap(), is_public)
+    }
+
+    #[test]
+    fn refuses_non_public_ipv4() {
+        for ip in [
+            "0.0.0.0",
+            "0.1.2.3",
+            "10.1.2.3",
+            "127.0.0.1",
+            "127.255.255.254",
+            "100.64.0.1",
+            "100.127.255.255",
+            "169.254.169.254",
+            "172.16.0.1",
+            "172.31.255.255",
+            "192.168.1.1",
+            "192.0.0.8",
+            "192.0.2.1",
+            "198.18.0.1",
+            "198.51.100.7",
+            "203.0.113.9",
+            "224.0.0.1",
+            "240.0.0.1",
+            "255.255.255.255",
+        ] {
+            assert!(!v4(ip), "{ip}");
+        }
+        for ip in [
+            "1.1.1.1",
+            "8.8.8.8",
+            "100.63.255.255",
+            "100.128.0.1",
+            "172.15.255.255",
+            "172.32.0.1",
+            "192.169.0.1",
+            "93.184.216.34",
+        ] {
+            assert!(v4(ip), "{ip}");
+        }
+    }
+
+    #[test]
+    fn refuses_non_public_ipv6() {
+        for ip in [
+            "::",
+            "::1",
+            "fc00::1",
+            "fd12:3456:789a::1",
+            "fe80::1",
+            "febf::1",
+            "ff02::1",
+            "2001:db8::1",
+            "100::1",
+            // IPv4-mapped and -compatible forms of private addresses.
+            "::ffff:127.0.0.1",
+            "::ffff:10.0.0.1",
+            "::ffff:169.254.169.254",
+            "::127.0.0.1",
+            // NAT64 reaches IPv4 through a gateway; refused whatever it embeds.
+            "64:ff9b::a00:1",
+            "64:ff9b::808:808",
+        ] {
+            assert!(!v6(ip), "{ip}");
+        }
+        for ip in [
+            "2606:4700:4700::1111",
+            "2001:4860:4860::8888",
+            "::ffff:8.8.8.8",
+        ] {
+            assert!(v6(ip), "{ip}");
+        }
+    }
+
+    #[test]
+    fn checks_scheme_and_ip_literals() {
+        assert!(check("http://example.com/").is_ok());
+        assert!(check("https://8.8.8.8/page").is_ok());
+        assert!(check("https://[2606:4700:4700::1111]/").is_ok());
+
+        for url in [
+            "ftp://example.com/",
+            "file:///etc/passwd",
+            "http://127.0.0.1/",
+            "http://0.0.0.0:8080/",
+            "http://10.0.0.1/",
+            "http://169.254.169.254/latest/meta-data/",
+            "http://[::1]/",
+            "http://[::ffff:127.0.0.1]/",
+            "http://[fd00::1]/",
+            // Other spellings of 127.0.0.1, normalised by the URL parser.
+            "http://2130706433/",
+            "http://0x7f.1/",
+        ] {
+            assert!(check(url).is_err(), "{url}");
+        }
+
+        let loopback = Url::parse("http://127.0.0.1/").unwrap();
+        assert!(check_url(&loopback, |_| true).is_ok());
+    }
+
+    #[tokio::test]
+    async fn resolver_drops_non_public_addresses() {
+        let resolver = PublicResolver { allowed: is_public };
+        let name: Name = "localhost".parse().unwrap();
+        assert!(resolver.resolve(name).await.is_err());
+    }
+
+    /// Serve one canned HTTP response per connection on loopback, closing the
+    /// connection after it.
+    async fn serve(response: &'static str) -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let mut request = [0; 4096];
+                let _ = stream.read(&mut request).await;
+                let _ = stream.write_all(response.as_bytes()).await;
+            }
+        });
+        addr
+    }
+
+    /// A fetcher that may reach only 127.0.0.1, where the test servers run.
+    fn loopback_fetcher() -> Fetcher {
+        let allowed: AddressFilter = |ip| ip == IpAddr::V4(Ipv4Addr::LOCALHOST);
+        Fetcher::with_filter(Duration::from_secs(5), 1 << 16, allowed).unwrap()
+    }
+
+    #[tokio::test]
+    async fn refuses_redirect_to_private_ip_literal() {
+        let page = serve(
+            "HTTP/1.1 200 OK\r\nContent-Type: text/html\r\nConnection: close\r\n\r\n\
+             <title>Reachable</title>",
+        )
+        .await;
+        let redirect = serve(
+            "HTTP/1.1 302 Found\r\nLocation: http://169.254.169.254/latest/meta-data/\r\n\
+             Content-Length: 0\r\n\r\n",
+        )
+        .await;
+        let fetcher = loopback_fetcher();
+
+        let fetched = fetcher
+            .fetch(&Url::parse(&format!("http://{page}/")).unwrap())
+            .await
+            .unwrap();
+        assert!(fetched.html.contains("Reachable"));
+
+        let err = fetcher
+            .fetch(&Url::parse(&format!("http://{redirect}/")).unwrap())
+            .await
+            .err()
+            .unwrap();
+        assert!(format!("{err:#}").contains("non-public address"), "{err:#}");
+    }
+}
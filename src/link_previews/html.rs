@@ -0,0 +1,313 @@
+//! Pulls preview metadata out of the head of an HTML page.
+//!
+//! Pages are often malformed and only their beginning is read, so this is a
+//! forgiving tag scanner rather than a parser: it looks at `<meta>` and
+//! `<title>` tags, skips comments, scripts and styles, and ignores everything
+//! else.
+
+use reqwest::Url;
+
+use crate::models::link_preview::LinkPreview;
+
+const MAX_TITLE_CHARS: usize = 300;
+const MAX_DESCRIPTION_CHARS: usize = 1000;
+const MAX_SITE_NAME_CHARS: usize = 100;
+const MAX_IMAGE_URL_LEN: usize = 2048;
+
+/// The preview of the page at `page_url` (where it was fetched from, after
+/// redirects), stored under `url` (the link as posted).
+pub fn extract_preview(url: &str, page_url: &Url, html: &str) -> LinkPreview {
+    let mut og_title = None;
+    let mut og_description = None;
+    let mut og_image = None;
+    let mut og_site_name = None;
+    let mut title = None;
+    let mut description = None;
+
+    let mut rest = html;
+    while let Some(start) = rest.find('<') {
+        rest = &rest[start..];
+        if let Some(comment) = rest.strip_prefix("<!--") {
+            rest = comment.find("-->").map_or("", |end| &comment[end + 3..]);
+            continue;
+        }
+        let Some(end) = rest.find('>') else {
+            break;
+        };
+        let tag = &rest[1..end];
+        rest = &rest[end + 1..];
+
+        // A closing tag's name starts with its '/'.
+        let name_len = tag
+            .char_indices()
+            .skip(1)
+            .find(|&(_, c)| c.is_ascii_whitespace() || c == '/')
+            .map_or(tag.len(), |(i, _)| i);
+        let name = tag[..name_len].to_ascii_lowercase();
+        match name.as_str() {
+            "meta" => {
+                let attrs = attributes(&tag[name_len..]);
+                let attr = |wanted: &str| {
+                    attrs
+                        .iter()
+                        .find(|(name, _)| name.eq_ignore_ascii_case(wanted))
+                        .map(|(_, value)| value.as_str())
+                };
+                let Some(content) = attr("content") else {
+                    continue;
+                };
+                let key = attr("property")
+                    .or_else(|| attr("name"))
+                    .unwrap_or_default()
+                    .to_ascii_lowercase();
+                let slot = match key.as_str() {
+                    "og:title" => &mut og_title,
+                    "og:description" => &mut og_description,
+                    "og:image" | "og:image:url" | "og:image:secure_url" => &mut og_image,
+                    "og:site_name" => &mut og_site_name,
+                    "description" => &mut description,
+                    _ => continue,
+                };
+                if slot.is_none() {
+                    *slot = Some(content.to_string());
+                }
+            }
+            "title" => {
+                let end = find_ignore_case(rest, "</title").unwrap_or(rest.len());
+                if title.is_none() {
+                    title = Some(decode_entities(&rest[..end]));
+                }
+                rest = &rest[end..];
+            }
+            "script" | "style" => {
+                let close = format!("</{name}");
+                rest = find_ignore_case(rest, &close).map_or("", |end| &rest[end..]);
+            }
+            // Nothing of interest comes after the head.
+            "body" | "/head" => break,
+            _ => {}
+        }
+    }
+
+    LinkPreview {
+        url: url.to_string(),
+        title: clean(og_title.or(title), MAX_TITLE_CHARS),
+        description: clean(og_description.or(description), MAX_DESCRIPTION_CHARS),
+        image_url: og_image
+            .and_then(|image| page_url.join(image.trim()).ok())
+            .filter(|image| matches!(image.scheme(), "http" | "https"))
+            .map(String::from)
+            .filter(|image| image.len() <= MAX_IMAGE_URL_LEN),
+        site_name: clean(og_site_name, MAX_SITE_NAME_CHARS),
+    }
+}
+
+/// The attributes of a tag, values entity-decoded. Accepts double-quoted,
+/// single-quoted and unquoted values.
+fn attributes(mut s: &str) -> Vec<(String, String)> {
+    let mut attrs = Vec::new();
+    loop {
+        s = s.trim_start_matches(|c: char| c.is_ascii_whitespace() || c == '/');
+        if s.is_empty() {
+            return attrs;
+        }
+        let name_len = s
+            .find(|c: char| c.is_ascii_whitespace() || c == '=' || c == '/')
+            .unwrap_or(s.len());
+        let name = s[..name_len].to_string();
+        s = s[name_len..].trim_start();
+        let Some(after_eq) = s.strip_prefix('=') else {
+            attrs.push((name, String::new()));
+            continue;
+        };
+        s = after_eq.trim_start();
+        let value = match s.chars().next() {
+            Some(quote @ ('"' | '\'')) => {
+                let end = s[1..].find(quote).map_or(s.len(), |end| end + 1);
+                let value = &s[1..end];
+                s = s.get(end + 1..).unwrap_or_default();
+                value
+            }
+            _ => {
+                let end = s.find(|c: char| c.is_ascii_whitespace()).unwrap_or(s.len());
+                let value = &s[..end];
+                s = &s[end..];
+                value
+            }
+        };
+        attrs.push((name, decode_entities(value)));
+    }
+}
+
+fn find_ignore_case(haystack: &str, needle: &str) -> Option<usize> {
+    haystack
+        .as_bytes()
+        .windows(needle.len())
+        .position(|window| window.eq_ignore_ascii_case(needle.as_bytes()))
+}
+
+/// Decode the character references pages commonly use in titles and
+/// descriptions; anything unrecognised is left as written.
+fn decode_entities(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    let mut rest = s;
+    while let Some(amp) = rest.find('&') {
+        out.push_str(&rest[..amp]);
+        rest = &rest[amp..];
+        let decoded = rest[1..]
+            .find(';')
+            .filter(|&end| end <= 10)
+            .and_then(|end| {
+                let entity = &rest[1..end + 1];
+                let c = match entity {
+                    "amp" => Some('&'),
+                    "lt" => Some('<'),
+                    "gt" => Some('>'),
+                    "quot" => Some('"'),
+                    "apos" => Some('\''),
+                    "nbsp" => Some(' '),
+                    _ => entity
+                        .strip_prefix("#x")
+                        .or_else(|| entity.strip_prefix("#X"))
+                        .map(|hex| u32::from_str_radix(hex, 16))
+                        .or_else(|| entity.strip_prefix('#').map(str::parse))
+                        .and_then(Result::ok)
+                        .and_then(char::from_u32),
+                };
+                c.map(|c| (c, end + 2))
+            });
+        match decoded {
+            Some((c, len)) => {
+                out.push(c);
+                rest = &rest[len..];
+            }
+            None => {
+                out.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+/// Collapse whitespace, drop control characters and cap at `max_chars`.
+/// `None` if nothing is left.
+fn clean(value: Option<String>, max_chars: usize) -> Option<String> {
+    let value = value?;
+    let mut out = String::new();
+    for word in value.split_whitespace() {
+        if !out.is_empty() {
+            out.push(' ');
+        }
+        out.extend(word.chars().filter(|c| !c.is_control()));
+    }
+    if out.chars().count() > max_chars {
+        out = out
+            .chars()
+            .take(max_chars - 1)
+            .collect::<String>()
+            .trim_end()
+            .to_string();
+        out.push('…');
+    }
+    (!out.is_empty()).then_some(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn preview(html: &str) -> LinkPreview {
+        let page_url = Url::parse("https://example.com/blog/post?id=1").unwrap();
+        extract_preview("https://example.com/p", &page_url, html)
+    }
+
+    #[test]
+    fn prefers_open_graph_tags() {
+        let preview = preview(
+            r#"<html><head>
+            <title>Page title</title>
+            <meta name="description" content="Plain description">
+            <meta property="og:title" content="OG title">
+            <meta property="og:description" content="OG description">
+            <meta property="og:site_name" content="Example">
+            <meta property="og:image" content="https://cdn.example.com/a.png">
+            </head><body></body></html>"#,
+        );
+        assert_eq!(preview.url, "https://example.com/p");
+        assert_eq!(preview.title.as_deref(), Some("OG title"));
+        assert_eq!(preview.description.as_deref(), Some("OG description"));
+        assert_eq!(preview.site_name.as_deref(), Some("Example"));
+        assert_eq!(
+            preview.image_url.as_deref(),
+            Some("https://cdn.example.com/a.png")
+        );
+    }
+
+    #[test]
+    fn falls_back_to_title_and_description() {
+        let preview = preview(
+            "<HTML><HEAD><TITLE>\n  Plain   title\n</TITLE>\
+             <META NAME='Description' CONTENT=Short></HEAD></HTML>",
+        );
+        assert_eq!(preview.title.as_deref(), Some("Plain title"));
+        assert_eq!(preview.description.as_deref(), Some("Short"));
+        assert_eq!(preview.image_url, None);
+        assert_eq!(preview.site_name, None);
+    }
+
+    #[test]
+    fn decodes_entities() {
+        let preview = preview(
+            r#"<title>Tom &amp; Jerry&#39;s &lt;b&gt; &#x1F600; &bogus; & more</title>
+            <meta property="og:description" content="&quot;Quoted&quot;&nbsp;text">"#,
+        );
+        assert_eq!(
+            preview.title.as_deref(),
+            Some("Tom & Jerry's <b> 😀 &bogus; & more")
+        );
+        assert_eq!(preview.description.as_deref(), Some("\"Quoted\" text"));
+    }
+
+    #[test]
+    fn resolves_relative_images_against_the_page() {
+        let image = |src: &str| {
+            preview(&format!(r#"<meta property="og:image" content="{src}">"#)).image_url
+        };
+        assert_eq!(
+            image("/img/a.png").as_deref(),
+            Some("https://example.com/img/a.png")
+        );
+        assert_eq!(
+            image("b.png").as_deref(),
+            Some("https://example.com/blog/b.png")
+        );
+        assert_eq!(
+            image("//cdn.example.com/c.png").as_deref(),
+            Some("https://cdn.example.com/c.png")
+        );
+        assert_eq!(image("javascript:alert(1)"), None);
+        assert_eq!(image("data:image/png;base64,AAAA"), None);
+    }
+
+    #[test]
+    fn ignores_comments_scripts_and_the_body() {
+        let preview = preview(
+            r#"<!-- <title>Commented</title> -->
+            <script>document.write("<title>Scripted</title>")</script>
+            <title>Real</title></head>
+            <meta property="og:title" content="In the body">"#,
+        );
+        assert_eq!(preview.title.as_deref(), Some("Real"));
+    }
+
+    #[test]
+    fn caps_long_values() {
+        let preview = preview(&format!("<title>{}</title>", "word ".repeat(200)));
+        let title = preview.title.unwrap();
+        assert_eq!(title.chars().count(), MAX_TITLE_CHARS);
+        assert!(title.ends_with("word…"));
+        assert!(self::preview("<title> \n </title>").title.is_none());
+    }
+}
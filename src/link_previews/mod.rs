@@ -0,0 +1,293 @@
+//! Previews of links posted in messages.
+//!
+//! After a message is stored and broadcast, the first few http(s) links in it
+//! are looked up in the background: from the cache if they were fetched
+//! recently, otherwise over HTTP through a client that refuses non-public
+//! addresses ([`fetch`]). Pages that yield a title, description or image are
+//! attached to the message, which is then broadcast again as a `preview`
+//! event. Fetches never hold up sending the message itself.
+
+use std::{sync::Arc, time::Duration};
+
+use chrono::Utc;
+use reqwest::Url;
+use tokio::{sync::Semaphore, task::JoinSet};
+use uuid::Uuid;
+
+use crate::{
+    config::Config,
+    db::{
+        link_previews::{get_cached_preview, link_previews, upsert_preview},
+        messages::get_message_with_username,
+    },
+    error::AppResult,
+    models::{
+        link_preview::LinkPreview,
+        message::{OutgoingWsMessage, WsMessageKind},
+    },
+    state::AppState,
+};
+
+mod fetch;
+mod html;
+
+/// Links previewed per message.
+const MAX_LINKS_PER_MESSAGE: usize = 3;
+
+/// Longest link considered, in bytes.
+const MAX_URL_LEN: usize = 2048;
+
+/// Fetches in flight across the instance; further ones wait for a slot.
+const MAX_CONCURRENT_FETCHES: usize = 16;
+
+/// How long a fetched preview is reused.
+const CACHE_TTL: chrono::Duration = chrono::Duration::hours(24);
+
+/// How long a failed fetch is remembered before the link is tried again.
+const FAILURE_TTL: chrono::Duration = chrono::Duration::hours(1);
+
+/// Handle to the preview fetcher; disabled when `LINK_PREVIEWS` is off.
+#[derive(Clone)]
+pub struct LinkPreviews {
+    inner: Option<Arc<Inner>>,
+}
+
+struct Inner {
+    fetcher: fetch::Fetcher,
+    slots: Semaphore,
+    timeout: Duration,
+}
+
+impl LinkPreviews {
+    pub fn from_config(config: &Config) -> anyhow::Result<Self> {
+        if !config.link_previews {
+            return Ok(Self { inner: None });
+        }
+        if config.link_preview_allow_private {
+            tracing::warn!(
+                "LINK_PREVIEW_ALLOW_PRIVATE is set: link previews may fetch internal addresses"
+            );
+        }
+        let timeout = Duration::from_secs(config.link_preview_timeout_secs);
+        let fetcher = fetch::Fetcher::new(
+            timeout,
+            config.link_preview_max_bytes,
+            config.link_preview_allow_private,
+        )?;
+
+        Ok(Self {
+            inner: Some(Arc::new(Inner {
+                fetcher,
+                slots: Semaphore::new(MAX_CONCURRENT_FETCHES),
+                timeout,
+            })),
+        })
+    }
+}
+
+/// Look up previews for the links in a just-sent message and, if any are
+/// found, broadcast the message again with them.
+pub fn spawn_link_previews(state: &AppState, message_id: Uuid, content: &str) {
+    if state.link_previews.inner.is_none() {
+        return;
+    }
+    let urls = extract_urls(content);
+    if urls.is_empty() {
+        return;
+    }
+    let state = state.clone();
+    tokio::spawn(async move {
+        if let Err(err) = attach_previews(&state, message_id, urls).await {
+            tracing::warn!(%message_id, "failed to attach link previews: {err}");
+        }
+    });
+}
+
+async fn attach_previews(state: &AppState, message_id: Uuid, urls: Vec<Url>) -> AppResult<()> {
+    let mut lookups = JoinSet::new();
+    for (position, url) in urls.into_iter().enumerate() {
+        let state = state.clone();
+        lookups.spawn(async move { (position, preview(&state, &url).await) });
+    }
+    let mut found = Vec::new();
+    while let Some(result) = lookups.join_next().await {
+        if let Ok((position, Some(preview))) = result {
+            if !preview.is_empty() {
+                found.push((position, preview.url));
+            }
+        }
+    }
+    found.sort_unstable();
+    let found: Vec<String> = found.into_iter().map(|(_, url)| url).collect();
+    if found.is_empty() || !link_previews(&state.db, message_id, &found).await? {
+        return Ok(());
+    }
+
+    if let Some(message) = get_message_with_username(&state.db, message_id).await? {
+        state
+            .broadcaster
+            .publish(OutgoingWsMessage::stored(message, WsMessageKind::Preview))
+            .await;
+    }
+    Ok(())
+}
+
+/// The preview of `url`, cached or freshly fetched. `None` if it could not
+/// be looked up right now (database error, or no fetch slot in time).
+async fn preview(state: &AppState, url: &Url) -> Option<LinkPreview> {
+    let inner = state.link_previews.inner.as_ref()?;
+    let now = Utc::now();
+    match get_cached_preview(&state.db, url.as_str(), now - CACHE_TTL, now - FAILURE_TTL).await {
+        Ok(Some(cached)) => return Some(cached),
+        Ok(None) => {}
+        Err(err) => {
+            tracing::warn!("failed to read link preview cache: {err}");
+            return None;
+        }
+    }
+
+    let _slot = tokio::time::timeout(inner.timeout, inner.slots.acquire())
+        .await
+        .ok()?
+        .ok()?;
+    let preview = match inner.fetcher.fetch(url).await {
+        Ok(page) => html::extract_preview(url.as_str(), &page.url, &page.html),
+        Err(err) => {
+            tracing::debug!(%url, "link preview fetch failed: {err:#}");
+            LinkPreview {
+                url: url.to_string(),
+                title: None,
+                description: None,
+                image_url: None,
+                site_name: None,
+            }
+        }
+    };
+    if let Err(err) = upsert_preview(&state.db, &preview).await {
+        tracing::warn!("failed to cache link preview: {err}");
+    }
+    Some(preview)
+}
+
+/// The distinct http(s) links in `content`, in order, at most
+/// [`MAX_LINKS_PER_MESSAGE`]. Punctuation right after a link (as in "see
+/// https://example.com.") is not part of it; fragments are dropped since they
+/// do not change the page.
+fn extract_urls(content: &str) -> Vec<Url> {
+    let mut urls: Vec<Url> = Vec::new();
+    for word in content.split_whitespace() {
+        let Some(start) = find_scheme(word) else {
+            continue;
+        };
+        let mut candidate = &word[start..];
+        loop {
+            let trimmed = candidate
+                .trim_end_matches(['.', ',', ';', ':', '!', '?', '\'', '"', '>', ']', '}']);
+            // Keep a closing parenthesis that belongs to the link.
+            let trimmed = match trimmed.strip_suffix(')') {
+                Some(inner) if inner.matches('(').count() < trimmed.matches(')').count() => inner,
+                _ => trimmed,
+            };
+            if trimmed.len() == candidate.len() {
+                break;
+            }
+            candidate = trimmed;
+        }
+        if candidate.len() > MAX_URL_LEN {
+            continue;
+        }
+        let Ok(mut url) = Url::parse(candidate) else {
+            continue;
+        };
+        if url.host_str().is_none() {
+            continue;
+        }
+        url.set_fragment(None);
+        if !urls.contains(&url) {
+            urls.push(url);
+            if urls.len() == MAX_LINKS_PER_MESSAGE {
+                break;
+            }
+        }
+    }
+    urls
+}
+
+/// Where an `http://` or `https://` link starts in `word`, if it contains one.
+fn find_scheme(word: &str) -> Option<usize> {
+    let lower = word.to_ascii_lowercase();
+    lower
+        .find("http://")
+        .into_iter()
+        .chain(lower.find("https://"))
+        .min()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn urls(content: &str) -> Vec<String> {
+        extract_urls(content)
+            .into_iter()
+            .map(String::from)
+            .collect()
+    }
+
+    #[test]
+    fn trims_trailing_punctuation() {
+        assert_eq!(urls("see https://example.com."), ["https://example.com/"]);
+        assert_eq!(
+            urls("https://example.com/a?b=c, then"),
+            ["https://example.com/a?b=c"]
+        );
+        assert_eq!(
+            urls("\"https://example.com/q\"!?"),
+            ["https://example.com/q"]
+        );
+        assert_eq!(urls("<https://example.com/x>"), ["https://example.com/x"]);
+        assert_eq!(urls("[https://example.com/y]."), ["https://example.com/y"]);
+        assert_eq!(
+            urls("it's https://example.com/z';"),
+            ["https://example.com/z"]
+        );
+    }
+
+    #[test]
+    fn keeps_balanced_parentheses() {
+        assert_eq!(
+            urls("(see https://en.wikipedia.org/wiki/Rust_(programming_language))."),
+            ["https://en.wikipedia.org/wiki/Rust_(programming_language)"]
+        );
+        assert_eq!(urls("(https://example.com/a)"), ["https://example.com/a"]);
+    }
+
+    #[test]
+    fn finds_links_inside_words() {
+        assert_eq!(
+            urls("link:https://example.com/a HTTPS://EXAMPLE.COM/B"),
+            ["https://example.com/a", "https://example.com/B"]
+        );
+    }
+
+    #[test]
+    fn dedupes_drops_fragments_and_caps() {
+        assert_eq!(
+            urls(
+                "https://a.example/#top https://a.example/#bottom https://b.example \
+                 https://c.example https://d.example"
+            ),
+            [
+                "https://a.example/",
+                "https://b.example/",
+                "https://c.example/"
+            ]
+        );
+    }
+
+    #[test]
+    fn ignores_non_links() {
+        assert!(urls("ftp://example.com mailto:a@example.com https:// http:").is_empty());
+        assert!(urls(&format!("https://example.com/{}", "a".repeat(MAX_URL_LEN))).is_empty());
+    }
+}
@@ -0,0 +1,77 @@
+//! Outgoing mail (password reset links).
+//!
+//! [`Mailer`] is the extension point for a real delivery backend (SMTP, an
+//! email API). The built-in backends are for local use: one logs each message,
+//! the other writes it to a `.eml` file.
+
+use std::{path::PathBuf, sync::Arc};
+
+use async_trait::async_trait;
+use chrono::Utc;
+use uuid::Uuid;
+
+use crate::config::{Config, MailerKind};
+
+#[derive(Debug, Clone)]
+pub struct Email {
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+#[async_trait]
+pub trait Mailer: Send + Sync {
+    async fn send(&self, email: &Email) -> anyhow::Result<()>;
+}
+
+/// Logs every message, body included. Never use in production: the body
+/// holds secrets such as reset links.
+pub struct LogMailer;
+
+#[async_trait]
+impl Mailer for LogMailer {
+    async fn send(&self, email: &Email) -> anyhow::Result<()> {
+        tracing::info!(to = %email.to, subject = %email.subject, "mail:\n{}", email.body);
+        Ok(())
+    }
+}
+
+/// Writes each message to its own `.eml` file in `dir`.
+pub struct FileMailer {
+    dir: PathBuf,
+    from: String,
+}
+
+#[async_trait]
+impl Mailer for FileMailer {
+    async fn send(&self, email: &Email) -> anyhow::Result<()> {
+        let now = Utc::now();
+        let path = self
+            .dir
+            .join(format!("{}-{}.eml", now.format("%Y%m%dT%H%M%S"), Uuid::new_v4()));
+        let message = format!(
+            "From: {}\r\nTo: {}\r\nSubject: {}\r\nDate: {}\r\nContent-Type: text/plain; charset=utf-8\r\n\r\n{}\r\n",
+            self.from,
+            email.to,
+            email.subject,
+            now.to_rfc2822(),
+            email.body.replace('\n', "\r\n"),
+        );
+        tokio::fs::write(&path, message).await?;
+        tracing::debug!("wrote mail to {}", path.display());
+        Ok(())
+    }
+}
+
+pub fn from_config(config: &Config) -> anyhow::Result<Arc<dyn Mailer>> {
+    Ok(match &config.mailer {
+        MailerKind::Log => Arc::new(LogMailer),
+        MailerKind::File { dir } => {
+            std::fs::create_dir_all(dir)?;
+            Arc::new(FileMailer {
+                dir: dir.into(),
+                from: config.mail_from.clone(),
+            })
+        }
+    })
+}
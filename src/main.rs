@@ -1,3 +1,5 @@
+use std::{net::SocketAddr, time::Duration};
+
 use axum::Router;
 use dotenvy::dotenv;
 use sqlx::postgres::PgPoolOptions;
@@ -5,18 +7,29 @@ use tokio::net::TcpListener;
 use tracing_subscriber::EnvFilter;
 
 mod config;
+mod attachments;
 mod auth;
 mod db;
 mod error;
+mod extract;
 mod handlers;
+mod link_previews;
+mod mailer;
 mod models;
+mod pubsub;
+mod request_id;
 mod routes;
 mod state;
+mod storage;
+mod validation;
 mod websocket;
 
+use crate::attachments::spawn_attachment_sweeper;
+use crate::auth::revocation::{spawn_revocation_sync, RevocationStore};
 use crate::config::Config;
 use crate::routes::create_router;
 use crate::state::AppState;
+use crate::websocket::registry::{spawn_sweeper, RoomRegistry, RoomSettings};
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
@@ -32,12 +45,45 @@ async fn main() -> anyhow::Result<()> {
 
     sqlx::migrate!("./migrations").run(&pool).await?;
 
+    let rooms = RoomRegistry::new(RoomSettings {
+        channel_capacity: config.room_channel_capacity,
+    });
+    let broadcaster = pubsub::connect(&config, &pool, rooms.clone()).await?;
+    let revocations =
+        RevocationStore::load(&pool, chrono::Duration::minutes(config.access_token_ttl_minutes))
+            .await?;
+    spawn_revocation_sync(
+        revocations.clone(),
+        pool.clone(),
+        Duration::from_secs(config.revocation_sync_interval_secs),
+        chrono::Duration::days(config.refresh_token_ttl_days),
+    );
+    let mailer = mailer::from_config(&config)?;
+    let storage = storage::from_config(&config)?;
+    let oidc = auth::oidc::OidcProviders::from_config(&config)?;
+    let link_previews = link_previews::LinkPreviews::from_config(&config)?;
+    let jwt_keys = auth::keys::JwtKeys::from_config(&config)?;
+    let credential_policy = validation::CredentialPolicy::from_config(&config)?;
     let app_state = AppState::new(
         pool,
-        config.jwt_secret,
-        config.jwt_issuer,
-        config.jwt_exp_hours,
+        &config,
+        rooms,
+        broadcaster,
+        revocations,
+        mailer,
+        storage,
+        oidc,
+        link_previews,
+        jwt_keys,
+        credential_policy,
+    );
+
+    spawn_sweeper(
+        app_state.rooms.clone(),
+        Duration::from_secs(config.room_sweep_interval_secs),
+        Duration::from_secs(config.room_idle_timeout_secs),
     );
+    spawn_attachment_sweeper(app_state.clone(), Duration::from_secs(60 * 60));
 
     let allowed_origins: Vec<String> = std::env::var("ALLOWED_ORIGINS")
         .ok()
@@ -49,7 +95,7 @@ async fn main() -> anyhow::Result<()> {
     tracing::info!("listening on {}", config.server_addr);
     let listener = TcpListener::bind(config.server_addr).await?;
 
-    axum::serve(listener, app.into_make_service()).await?;
+    axum::serve(listener, app.into_make_service_with_connect_info::<SocketAddr>()).await?;
 
     Ok(())
 }
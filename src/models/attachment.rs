@@ -0,0 +1,82 @@
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use uuid::Uuid;
+
+/// An uploaded file. It belongs to `message_id` once a message is sent with
+/// it; until then only the uploader can see it.
+#[derive(Debug, Clone, FromRow)]
+pub struct Attachment {
+    pub id: Uuid,
+    pub room_id: Uuid,
+    pub message_id: Option<Uuid>,
+    pub uploader_id: Option<Uuid>,
+    pub filename: String,
+    pub content_type: String,
+    pub size_bytes: i64,
+    pub storage_key: String,
+    /// Pixel size, for images.
+    pub width: Option<i32>,
+    pub height: Option<i32>,
+}
+
+/// A downscaled copy of an image attachment.
+#[derive(Debug, Clone, FromRow)]
+pub struct Thumbnail {
+    pub attachment_id: Uuid,
+    pub size: i32,
+    pub width: i32,
+    pub height: i32,
+    pub content_type: String,
+    pub storage_key: String,
+}
+
+/// What clients see of an attachment. The file itself is served by
+/// `GET /api/attachments/{id}`.
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct AttachmentInfo {
+    pub id: Uuid,
+    pub filename: String,
+    pub content_type: String,
+    pub size_bytes: i64,
+    pub width: Option<i32>,
+    pub height: Option<i32>,
+    /// Loaded separately (see `db::attachments::load_thumbnails`).
+    #[sqlx(skip)]
+    #[serde(default)]
+    pub thumbnails: Vec<ThumbnailInfo>,
+}
+
+/// Served by `GET /api/attachments/{id}/thumbnails/{size}`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ThumbnailInfo {
+    pub size: i32,
+    pub width: i32,
+    pub height: i32,
+}
+
+impl From<Attachment> for AttachmentInfo {
+    fn from(a: Attachment) -> Self {
+        Self {
+            id: a.id,
+            filename: a.filename,
+            content_type: a.content_type,
+            size_bytes: a.size_bytes,
+            width: a.width,
+            height: a.height,
+            thumbnails: Vec::new(),
+        }
+    }
+}
+
+impl From<&Thumbnail> for ThumbnailInfo {
+    fn from(t: &Thumbnail) -> Self {
+        Self {
+            size: t.size,
+            width: t.width,
+            height: t.height,
+        }
+    }
+}
+
+/// Most attachments one message can carry.
+pub const MAX_ATTACHMENTS_PER_MESSAGE: usize = 10;
@@ -6,6 +6,8 @@ use uuid::Uuid;
 pub struct RegisterRequest {
     pub username: String,
     pub password: String,
+    /// Optional; needed to reset a forgotten password.
+    pub email: Option<String>,
 }
 
 #[derive(Debug, Clone, Deserialize)]
@@ -16,11 +18,126 @@ pub struct LoginRequest {
 
 #[derive(Debug, Clone, Serialize)]
 pub struct LoginResponse {
+    /// Short-lived access token (JWT).
     pub token: String,
+    /// Seconds until `token` expires.
+    pub expires_in: i64,
+    /// Opaque token for `POST /api/auth/refresh`; single use.
+    pub refresh_token: String,
     pub user_id: Uuid,
     pub username: String,
 }
 
+/// Returned by `login` instead of tokens when the account has 2FA enabled.
+#[derive(Debug, Clone, Serialize)]
+pub struct TwoFactorChallenge {
+    /// Always `true`; lets clients tell this apart from a `LoginResponse`.
+    pub two_factor_required: bool,
+    /// Single use; exchange it with a code at `POST /api/auth/login/2fa`.
+    pub challenge_token: String,
+    /// Seconds until `challenge_token` expires.
+    pub expires_in: i64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(untagged)]
+pub enum LoginOutcome {
+    Authenticated(LoginResponse),
+    TwoFactorRequired(TwoFactorChallenge),
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct TwoFactorLoginRequest {
+    pub challenge_token: String,
+    /// Six-digit TOTP code, or a recovery code.
+    pub code: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TotpEnrollResponse {
+    /// Base32 secret, for manual entry.
+    pub secret: String,
+    /// `otpauth://` URI, usually rendered as a QR code.
+    pub otpauth_uri: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct TotpCodeRequest {
+    pub code: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RecoveryCodesResponse {
+    /// Each works once in place of a TOTP code. Shown only now.
+    pub recovery_codes: Vec<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct DisableTwoFactorRequest {
+    /// Required unless the account has no password (single sign-on).
+    pub password: Option<String>,
+    /// Current TOTP code, or a recovery code.
+    pub code: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct RefreshRequest {
+    pub refresh_token: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ChangePasswordRequest {
+    pub current_password: String,
+    pub new_password: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct PasswordResetRequest {
+    pub email: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct PasswordResetConfirmRequest {
+    pub token: String,
+    pub new_password: String,
+}
+
+/// A provider offered on the sign-in screen.
+#[derive(Debug, Clone, Serialize)]
+pub struct OidcProviderResponse {
+    pub name: String,
+    pub display_name: String,
+}
+
+/// Query of the provider's redirect back to us.
+#[derive(Debug, Clone, Deserialize)]
+pub struct OidcCallbackQuery {
+    pub code: Option<String>,
+    pub state: Option<String>,
+    /// Set instead of `code` when the sign-in failed or was cancelled.
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct OidcGrantRequest {
+    /// From the `oidc_grant` fragment parameter of the post-sign-in redirect.
+    pub grant: String,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct WsTicketRequest {
+    /// Limit the ticket to this room.
+    pub room_id: Option<Uuid>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct WsTicketResponse {
+    /// Single use; pass it when opening the WebSocket.
+    pub ticket: String,
+    /// Seconds until `ticket` expires.
+    pub expires_in: i64,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Claims {
     pub sub: Uuid,
@@ -28,6 +145,10 @@ pub struct Claims {
     pub exp: i64,
     pub iat: i64,
     pub iss: String,
+    /// Unique token id, used to revoke the token before it expires.
+    pub jti: Uuid,
+    /// Session the token was issued for.
+    pub sid: Uuid,
 }
 
 #[derive(Debug, Clone, Serialize)]
@@ -0,0 +1,51 @@
+//! Everything `GET /api/me/export` hands a user about themselves.
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::FromRow;
+use uuid::Uuid;
+
+use crate::models::{
+    session::Session,
+    user::{BlockedUser, UserProfile},
+};
+
+#[derive(Debug, Serialize)]
+pub struct AccountExport {
+    pub exported_at: DateTime<Utc>,
+    pub profile: UserProfile,
+    pub email: Option<String>,
+    pub two_factor_enabled: bool,
+    pub identities: Vec<ExportedIdentity>,
+    pub sessions: Vec<Session>,
+    pub memberships: Vec<ExportedMembership>,
+    pub blocked_users: Vec<BlockedUser>,
+    /// Oldest first.
+    pub messages: Vec<ExportedMessage>,
+}
+
+/// A linked single sign-on account.
+#[derive(Debug, Serialize, FromRow)]
+pub struct ExportedIdentity {
+    pub provider: String,
+    pub subject: String,
+    pub email: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub last_login_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize, FromRow)]
+pub struct ExportedMembership {
+    pub room_id: Uuid,
+    pub room_name: String,
+    pub role: String,
+    pub joined_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize, FromRow)]
+pub struct ExportedMessage {
+    pub id: Uuid,
+    pub room_id: Uuid,
+    pub content: String,
+    pub created_at: DateTime<Utc>,
+}
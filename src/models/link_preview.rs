@@ -0,0 +1,20 @@
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+
+/// What a linked page says about itself (OpenGraph tags, falling back to its
+/// `<title>` and meta description).
+#[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
+pub struct LinkPreview {
+    pub url: String,
+    pub title: Option<String>,
+    pub description: Option<String>,
+    pub image_url: Option<String>,
+    pub site_name: Option<String>,
+}
+
+impl LinkPreview {
+    /// Whether the page had anything worth showing.
+    pub fn is_empty(&self) -> bool {
+        self.title.is_none() && self.description.is_none() && self.image_url.is_none()
+    }
+}
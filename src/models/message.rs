@@ -3,6 +3,8 @@ use serde::{Deserialize, Serialize};
 use sqlx::FromRow;
 use uuid::Uuid;
 
+use crate::models::{attachment::AttachmentInfo, link_preview::LinkPreview};
+
 #[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
 pub struct Message {
     pub id: Uuid,
@@ -18,33 +20,91 @@ pub struct OutgoingWsMessage {
     pub room_id: Uuid,
     pub user_id: Uuid,
     pub username: String,
+    /// The sender's current display name, if they have set one.
+    pub display_name: Option<String>,
+    pub avatar_url: Option<String>,
     pub content: String,
+    #[serde(default)]
+    pub attachments: Vec<AttachmentInfo>,
+    #[serde(default)]
+    pub previews: Vec<LinkPreview>,
     pub created_at: DateTime<Utc>,
     pub kind: WsMessageKind,
 }
 
+impl OutgoingWsMessage {
+    pub fn stored(message: MessageWithUsername, kind: WsMessageKind) -> Self {
+        Self {
+            id: message.id,
+            room_id: message.room_id,
+            user_id: message.user_id,
+            username: message.username,
+            display_name: message.display_name,
+            avatar_url: message.avatar_url,
+            content: message.content,
+            attachments: message.attachments,
+            previews: message.previews,
+            created_at: message.created_at,
+            kind,
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "snake_case")]
 pub enum WsMessageKind {
     History,
     Message,
     System,
+    Gap,
+    /// A message sent again once its link previews were fetched; clients
+    /// replace their copy with the same `id`.
+    Preview,
+}
+
+/// Sent to a client that fell behind the room's broadcast buffer. Messages
+/// strictly between `after_id` and `before_id` were not delivered and can be
+/// fetched with `GET /api/rooms/{room_id}/messages?before=...`. Either bound
+/// is `None` when unknown.
+#[derive(Debug, Clone, Serialize)]
+pub struct GapEvent {
+    pub kind: WsMessageKind,
+    pub room_id: Uuid,
+    pub after_id: Option<Uuid>,
+    pub before_id: Option<Uuid>,
+    pub skipped: u64,
 }
 
-/// Message row with username (e.g. from JOIN with users).
+/// Message row with the sender's username and profile (e.g. from JOIN with
+/// users).
 #[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
 pub struct MessageWithUsername {
     pub id: Uuid,
     pub room_id: Uuid,
     pub user_id: Uuid,
     pub username: String,
+    pub display_name: Option<String>,
+    pub avatar_url: Option<String>,
     pub content: String,
+    /// Loaded separately (see `db::attachments::load_message_attachments`).
+    #[sqlx(skip)]
+    #[serde(default)]
+    pub attachments: Vec<AttachmentInfo>,
+    /// Loaded separately (see `db::link_previews::load_message_previews`).
+    #[sqlx(skip)]
+    #[serde(default)]
+    pub previews: Vec<LinkPreview>,
     pub created_at: DateTime<Utc>,
 }
 
 #[derive(Debug, Clone, Deserialize)]
 pub struct IncomingWsMessage {
+    /// May be empty when the message carries attachments.
     pub content: String,
+    /// Uploads (see `POST /api/rooms/{room_id}/attachments`) to send with the
+    /// message.
+    #[serde(default)]
+    pub attachment_ids: Vec<Uuid>,
 }
 
 /// Max allowed message body length (chars/bytes).
@@ -1,5 +1,9 @@
+pub mod attachment;
 pub mod auth;
+pub mod export;
+pub mod link_preview;
 pub mod message;
 pub mod room;
+pub mod session;
 pub mod user;
 
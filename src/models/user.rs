@@ -1,13 +1,79 @@
 use chrono::{DateTime, Utc};
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize};
 use sqlx::FromRow;
 use uuid::Uuid;
 
+/// The account anonymized messages of deleted users are attributed to.
+pub const DELETED_USER_ID: Uuid = Uuid::from_u128(1);
+
 #[derive(Debug, Clone, Serialize, Deserialize, FromRow)]
 pub struct User {
     pub id: Uuid,
     pub username: String,
-    pub password_hash: String,
+    pub email: Option<String>,
+    /// `None` for accounts created through single sign-on.
+    pub password_hash: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+
+/// A user's public profile.
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub struct UserProfile {
+    pub id: Uuid,
+    pub username: String,
+    pub display_name: Option<String>,
+    pub avatar_url: Option<String>,
+    pub bio: Option<String>,
+    /// Free-form status text, such as "on holiday".
+    pub status: Option<String>,
     pub created_at: DateTime<Utc>,
 }
 
+/// Body of `PATCH /api/me/profile`. A missing field is left unchanged;
+/// `null` or an empty string clears it.
+#[derive(Debug, Default, Deserialize)]
+pub struct UpdateProfileRequest {
+    #[serde(default, deserialize_with = "present")]
+    pub display_name: Option<Option<String>>,
+    #[serde(default, deserialize_with = "present")]
+    pub avatar_url: Option<Option<String>>,
+    #[serde(default, deserialize_with = "present")]
+    pub bio: Option<Option<String>>,
+    #[serde(default, deserialize_with = "present")]
+    pub status: Option<Option<String>>,
+}
+
+/// Tells a field given as `null` (`Some(None)`) from a missing one (`None`).
+fn present<'de, D>(deserializer: D) -> Result<Option<Option<String>>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Option::<String>::deserialize(deserializer).map(Some)
+}
+
+/// The few fields user search exposes.
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub struct UserSummary {
+    pub id: Uuid,
+    pub username: String,
+    pub display_name: Option<String>,
+    pub avatar_url: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub struct BlockedUser {
+    pub id: Uuid,
+    pub username: String,
+    pub display_name: Option<String>,
+    pub avatar_url: Option<String>,
+    pub blocked_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DeleteAccountRequest {
+    /// Required unless the account has no password (single sign-on).
+    pub password: Option<String>,
+    /// Current TOTP code or a recovery code; required with 2FA enabled.
+    pub code: Option<String>,
+}
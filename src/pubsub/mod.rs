@@ -0,0 +1,154 @@
+//! Cross-instance fan-out of room events.
+//!
+//! Each instance only holds broadcast channels for sockets connected to it
+//! (see [`RoomRegistry`]). [`Broadcaster`] delivers an event to local sockets
+//! directly and hands it to a [`PubSubBackend`], which relays it to every other
+//! instance so their sockets see it too. Block list changes travel the same
+//! way, since every socket of the user who blocked someone filters by them.
+
+pub mod postgres;
+#[cfg(feature = "redis")]
+pub mod redis;
+
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{
+    config::{Config, PubSubBackendKind},
+    models::message::OutgoingWsMessage,
+    websocket::registry::RoomRegistry,
+};
+
+/// A room event as relayed between instances.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ClusterEnvelope {
+    /// Instance that produced the event; it has already delivered it locally.
+    pub origin: Uuid,
+    pub message: OutgoingWsMessage,
+}
+
+/// A user's block list changed, as relayed between instances.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BlocksChanged {
+    /// Instance the change was made on; it has already told its sockets.
+    pub origin: Uuid,
+    pub user_id: Uuid,
+}
+
+/// Transport used to relay room events between instances.
+///
+/// Implementations deliver events received from other instances into the
+/// [`RoomRegistry`] they were constructed with, skipping their own. After
+/// losing their connection they report block changes as possibly missed
+/// ([`RoomRegistry::blocks_changed`] with `None`).
+#[async_trait]
+pub trait PubSubBackend: Send + Sync {
+    async fn publish(&self, envelope: &ClusterEnvelope) -> anyhow::Result<()>;
+
+    async fn publish_blocks_changed(&self, event: &BlocksChanged) -> anyhow::Result<()>;
+
+    /// Users online in `room_id` across all instances, for backends that track
+    /// cluster-wide presence. `None` means only this instance's view is known.
+    async fn online_users(&self, _room_id: Uuid) -> anyhow::Result<Option<Vec<Uuid>>> {
+        Ok(None)
+    }
+}
+
+/// Single-instance backend: nothing to relay.
+pub struct LocalPubSub;
+
+#[async_trait]
+impl PubSubBackend for LocalPubSub {
+    async fn publish(&self, _envelope: &ClusterEnvelope) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    async fn publish_blocks_changed(&self, _event: &BlocksChanged) -> anyhow::Result<()> {
+        Ok(())
+    }
+}
+
+#[derive(Clone)]
+pub struct Broadcaster {
+    node_id: Uuid,
+    rooms: RoomRegistry,
+    backend: Arc<dyn PubSubBackend>,
+}
+
+impl Broadcaster {
+    /// Deliver `message` to sockets in its room on this instance and on every
+    /// other instance. Relay failures are logged; local delivery still happens.
+    pub async fn publish(&self, message: OutgoingWsMessage) {
+        self.rooms.broadcast(message.room_id, message.clone());
+
+        let envelope = ClusterEnvelope {
+            origin: self.node_id,
+            message,
+        };
+        if let Err(err) = self.backend.publish(&envelope).await {
+            tracing::warn!("failed to relay room event to other instances: {err:#}");
+        }
+    }
+
+    /// Have every socket of `user_id`, on any instance, reload their block
+    /// list. Relay failures are logged.
+    pub async fn publish_blocks_changed(&self, user_id: Uuid) {
+        self.rooms.blocks_changed(Some(user_id));
+
+        let event = BlocksChanged {
+            origin: self.node_id,
+            user_id,
+        };
+        if let Err(err) = self.backend.publish_blocks_changed(&event).await {
+            tracing::warn!("failed to relay block list change to other instances: {err:#}");
+        }
+    }
+
+    /// Users online in `room_id`, cluster-wide when the backend supports it.
+    pub async fn online_users(&self, room_id: Uuid) -> Vec<Uuid> {
+        match self.backend.online_users(room_id).await {
+            Ok(Some(users)) => users,
+            Ok(None) => self.rooms.online_users(room_id),
+            Err(err) => {
+                tracing::warn!("failed to read cluster presence, using local view: {err:#}");
+                self.rooms.online_users(room_id)
+            }
+        }
+    }
+}
+
+/// Build the broadcaster for the backend selected in `config`, starting any
+/// background listener it needs.
+pub async fn connect(config: &Config, db: &PgPool, rooms: RoomRegistry) -> anyhow::Result<Broadcaster> {
+    let node_id = Uuid::new_v4();
+    let backend: Arc<dyn PubSubBackend> = match &config.pubsub_backend {
+        PubSubBackendKind::Local => Arc::new(LocalPubSub),
+        PubSubBackendKind::Postgres => {
+            Arc::new(postgres::PgPubSub::start(db.clone(), node_id, rooms.clone()).await?)
+        }
+        #[cfg(feature = "redis")]
+        PubSubBackendKind::Redis {
+            url,
+            presence_ttl_secs,
+        } => Arc::new(
+            redis::RedisPubSub::start(
+                url,
+                node_id,
+                rooms.clone(),
+                std::time::Duration::from_secs(*presence_ttl_secs),
+            )
+            .await?,
+        ),
+    };
+    tracing::info!(node_id = %node_id, "room pub/sub ready");
+
+    Ok(Broadcaster {
+        node_id,
+        rooms,
+        backend,
+    })
+}
@@ -0,0 +1,161 @@
+//! Postgres `LISTEN/NOTIFY` backend.
+//!
+//! Every instance listens on one channel and filters events by room locally.
+//! Block list changes share the channel.
+//! `NOTIFY` payloads are capped at 8000 bytes, so large messages are sent as a
+//! reference and the receiving instances load them from the database.
+
+use std::time::Duration;
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use sqlx::{postgres::PgListener, PgPool};
+use uuid::Uuid;
+
+use crate::{
+    db::messages::get_message_with_username,
+    models::message::{OutgoingWsMessage, WsMessageKind},
+    pubsub::{BlocksChanged, ClusterEnvelope, PubSubBackend},
+    websocket::registry::RoomRegistry,
+};
+
+const CHANNEL: &str = "chat_room_events";
+
+/// Stay safely below Postgres' 8000-byte `NOTIFY` payload limit.
+const MAX_INLINE_PAYLOAD: usize = 7900;
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Notification {
+    Inline(ClusterEnvelope),
+    Stored {
+        origin: Uuid,
+        message_id: Uuid,
+        /// Missing from notifications sent before previews existed.
+        #[serde(default = "stored_kind")]
+        kind: WsMessageKind,
+    },
+    BlocksChanged(BlocksChanged),
+}
+
+fn stored_kind() -> WsMessageKind {
+    WsMessageKind::Message
+}
+
+pub struct PgPubSub {
+    db: PgPool,
+}
+
+impl PgPubSub {
+    /// Start listening for events from other instances and return the
+    /// publishing half.
+    pub async fn start(db: PgPool, node_id: Uuid, rooms: RoomRegistry) -> anyhow::Result<Self> {
+        let mut listener = PgListener::connect_with(&db).await?;
+        listener.listen(CHANNEL).await?;
+
+        let listener_db = db.clone();
+        tokio::spawn(async move {
+            let mut lost = false;
+            loop {
+                if lost {
+                    // Reconnect (and re-LISTEN) now rather than when the next
+                    // notification is awaited, then have sockets reload block
+                    // lists in case changes were sent meanwhile.
+                    if let Err(err) = sqlx::query("SELECT 1").execute(&mut listener).await {
+                        tracing::warn!("failed to reconnect LISTEN connection: {err}");
+                        tokio::time::sleep(Duration::from_secs(1)).await;
+                        continue;
+                    }
+                    lost = false;
+                    rooms.blocks_changed(None);
+                }
+                match listener.try_recv().await {
+                    Ok(Some(notification)) => {
+                        if let Err(err) =
+                            deliver(&listener_db, node_id, &rooms, notification.payload()).await
+                        {
+                            tracing::warn!("dropping malformed room notification: {err:#}");
+                        }
+                    }
+                    Ok(None) => {
+                        // Connection lost. Room events sent meanwhile are missed.
+                        tracing::warn!("lost LISTEN connection to Postgres; reconnecting");
+                        lost = true;
+                    }
+                    Err(err) => {
+                        tracing::warn!("Postgres LISTEN error: {err}");
+                        tokio::time::sleep(Duration::from_secs(1)).await;
+                        lost = true;
+                    }
+                }
+            }
+        });
+
+        Ok(Self { db })
+    }
+}
+
+#[async_trait]
+impl PubSubBackend for PgPubSub {
+    async fn publish(&self, envelope: &ClusterEnvelope) -> anyhow::Result<()> {
+        let mut payload = serde_json::to_string(&Notification::Inline(envelope.clone()))?;
+        if payload.len() > MAX_INLINE_PAYLOAD {
+            payload = serde_json::to_string(&Notification::Stored {
+                origin: envelope.origin,
+                message_id: envelope.message.id,
+                kind: envelope.message.kind.clone(),
+            })?;
+        }
+
+        self.notify(&payload).await
+    }
+
+    async fn publish_blocks_changed(&self, event: &BlocksChanged) -> anyhow::Result<()> {
+        let payload = serde_json::to_string(&Notification::BlocksChanged(event.clone()))?;
+        self.notify(&payload).await
+    }
+}
+
+impl PgPubSub {
+    async fn notify(&self, payload: &str) -> anyhow::Result<()> {
+        sqlx::query("SELECT pg_notify($1, $2)")
+            .bind(CHANNEL)
+            .bind(payload)
+            .execute(&self.db)
+            .await?;
+        Ok(())
+    }
+}
+
+async fn deliver(db: &PgPool, node_id: Uuid, rooms: &RoomRegistry, payload: &str) -> anyhow::Result<()> {
+    let message = match serde_json::from_str::<Notification>(payload)? {
+        Notification::Inline(envelope) => {
+            if envelope.origin == node_id {
+                return Ok(());
+            }
+            envelope.message
+        }
+        Notification::Stored {
+            origin,
+            message_id,
+            kind,
+        } => {
+            if origin == node_id {
+                return Ok(());
+            }
+            let Some(m) = get_message_with_username(db, message_id).await? else {
+                return Ok(());
+            };
+            OutgoingWsMessage::stored(m, kind)
+        }
+        Notification::BlocksChanged(event) => {
+            if event.origin != node_id {
+                rooms.blocks_changed(Some(event.user_id));
+            }
+            return Ok(());
+        }
+    };
+
+    rooms.broadcast(message.room_id, message);
+    Ok(())
+}
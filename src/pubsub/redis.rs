@@ -0,0 +1,397 @@
+//! Redis pub/sub backend (`--features redis`).
+//!
+//! Each room maps to its own Redis channel, and an instance only subscribes to
+//! rooms that currently have sockets connected to it. Block list changes go
+//! over one channel every instance subscribes to. Presence is kept in one
+//! sorted set per room whose scores are expiry timestamps; every instance
+//! refreshes its own entries periodically, so entries left by a crashed
+//! instance age out after the TTL.
+
+use std::{collections::BTreeSet, time::Duration};
+
+use async_trait::async_trait;
+use chrono::Utc;
+use futures_util::StreamExt;
+use redis::{aio::ConnectionManager, AsyncCommands};
+use uuid::Uuid;
+
+use crate::{
+    pubsub::{BlocksChanged, ClusterEnvelope, PubSubBackend},
+    websocket::registry::{RoomLifecycle, RoomRegistry},
+};
+
+const MAX_RECONNECT_BACKOFF: Duration = Duration::from_secs(30);
+
+const BLOCKS_CHANNEL: &str = "chat:blocks";
+
+fn room_channel(room_id: Uuid) -> String {
+    format!("chat:room:{room_id}")
+}
+
+fn presence_key(room_id: Uuid) -> String {
+    format!("chat:presence:{room_id}")
+}
+
+/// Presence members are per instance so one instance leaving does not hide a
+/// user who is still connected elsewhere.
+fn presence_member(user_id: Uuid, node_id: Uuid) -> String {
+    format!("{user_id}:{node_id}")
+}
+
+pub struct RedisPubSub {
+    conn: ConnectionManager,
+}
+
+impl RedisPubSub {
+    /// Connect to Redis and start the subscriber and presence refresh tasks.
+    pub async fn start(
+        url: &str,
+        node_id: Uuid,
+        rooms: RoomRegistry,
+        presence_ttl: Duration,
+    ) -> anyhow::Result<Self> {
+        let client = redis::Client::open(url)?;
+        let conn = client.get_connection_manager().await?;
+        let lifecycle = rooms
+            .watch_lifecycle()
+            .ok_or_else(|| anyhow::anyhow!("room lifecycle is already being watched"))?;
+
+        let subscriber = Subscriber {
+            client,
+            conn: conn.clone(),
+            node_id,
+            rooms: rooms.clone(),
+            presence_ttl,
+        };
+        tokio::spawn(subscriber.run(lifecycle));
+        tokio::spawn(refresh_presence(conn.clone(), node_id, rooms, presence_ttl));
+
+        Ok(Self { conn })
+    }
+}
+
+#[async_trait]
+impl PubSubBackend for RedisPubSub {
+    async fn publish(&self, envelope: &ClusterEnvelope) -> anyhow::Result<()> {
+        let payload = serde_json::to_string(envelope)?;
+        let mut conn = self.conn.clone();
+        let _: i64 = conn
+            .publish(room_channel(envelope.message.room_id), payload)
+            .await?;
+        Ok(())
+    }
+
+    async fn publish_blocks_changed(&self, event: &BlocksChanged) -> anyhow::Result<()> {
+        let payload = serde_json::to_string(event)?;
+        let mut conn = self.conn.clone();
+        let _: i64 = conn.publish(BLOCKS_CHANNEL, payload).await?;
+        Ok(())
+    }
+
+    async fn online_users(&self, room_id: Uuid) -> anyhow::Result<Option<Vec<Uuid>>> {
+        let mut conn = self.conn.clone();
+        let members: Vec<String> = conn
+            .zrangebyscore(presence_key(room_id), Utc::now().timestamp_millis(), "+inf")
+            .await?;
+
+        let users: BTreeSet<Uuid> = members
+            .iter()
+            .filter_map(|m| m.split_once(':'))
+            .filter_map(|(user_id, _node)| user_id.parse().ok())
+            .collect();
+        Ok(Some(users.into_iter().collect()))
+    }
+}
+
+struct Subscriber {
+    client: redis::Client,
+    conn: ConnectionManager,
+    node_id: Uuid,
+    rooms: RoomRegistry,
+    presence_ttl: Duration,
+}
+
+impl Subscriber {
+    /// Keep a pub/sub connection open, mirroring the registry's live rooms as
+    /// channel subscriptions. On reconnect, subscriptions are rebuilt from the
+    /// registry; queued lifecycle events are then applied on top in order.
+    /// Block changes published while disconnected are missed, so sockets are
+    /// told to reload their block lists after a reconnect.
+    async fn run(mut self, mut lifecycle: tokio::sync::mpsc::UnboundedReceiver<RoomLifecycle>) {
+        let mut backoff = Duration::from_millis(500);
+        let mut reconnecting = false;
+        loop {
+            match self.client.get_async_pubsub().await {
+                Ok(pubsub) => {
+                    backoff = Duration::from_millis(500);
+                    let (mut sink, mut stream) = pubsub.split();
+
+                    let live_rooms = self.rooms.live_rooms();
+                    let mut live: Vec<String> =
+                        live_rooms.iter().copied().map(room_channel).collect();
+                    live.push(BLOCKS_CHANNEL.to_string());
+                    let resubscribed = sink.subscribe(&live).await;
+                    if resubscribed.is_ok() {
+                        self.rooms.mark_all_subscribed(&live_rooms);
+                        if reconnecting {
+                            self.rooms.blocks_changed(None);
+                        }
+                    }
+                    reconnecting = true;
+
+                    if let Err(err) = resubscribed {
+                        tracing::warn!("failed to resubscribe to Redis room channels: {err}");
+                    } else {
+                        tracing::debug!("subscribed to {} Redis room channels", live_rooms.len());
+                        loop {
+                            tokio::select! {
+                                event = lifecycle.recv() => {
+                                    let Some(event) = event else { return };
+                                    if let Err(err) = self.apply(&mut sink, event).await {
+                                        tracing::warn!("failed to apply room lifecycle event to Redis: {err}");
+                                        break;
+                                    }
+                                }
+                                msg = stream.next() => {
+                                    let Some(msg) = msg else {
+                                        tracing::warn!("lost Redis pub/sub connection; reconnecting");
+                                        break;
+                                    };
+                                    self.deliver(&msg);
+                                }
+                            }
+                        }
+                    }
+                }
+                Err(err) => tracing::warn!("failed to connect to Redis pub/sub: {err}"),
+            }
+
+            tokio::time::sleep(backoff).await;
+            backoff = (backoff * 2).min(MAX_RECONNECT_BACKOFF);
+        }
+    }
+
+    async fn apply(
+        &mut self,
+        sink: &mut redis::aio::PubSubSink,
+        event: RoomLifecycle,
+    ) -> redis::RedisResult<()> {
+        match event {
+            RoomLifecycle::Opened {
+                room_id,
+                generation,
+            } => {
+                // Returns once Redis has acknowledged the subscription.
+                sink.subscribe(room_channel(room_id)).await?;
+                self.rooms.mark_subscribed(room_id, generation);
+                Ok(())
+            }
+            RoomLifecycle::Closed(room_id) => sink.unsubscribe(room_channel(room_id)).await,
+            RoomLifecycle::UserJoined { room_id, user_id } => {
+                mark_present(&mut self.conn, room_id, user_id, self.node_id, self.presence_ttl)
+                    .await
+                    .or_else(log_presence_error)
+            }
+            RoomLifecycle::UserLeft { room_id, user_id } => {
+                let _: redis::RedisResult<i64> = self
+                    .conn
+                    .zrem(presence_key(room_id), presence_member(user_id, self.node_id))
+                    .await
+                    .inspect_err(|err| tracing::warn!("failed to clear Redis presence: {err}"));
+                Ok(())
+            }
+        }
+    }
+
+    fn deliver(&self, msg: &redis::Msg) {
+        if msg.get_channel_name() == BLOCKS_CHANNEL {
+            match msg
+                .get_payload::<String>()
+                .map_err(anyhow::Error::from)
+                .and_then(|payload| Ok(serde_json::from_str::<BlocksChanged>(&payload)?))
+            {
+                Ok(event) if event.origin != self.node_id => {
+                    self.rooms.blocks_changed(Some(event.user_id));
+                }
+                Ok(_) => {}
+                Err(err) => tracing::warn!("dropping malformed Redis block change: {err:#}"),
+            }
+            return;
+        }
+        let envelope = match msg
+            .get_payload::<String>()
+            .map_err(anyhow::Error::from)
+            .and_then(|payload| Ok(serde_json::from_str::<ClusterEnvelope>(&payload)?))
+        {
+            Ok(envelope) => envelope,
+            Err(err) => {
+                tracing::warn!("dropping malformed Redis room event: {err:#}");
+                return;
+            }
+        };
+        if envelope.origin == self.node_id {
+            return;
+        }
+        self.rooms.broadcast(envelope.message.room_id, envelope.message);
+    }
+}
+
+/// Presence writes go through the auto-reconnecting connection manager and are
+/// independent of the pub/sub connection; a failure is logged, not fatal.
+fn log_presence_error(err: redis::RedisError) -> redis::RedisResult<()> {
+    tracing::warn!("failed to record Redis presence: {err}");
+    Ok(())
+}
+
+async fn mark_present(
+    conn: &mut ConnectionManager,
+    room_id: Uuid,
+    user_id: Uuid,
+    node_id: Uuid,
+    ttl: Duration,
+) -> redis::RedisResult<()> {
+    let expires_at = Utc::now().timestamp_millis() + ttl.as_millis() as i64;
+    let key = presence_key(room_id);
+    redis::pipe()
+        .zadd(&key, presence_member(user_id, node_id), expires_at)
+        .ignore()
+        .zrembyscore(&key, "-inf", Utc::now().timestamp_millis())
+        .ignore()
+        .expire(&key, (ttl.as_secs() * 2).max(1) as i64)
+        .ignore()
+        .query_async(conn)
+        .await
+}
+
+/// Re-stamp this instance's presence entries well before they expire.
+async fn refresh_presence(
+    mut conn: ConnectionManager,
+    node_id: Uuid,
+    rooms: RoomRegistry,
+    ttl: Duration,
+) {
+    let mut ticker = tokio::time::interval((ttl / 3).max(Duration::from_secs(1)));
+    ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    loop {
+        ticker.tick().await;
+        for (room_id, user_id) in rooms.presence_snapshot() {
+            if let Err(err) = mark_present(&mut conn, room_id, user_id, node_id, ttl).await {
+                tracing::warn!("failed to refresh Redis presence: {err}");
+                break;
+            }
+        }
+    }
+}
+
+/// Against a local Redis: `REDIS_URL=redis://127.0.0.1:6379 cargo test
+/// --features redis -- --ignored`.
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        models::message::{OutgoingWsMessage, WsMessageKind},
+        websocket::registry::RoomSettings,
+    };
+
+    const SETTINGS: RoomSettings = RoomSettings { channel_capacity: 16 };
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    fn redis_url() -> String {
+        std::env::var("REDIS_URL").unwrap_or_else(|_| "redis://127.0.0.1:6379".to_string())
+    }
+
+    async fn instance() -> (Uuid, RoomRegistry, RedisPubSub) {
+        let node_id = Uuid::new_v4();
+        let rooms = RoomRegistry::with_shards(SETTINGS, 4);
+        let backend = RedisPubSub::start(&redis_url(), node_id, rooms.clone(), TIMEOUT)
+            .await
+            .expect("Redis is not reachable at REDIS_URL");
+        (node_id, rooms, backend)
+    }
+
+    fn message(room_id: Uuid, content: &str) -> OutgoingWsMessage {
+        OutgoingWsMessage {
+            id: Uuid::new_v4(),
+            room_id,
+            user_id: Uuid::new_v4(),
+            username: "alice".to_string(),
+            display_name: None,
+            avatar_url: None,
+            content: content.to_string(),
+            attachments: Vec::new(),
+            previews: Vec::new(),
+            created_at: Utc::now(),
+            kind: WsMessageKind::Message,
+        }
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a local redis-server"]
+    async fn event_published_right_after_joining_is_delivered() {
+        let (origin, _, sender) = instance().await;
+        let (_, rooms, _receiver) = instance().await;
+        let room_id = Uuid::new_v4();
+
+        let mut subscription = rooms.join(room_id, Uuid::new_v4());
+        assert!(subscription.wait_subscribed(TIMEOUT).await);
+        let sent = message(room_id, "hello");
+        sender
+            .publish(&ClusterEnvelope {
+                origin,
+                message: sent.clone(),
+            })
+            .await
+            .unwrap();
+
+        let received = tokio::time::timeout(TIMEOUT, subscription.recv())
+            .await
+            .expect("event not relayed")
+            .unwrap();
+        assert_eq!(received.id, sent.id);
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a local redis-server"]
+    async fn own_events_are_not_delivered_twice() {
+        let (origin, rooms, backend) = instance().await;
+        let room_id = Uuid::new_v4();
+
+        let mut subscription = rooms.join(room_id, Uuid::new_v4());
+        assert!(subscription.wait_subscribed(TIMEOUT).await);
+        backend
+            .publish(&ClusterEnvelope {
+                origin,
+                message: message(room_id, "echo"),
+            })
+            .await
+            .unwrap();
+
+        assert!(tokio::time::timeout(Duration::from_millis(500), subscription.recv())
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a local redis-server"]
+    async fn presence_is_shared_across_instances() {
+        let (_, rooms_a, backend_a) = instance().await;
+        let (_, rooms_b, _backend_b) = instance().await;
+        let room_id = Uuid::new_v4();
+        let (alice, bob) = (Uuid::new_v4(), Uuid::new_v4());
+
+        let _a = rooms_a.join(room_id, alice);
+        let _b = rooms_b.join(room_id, bob);
+        let deadline = tokio::time::Instant::now() + TIMEOUT;
+        loop {
+            let mut online = backend_a.online_users(room_id).await.unwrap().unwrap();
+            online.sort();
+            let mut expected = vec![alice, bob];
+            expected.sort();
+            if online == expected {
+                break;
+            }
+            assert!(tokio::time::Instant::now() < deadline, "presence not shared: {online:?}");
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    }
+}
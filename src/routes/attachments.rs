@@ -0,0 +1,15 @@
+use axum::{routing::get, Router};
+
+use crate::{
+    handlers::attachment_handlers::{download_attachment_handler, download_thumbnail_handler},
+    state::AppState,
+};
+
+pub fn attachment_routes() -> Router<AppState> {
+    Router::new()
+        .route("/{attachment_id}", get(download_attachment_handler))
+        .route(
+            "/{attachment_id}/thumbnails/{size}",
+            get(download_thumbnail_handler),
+        )
+}
@@ -1,16 +1,36 @@
 use axum::{
-    routing::post,
+    routing::{get, post},
     Router,
 };
 
 use crate::state::AppState;
 
-use crate::handlers::auth_handlers::{login, me, register};
+use crate::handlers::auth_handlers::{
+    change_password, confirm_password_reset, confirm_two_factor, disable_two_factor,
+    enroll_two_factor, login, login_two_factor, logout, me, refresh, register,
+    request_password_reset,
+};
+use crate::handlers::oidc_handlers::{
+    authorize_oidc, exchange_oidc_grant, list_oidc_providers, oidc_callback,
+};
 
 pub fn auth_routes() -> Router<AppState> {
     Router::new()
         .route("/register", post(register))
         .route("/login", post(login))
+        .route("/login/2fa", post(login_two_factor))
+        .route("/refresh", post(refresh))
+        .route("/logout", post(logout))
+        .route("/password", post(change_password))
+        .route("/password/reset", post(request_password_reset))
+        .route("/password/reset/confirm", post(confirm_password_reset))
+        .route("/2fa/enroll", post(enroll_two_factor))
+        .route("/2fa/confirm", post(confirm_two_factor))
+        .route("/2fa/disable", post(disable_two_factor))
+        .route("/oidc/providers", get(list_oidc_providers))
+        .route("/oidc/exchange", post(exchange_oidc_grant))
+        .route("/oidc/{provider}/authorize", get(authorize_oidc))
+        .route("/oidc/{provider}/callback", get(oidc_callback))
         .route("/me", post(me))
 }
 
@@ -0,0 +1,27 @@
+use axum::{
+    routing::{delete, get, post},
+    Router,
+};
+
+use crate::{
+    handlers::me_handlers::{
+        block_user_handler, delete_account_handler, export_account_handler,
+        get_profile_handler, list_blocks_handler, list_sessions_handler, revoke_session_handler,
+        unblock_user_handler, update_profile_handler,
+    },
+    state::AppState,
+};
+
+pub fn me_routes() -> Router<AppState> {
+    Router::new()
+        .route("/", delete(delete_account_handler))
+        .route("/export", get(export_account_handler))
+        .route("/blocks", get(list_blocks_handler))
+        .route(
+            "/blocks/{user_id}",
+            post(block_user_handler).delete(unblock_user_handler),
+        )
+        .route("/profile", get(get_profile_handler).patch(update_profile_handler))
+        .route("/sessions", get(list_sessions_handler))
+        .route("/sessions/{session_id}", delete(revoke_session_handler))
+}
@@ -1,18 +1,31 @@
 use axum::{
     extract::State,
-    http::HeaderValue,
+    http::{header, HeaderValue},
+    response::IntoResponse,
     routing::get,
-    Router,
+    Json, Router,
 };
+use serde_json::json;
 use tower_http::cors::{AllowOrigin, CorsLayer};
 
 use crate::{
-    routes::{auth::auth_routes, rooms::room_routes, websocket::websocket_routes},
+    request_id::{self, REQUEST_ID_HEADER},
+    routes::{
+        attachments::attachment_routes,
+        auth::auth_routes,
+        me::me_routes,
+        rooms::room_routes,
+        users::user_routes,
+        websocket::{websocket_api_routes, websocket_routes},
+    },
     state::AppState,
 };
 
+pub mod attachments;
 pub mod auth;
+pub mod me;
 pub mod rooms;
+pub mod users;
 pub mod websocket;
 
 /// Build CORS layer. If `allowed_origins` is non-empty (e.g. from ALLOWED_ORIGINS env),
@@ -36,27 +49,37 @@ fn cors_layer(allowed_origins: &[String]) -> CorsLayer {
         .allow_methods([
             axum::http::Method::GET,
             axum::http::Method::POST,
+            axum::http::Method::PATCH,
+            axum::http::Method::DELETE,
             axum::http::Method::OPTIONS,
         ])
         .allow_headers([axum::http::header::AUTHORIZATION, axum::http::header::CONTENT_TYPE])
+        .expose_headers([REQUEST_ID_HEADER.clone()])
 }
 
 pub fn create_router(state: AppState, allowed_origins: Vec<String>) -> Router {
     let cors = cors_layer(&allowed_origins);
 
     let api = Router::new()
+        .nest("/attachments", attachment_routes())
         .nest("/auth", auth_routes())
+        .nest("/me", me_routes())
         .nest("/rooms", room_routes())
+        .nest("/users", user_routes())
+        .nest("/ws", websocket_api_routes())
         .route("/health", get(health_handler))
-        .route("/health/ready", get(ready_handler));
+        .route("/health/ready", get(ready_handler))
+        .route("/metrics", get(metrics_handler));
 
     let ws = websocket_routes();
 
     Router::new()
         .route("/", get(root_handler))
+        .route("/.well-known/jwks.json", get(jwks_handler))
         .nest("/api", api)
         .nest("/ws", ws)
         .layer(cors)
+        .layer(axum::middleware::from_fn(request_id::assign))
         .with_state(state)
 }
 
@@ -80,3 +103,21 @@ async fn ready_handler(State(state): State<AppState>) -> (axum::http::StatusCode
     }
 }
 
+/// Public keys that verify our access tokens, for other services. Empty when
+/// tokens are signed with HS256.
+async fn jwks_handler(State(state): State<AppState>) -> impl IntoResponse {
+    (
+        [(header::CACHE_CONTROL, "public, max-age=300")],
+        Json(json!({ "keys": state.jwt_keys.jwks() })),
+    )
+}
+
+/// Prometheus-style gauges for monitoring.
+async fn metrics_handler(State(state): State<AppState>) -> String {
+    let live_channels = state.rooms.live_count();
+    format!(
+        "# HELP chat_room_channels_live Room broadcast channels currently held in memory.\n\
+         # TYPE chat_room_channels_live gauge\n\
+         chat_room_channels_live {live_channels}\n"
+    )
+}
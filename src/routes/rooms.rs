@@ -1,12 +1,16 @@
 use axum::{
+    extract::DefaultBodyLimit,
     routing::{get, post},
     Router,
 };
 
 use crate::{
-    handlers::room_handlers::{
-        create_room_handler, join_room_handler, list_room_messages_handler,
-        list_rooms_handler,
+    handlers::{
+        attachment_handlers::upload_attachment_handler,
+        room_handlers::{
+            create_room_handler, join_room_handler, list_online_users_handler,
+            list_room_messages_handler, list_rooms_handler,
+        },
     },
     state::AppState,
 };
@@ -15,6 +19,12 @@ pub fn room_routes() -> Router<AppState> {
     Router::new()
         .route("/", get(list_rooms_handler).post(create_room_handler))
         .route("/{room_id}/join", post(join_room_handler))
+        // The upload handler enforces ATTACHMENT_MAX_BYTES itself.
+        .route(
+            "/{room_id}/attachments",
+            post(upload_attachment_handler).layer(DefaultBodyLimit::disable()),
+        )
         .route("/{room_id}/messages", get(list_room_messages_handler))
+        .route("/{room_id}/online", get(list_online_users_handler))
 }
 
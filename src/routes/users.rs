@@ -0,0 +1,12 @@
+use axum::{routing::get, Router};
+
+use crate::{
+    handlers::user_handlers::{get_user_handler, search_users_handler},
+    state::AppState,
+};
+
+pub fn user_routes() -> Router<AppState> {
+    Router::new()
+        .route("/", get(search_users_handler))
+        .route("/{user_id}", get(get_user_handler))
+}
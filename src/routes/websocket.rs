@@ -1,11 +1,18 @@
 use axum::{
-    routing::get,
+    routing::{get, post},
     Router,
 };
 
-use crate::{state::AppState, websocket::room::room_ws_handler};
+use crate::{
+    handlers::ws_handlers::create_ws_ticket, state::AppState, websocket::room::room_ws_handler,
+};
 
 pub fn websocket_routes() -> Router<AppState> {
     Router::new().route("/rooms/{room_id}", get(room_ws_handler))
 }
 
+/// HTTP endpoints supporting the WebSocket, under `/api/ws`.
+pub fn websocket_api_routes() -> Router<AppState> {
+    Router::new().route("/ticket", post(create_ws_ticket))
+}
+
@@ -1,28 +1,126 @@
-use std::{collections::HashMap, sync::Arc};
+use std::{sync::Arc, time::Duration};
 
 use sqlx::PgPool;
-use tokio::sync::{broadcast, RwLock};
-use uuid::Uuid;
 
-use crate::models::message::OutgoingWsMessage;
+use crate::{
+    attachments::images::ImageWorkers,
+    auth::{keys::JwtKeys, oidc::OidcProviders, revocation::RevocationStore},
+    config::{Config, DeletedUserMessages, LagPolicy},
+    link_previews::LinkPreviews,
+    mailer::Mailer,
+    pubsub::Broadcaster,
+    storage::Storage,
+    validation::CredentialPolicy,
+    websocket::registry::RoomRegistry,
+};
+
+/// Server-side WebSocket keepalive.
+#[derive(Debug, Clone, Copy)]
+pub struct WsHeartbeat {
+    pub ping_interval: Duration,
+    pub pong_timeout: Duration,
+}
+
+/// Password login throttling (see `auth::login_throttle`).
+#[derive(Debug, Clone, Copy)]
+pub struct LoginLimits {
+    /// Failed logins allowed per username before lockouts start.
+    pub max_failures: i32,
+    /// Failed logins allowed per client address before lockouts start.
+    pub max_failures_per_ip: i32,
+    pub lockout_base: chrono::Duration,
+    pub lockout_max: chrono::Duration,
+}
+
+/// What attachment uploads may be.
+#[derive(Debug, Clone)]
+pub struct AttachmentLimits {
+    pub max_bytes: usize,
+    /// Allowed content types; `type/*` allows a whole type.
+    pub types: Arc<Vec<String>>,
+}
 
 #[derive(Clone)]
 pub struct AppState {
     pub db: PgPool,
-    pub rooms: Arc<RwLock<HashMap<Uuid, broadcast::Sender<OutgoingWsMessage>>>>,
-    pub jwt_secret: Arc<String>,
+    pub rooms: RoomRegistry,
+    pub broadcaster: Broadcaster,
+    pub jwt_keys: Arc<JwtKeys>,
     pub jwt_issuer: Arc<String>,
-    pub jwt_exp_hours: i64,
+    pub access_token_ttl: chrono::Duration,
+    pub refresh_token_ttl: chrono::Duration,
+    pub revocations: RevocationStore,
+    pub mailer: Arc<dyn Mailer>,
+    pub storage: Arc<dyn Storage>,
+    pub attachment_limits: AttachmentLimits,
+    pub images: ImageWorkers,
+    pub link_previews: LinkPreviews,
+    pub password_reset_ttl: chrono::Duration,
+    pub password_reset_url: Arc<String>,
+    pub totp_issuer: Arc<String>,
+    pub oidc: OidcProviders,
+    pub credential_policy: Arc<CredentialPolicy>,
+    pub deleted_user_messages: DeletedUserMessages,
+    pub login_limits: LoginLimits,
+    pub trust_forwarded_for: bool,
+    pub lag_policy: LagPolicy,
+    pub ws_heartbeat: WsHeartbeat,
 }
 
 impl AppState {
-    pub fn new(db: PgPool, jwt_secret: String, jwt_issuer: String, jwt_exp_hours: i64) -> Self {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        db: PgPool,
+        config: &Config,
+        rooms: RoomRegistry,
+        broadcaster: Broadcaster,
+        revocations: RevocationStore,
+        mailer: Arc<dyn Mailer>,
+        storage: Arc<dyn Storage>,
+        oidc: OidcProviders,
+        link_previews: LinkPreviews,
+        jwt_keys: JwtKeys,
+        credential_policy: CredentialPolicy,
+    ) -> Self {
         Self {
             db,
-            rooms: Arc::new(RwLock::new(HashMap::new())),
-            jwt_secret: Arc::new(jwt_secret),
-            jwt_issuer: Arc::new(jwt_issuer),
-            jwt_exp_hours,
+            rooms,
+            broadcaster,
+            jwt_keys: Arc::new(jwt_keys),
+            jwt_issuer: Arc::new(config.jwt_issuer.clone()),
+            access_token_ttl: chrono::Duration::minutes(config.access_token_ttl_minutes),
+            refresh_token_ttl: chrono::Duration::days(config.refresh_token_ttl_days),
+            revocations,
+            mailer,
+            storage,
+            attachment_limits: AttachmentLimits {
+                max_bytes: config.attachment_max_bytes,
+                types: Arc::new(config.attachment_types.clone()),
+            },
+            images: ImageWorkers::start(
+                config.image_workers,
+                config.image_queue_capacity,
+                config.thumbnail_sizes.clone(),
+            ),
+            link_previews,
+            password_reset_ttl: chrono::Duration::minutes(config.password_reset_ttl_minutes),
+            password_reset_url: Arc::new(config.password_reset_url.clone()),
+            totp_issuer: Arc::new(config.totp_issuer.clone()),
+            oidc,
+            credential_policy: Arc::new(credential_policy),
+            deleted_user_messages: config.deleted_user_messages,
+            login_limits: LoginLimits {
+                max_failures: config.login_max_failures,
+                max_failures_per_ip: config.login_max_failures_per_ip,
+                lockout_base: chrono::Duration::seconds(config.login_lockout_base_secs),
+                lockout_max: chrono::Duration::seconds(config.login_lockout_max_secs),
+            },
+            trust_forwarded_for: config.trust_forwarded_for,
+            lag_policy: config.ws_lag_policy,
+            ws_heartbeat: WsHeartbeat {
+                ping_interval: Duration::from_secs(config.ws_ping_interval_secs),
+                pong_timeout: Duration::from_secs(config.ws_pong_timeout_secs),
+            },
         }
     }
 }
@@ -32,4 +130,3 @@ impl AsRef<AppState> for AppState {
         self
     }
 }
-
@@ -0,0 +1,97 @@
+//! Attachment file storage.
+//!
+//! [`Storage`] keeps opaque objects by key. Keys are generated by the server
+//! (see [`crate::attachments`]) and only contain letters, digits, `-`, `_`,
+//! `.` and `/`. Files can live on the local filesystem, which suits a single
+//! instance or a shared volume, or in an S3-compatible bucket.
+
+use std::{path::PathBuf, sync::Arc};
+
+use anyhow::bail;
+use async_trait::async_trait;
+use axum::body::Bytes;
+use uuid::Uuid;
+
+use crate::config::{Config, StorageKind};
+
+pub mod s3;
+
+#[async_trait]
+pub trait Storage: Send + Sync {
+    async fn put(&self, key: &str, data: Bytes, content_type: &str) -> anyhow::Result<()>;
+    /// `None` if there is no such object.
+    async fn get(&self, key: &str) -> anyhow::Result<Option<Bytes>>;
+    /// Deleting a missing object is not an error.
+    async fn delete(&self, key: &str) -> anyhow::Result<()>;
+}
+
+/// Stores each object as a file under `dir`, at its key's path.
+pub struct LocalStorage {
+    dir: PathBuf,
+}
+
+impl LocalStorage {
+    fn path(&self, key: &str) -> anyhow::Result<PathBuf> {
+        let valid = !key.is_empty()
+            && key.split('/').all(|part| {
+                !part.is_empty()
+                    && part != "."
+                    && part != ".."
+                    && part
+                        .bytes()
+                        .all(|b| b.is_ascii_alphanumeric() || matches!(b, b'-' | b'_' | b'.'))
+            });
+        if !valid {
+            bail!("invalid storage key {key:?}");
+        }
+        Ok(self.dir.join(key))
+    }
+}
+
+#[async_trait]
+impl Storage for LocalStorage {
+    async fn put(&self, key: &str, data: Bytes, _content_type: &str) -> anyhow::Result<()> {
+        let path = self.path(key)?;
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        // Write under a temporary name first, so readers never see half a file.
+        let partial = path.with_extension(format!("{}.partial", Uuid::new_v4()));
+        tokio::fs::write(&partial, &data).await?;
+        if let Err(err) = tokio::fs::rename(&partial, &path).await {
+            let _ = tokio::fs::remove_file(&partial).await;
+            return Err(err.into());
+        }
+        Ok(())
+    }
+
+    async fn get(&self, key: &str) -> anyhow::Result<Option<Bytes>> {
+        match tokio::fs::read(self.path(key)?).await {
+            Ok(data) => Ok(Some(data.into())),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    async fn delete(&self, key: &str) -> anyhow::Result<()> {
+        match tokio::fs::remove_file(self.path(key)?).await {
+            Ok(()) => Ok(()),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            Err(err) => Err(err.into()),
+        }
+    }
+}
+
+pub fn from_config(config: &Config) -> anyhow::Result<Arc<dyn Storage>> {
+    Ok(match &config.storage {
+        StorageKind::Local { dir } => {
+            std::fs::create_dir_all(dir)?;
+            tracing::info!("storing attachments in {dir}");
+            Arc::new(LocalStorage { dir: dir.into() })
+        }
+        StorageKind::S3(s3) => {
+            tracing::info!(endpoint = %s3.endpoint, bucket = %s3.bucket, "storing attachments in S3");
+            Arc::new(s3::S3Storage::new(s3.clone())?)
+        }
+    })
+}
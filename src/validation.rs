@@ -0,0 +1,379 @@
+//! Rules for user-supplied account fields.
+//!
+//! Usernames are NFKC-normalized and trimmed before they are checked, stored
+//! or looked up, so compatibility forms (fullwidth letters, ligatures, ...)
+//! collapse into the plain name; uniqueness ignores case. Passwords are
+//! checked for length, against the username and, if configured, against a
+//! local list of breached passwords. Profile text is NFKC-normalized and
+//! trimmed too, and an empty value clears the field.
+
+use std::collections::HashSet;
+
+use anyhow::Context;
+use reqwest::Url;
+use unicode_normalization::UnicodeNormalization;
+use unicode_script::ScriptExtension;
+
+use crate::{
+    config::{Config, UsernameCharset},
+    error::{AppError, AppResult},
+    models::user::UpdateProfileRequest,
+};
+
+/// Longest password accepted; hashing cost grows with its length.
+const MAX_PASSWORD_LEN: usize = 256;
+const MAX_DISPLAY_NAME_LEN: usize = 64;
+const MAX_AVATAR_URL_LEN: usize = 2048;
+const MAX_BIO_LEN: usize = 500;
+const MAX_STATUS_LEN: usize = 100;
+/// Length of the `-xxxx` suffix added to taken single sign-on usernames.
+const USERNAME_SUFFIX_LEN: usize = 5;
+
+/// The form a username is stored and looked up in.
+pub fn normalize_username(name: &str) -> String {
+    name.nfkc().collect::<String>().trim().to_string()
+}
+
+pub struct CredentialPolicy {
+    username_charset: UsernameCharset,
+    username_min_length: usize,
+    username_max_length: usize,
+    password_min_length: usize,
+    /// Lowercased.
+    breached_passwords: HashSet<String>,
+}
+
+impl CredentialPolicy {
+    pub fn from_config(config: &Config) -> anyhow::Result<Self> {
+        let breached_passwords = match &config.breached_passwords_file {
+            Some(path) => {
+                let list = std::fs::read_to_string(path)
+                    .with_context(|| format!("failed to read BREACHED_PASSWORDS_FILE {path}"))?;
+                let passwords: HashSet<String> = list
+                    .lines()
+                    .map(str::trim)
+                    .filter(|p| !p.is_empty())
+                    .map(str::to_lowercase)
+                    .collect();
+                tracing::info!("loaded {} breached passwords", passwords.len());
+                passwords
+            }
+            None => HashSet::new(),
+        };
+
+        Ok(Self {
+            username_charset: config.username_charset,
+            username_min_length: config.username_min_length,
+            username_max_length: config.username_max_length,
+            password_min_length: config.password_min_length,
+            breached_passwords,
+        })
+    }
+
+    /// Check a new username. Returns it normalized.
+    pub fn validate_username(&self, name: &str) -> AppResult<String> {
+        let name = normalize_username(name);
+        let (min, max) = (self.username_min_length, self.username_max_length);
+        let len = name.chars().count();
+        if len < min || len > max {
+            return Err(AppError::validation(
+                "username",
+                format!("username must be {min} to {max} characters"),
+            ));
+        }
+        if !name.chars().all(|c| self.allowed_in_username(c)) {
+            let letters = match self.username_charset {
+                UsernameCharset::Ascii => "ASCII letters",
+                UsernameCharset::Unicode => "letters",
+            };
+            return Err(AppError::validation(
+                "username",
+                format!("username may only contain {letters}, digits, '_', '-' and '.'"),
+            ));
+        }
+        if !name.starts_with(char::is_alphanumeric) {
+            return Err(AppError::validation(
+                "username",
+                "username must start with a letter or digit",
+            ));
+        }
+        // Common characters (digits, punctuation) go with any script.
+        if ScriptExtension::for_str(&name).is_empty() {
+            return Err(AppError::validation(
+                "username",
+                "username must not mix letters from different scripts",
+            ));
+        }
+        Ok(name)
+    }
+
+    /// Check a new password for the account called `username`.
+    pub fn validate_password(&self, password: &str, username: &str) -> AppResult<()> {
+        let min = self.password_min_length;
+        let len = password.chars().count();
+        if len < min {
+            return Err(AppError::validation(
+                "password",
+                format!("password must be at least {min} characters"),
+            ));
+        }
+        if len > MAX_PASSWORD_LEN {
+            return Err(AppError::validation(
+                "password",
+                format!("password must be at most {MAX_PASSWORD_LEN} characters"),
+            ));
+        }
+        let mut chars = password.chars();
+        if chars.next().is_some_and(|first| chars.all(|c| c == first)) {
+            return Err(AppError::validation(
+                "password",
+                "password must not repeat a single character",
+            ));
+        }
+        let lowered = password.to_lowercase();
+        if lowered == normalize_username(username).to_lowercase() {
+            return Err(AppError::validation(
+                "password",
+                "password must not be the username",
+            ));
+        }
+        if self.breached_passwords.contains(&lowered) {
+            return Err(AppError::validation(
+                "password",
+                "password is too common or appears in a data breach; choose another",
+            ));
+        }
+        Ok(())
+    }
+
+    /// A valid username derived from `hint` (such as a single sign-on
+    /// provider's preferred username), short enough to take a `-xxxx` suffix.
+    pub fn username_from_hint(&self, hint: &str) -> String {
+        let max = self
+            .username_max_length
+            .saturating_sub(USERNAME_SUFFIX_LEN)
+            .max(self.username_min_length);
+        let name: String = normalize_username(hint)
+            .chars()
+            .filter(|&c| self.allowed_in_username(c))
+            .skip_while(|c| !c.is_alphanumeric())
+            .take(max)
+            .collect();
+        match self.validate_username(&name) {
+            Ok(name) => name,
+            Err(_) => "user".to_string(),
+        }
+    }
+
+    fn allowed_in_username(&self, c: char) -> bool {
+        matches!(c, '_' | '-' | '.')
+            || match self.username_charset {
+                UsernameCharset::Ascii => c.is_ascii_alphanumeric(),
+                UsernameCharset::Unicode => c.is_alphanumeric(),
+            }
+    }
+}
+
+/// Check a profile update. Returns it with every given field normalized and
+/// empty values turned into `None`.
+pub fn validate_profile_update(update: UpdateProfileRequest) -> AppResult<UpdateProfileRequest> {
+    let avatar_url = profile_text(update.avatar_url, "avatar_url", MAX_AVATAR_URL_LEN, false)?;
+    let avatar_url = match avatar_url {
+        Some(Some(url)) => Some(Some(validate_avatar_url(url)?)),
+        other => other,
+    };
+    Ok(UpdateProfileRequest {
+        display_name: profile_text(
+            update.display_name,
+            "display_name",
+            MAX_DISPLAY_NAME_LEN,
+            false,
+        )?,
+        avatar_url,
+        bio: profile_text(update.bio, "bio", MAX_BIO_LEN, true)?,
+        status: profile_text(update.status, "status", MAX_STATUS_LEN, false)?,
+    })
+}
+
+/// Normalize and length-check one profile field. Only `multiline` fields may
+/// contain line breaks; other control characters are refused everywhere.
+fn profile_text(
+    value: Option<Option<String>>,
+    field: &'static str,
+    max: usize,
+    multiline: bool,
+) -> AppResult<Option<Option<String>>> {
+    let Some(value) = value else {
+        return Ok(None);
+    };
+    let text = value
+        .map(|v| {
+            let v: String = v.replace("\r\n", "\n").nfkc().collect();
+            v.trim().to_string()
+        })
+        .filter(|v| !v.is_empty());
+    if let Some(text) = &text {
+        if text.chars().count() > max {
+            return Err(AppError::validation(
+                field,
+                format!("{field} must be at most {max} characters"),
+            ));
+        }
+        if text.chars().any(|c| c.is_control() && !(multiline && c == '\n')) {
+            return Err(AppError::validation(
+                field,
+                format!("{field} must not contain control characters"),
+            ));
+        }
+    }
+    Ok(Some(text))
+}
+
+fn validate_avatar_url(url: String) -> AppResult<String> {
+    match Url::parse(&url) {
+        Ok(parsed) if parsed.scheme() == "https" && parsed.host().is_some() => Ok(url),
+        _ => Err(AppError::validation(
+            "avatar_url",
+            "avatar_url must be an https URL",
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy(username_charset: UsernameCharset) -> CredentialPolicy {
+        CredentialPolicy {
+            username_charset,
+            username_min_length: 3,
+            username_max_length: 32,
+            password_min_length: 8,
+            breached_passwords: ["password1", "letmein123"].map(String::from).into(),
+        }
+    }
+
+    fn rejected_field<T: std::fmt::Debug>(result: AppResult<T>) -> Option<&'static str> {
+        match result {
+            Err(AppError::Validation { field, .. }) => Some(field),
+            other => panic!("expected a validation error, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn normalize_username_folds_compatibility_forms() {
+        let cases = [
+            ("ｊｏｈｎ", "john"),
+            ("Ｊｏｈｎ＿９", "John_9"),
+            ("\u{FB01}sh", "fish"),
+            ("\u{FB00}ord", "fford"),
+            ("Ⅻ", "XII"),
+            ("  alice\t", "alice"),
+            ("e\u{301}mile", "émile"),
+        ];
+        for (input, expected) in cases {
+            assert_eq!(normalize_username(input), expected, "{input:?}");
+        }
+    }
+
+    #[test]
+    fn validate_username_accepts_single_script_names() {
+        let unicode = policy(UsernameCharset::Unicode);
+        let cases = [
+            ("alice", "alice"),
+            ("ｊｏｈｎ", "john"),
+            ("иван_99", "иван_99"),
+            ("ἀλέξανδρος", "ἀλέξανδρος"),
+            ("a.b-c", "a.b-c"),
+            ("abc", "abc"),
+            (&"a".repeat(32), &"a".repeat(32)),
+        ];
+        for (input, expected) in cases {
+            assert_eq!(unicode.validate_username(input).unwrap(), expected, "{input:?}");
+        }
+    }
+
+    #[test]
+    fn validate_username_rejects() {
+        let unicode = policy(UsernameCharset::Unicode);
+        let ascii = policy(UsernameCharset::Ascii);
+        let long = "a".repeat(33);
+        let cases = [
+            (&unicode, "ab"),
+            (&unicode, long.as_str()),
+            (&unicode, "a b"),
+            (&unicode, "_alice"),
+            (&unicode, "bob@home"),
+            // Latin "p", "y", "p", "l" with Cyrillic "а".
+            (&unicode, "p\u{430}yp\u{430}l"),
+            (&unicode, "ivan\u{432}"),
+            (&ascii, "иван"),
+            (&ascii, "émile"),
+        ];
+        for (policy, input) in cases {
+            let result = policy.validate_username(input);
+            assert_eq!(rejected_field(result), Some("username"), "{input:?}");
+        }
+    }
+
+    #[test]
+    fn validate_password_rules() {
+        let policy = policy(UsernameCharset::Unicode);
+        let cases = [
+            ("abcdefg", false),
+            ("abcdefgh", true),
+            ("ééééééé", false),
+            ("éèêëēėęa", true),
+            ("aaaaaaaa", false),
+            ("aaaaaaaab", true),
+            ("ZZZZZZZZZZZZ", false),
+            ("alice123", false),
+            ("ALICE123", false),
+            ("alice1234", true),
+            ("password1", false),
+            ("PassWord1", false),
+            ("letmein123", false),
+            ("letmein1234", true),
+        ];
+        for (password, ok) in cases {
+            let result = policy.validate_password(password, "Alice123");
+            if ok {
+                assert!(result.is_ok(), "{password:?}: {result:?}");
+            } else {
+                assert_eq!(rejected_field(result), Some("password"), "{password:?}");
+            }
+        }
+
+        let longest: String = ('a'..='z').cycle().take(MAX_PASSWORD_LEN).collect();
+        assert!(policy.validate_password(&longest, "alice").is_ok());
+        let too_long = format!("{longest}a");
+        let result = policy.validate_password(&too_long, "alice");
+        assert_eq!(rejected_field(result), Some("password"));
+    }
+
+    #[test]
+    fn username_from_hint_derives_a_valid_name() {
+        let unicode = policy(UsernameCharset::Unicode);
+        let ascii = policy(UsernameCharset::Ascii);
+        let cases = [
+            (&unicode, "alice", "alice"),
+            (&unicode, "Alice Smith", "AliceSmith"),
+            (&unicode, "__bob", "bob"),
+            (&unicode, "ｊｏｈｎ", "john"),
+            (&unicode, "alice@example.com", "aliceexample.com"),
+            (&unicode, "иван", "иван"),
+            (&ascii, "José", "Jos"),
+            (&ascii, "иван", "user"),
+            (&unicode, "p\u{430}yp\u{430}l", "user"),
+            (&unicode, "!!", "user"),
+            (&unicode, "", "user"),
+        ];
+        for (policy, hint, expected) in cases {
+            assert_eq!(policy.username_from_hint(hint), expected, "{hint:?}");
+        }
+
+        // Leaves room for the suffix added to taken names.
+        let name = unicode.username_from_hint(&"x".repeat(40));
+        assert_eq!(name.len(), 32 - USERNAME_SUFFIX_LEN);
+    }
+}
@@ -0,0 +1,428 @@
+//! Sharded in-memory registry of live rooms.
+//!
+//! The registry owns each room's broadcast channel, the set of users currently
+//! connected to it, and the settings channels are created with. Rooms are
+//! spread over a fixed number of shards keyed by room id, so connects to
+//! different rooms rarely contend on the same lock. Shard locks are plain
+//! `std` mutexes: they are only held for short, non-async critical sections.
+//! It also relays block list changes to the sockets on this instance, since
+//! those filter what they deliver.
+
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc, Mutex, MutexGuard, OnceLock,
+    },
+    time::{Duration, Instant},
+};
+
+use tokio::sync::{broadcast, mpsc, watch};
+use uuid::Uuid;
+
+use crate::models::message::OutgoingWsMessage;
+
+/// Channel tuning. Applied when a room's channel is created.
+#[derive(Debug, Clone, Copy)]
+pub struct RoomSettings {
+    pub channel_capacity: usize,
+}
+
+/// Changes to which rooms and users are live on this instance, for pub/sub
+/// backends that mirror subscriptions or presence outside the process.
+#[derive(Debug, Clone, Copy)]
+#[cfg_attr(not(feature = "redis"), allow(dead_code))]
+pub enum RoomLifecycle {
+    /// The room's channel was created: its first local socket connected.
+    /// Sockets wait for [`RoomRegistry::mark_subscribed`] with the same
+    /// `generation` before they count as live.
+    Opened { room_id: Uuid, generation: u64 },
+    /// The room's channel was removed: no local sockets remain.
+    Closed(Uuid),
+    /// The user's first local socket in the room connected.
+    UserJoined { room_id: Uuid, user_id: Uuid },
+    /// The user's last local socket in the room disconnected.
+    UserLeft { room_id: Uuid, user_id: Uuid },
+}
+
+struct RoomEntry {
+    tx: broadcast::Sender<OutgoingWsMessage>,
+    /// Connected users and how many sockets each has open in this room.
+    presence: HashMap<Uuid, usize>,
+    /// Set by the sweeper the first time it sees the room without receivers.
+    empty_since: Option<Instant>,
+    /// Tells apart successive channels of the same room.
+    generation: u64,
+    /// Whether events from other instances reach this channel yet.
+    subscribed: watch::Sender<bool>,
+}
+
+#[derive(Default)]
+struct Shard {
+    rooms: HashMap<Uuid, RoomEntry>,
+}
+
+struct Inner {
+    shards: Box<[Mutex<Shard>]>,
+    live_channels: AtomicUsize,
+    next_generation: AtomicU64,
+    settings: RoomSettings,
+    lifecycle: OnceLock<mpsc::UnboundedSender<RoomLifecycle>>,
+    block_changes: broadcast::Sender<Option<Uuid>>,
+}
+
+#[derive(Clone)]
+pub struct RoomRegistry {
+    inner: Arc<Inner>,
+}
+
+impl RoomRegistry {
+    pub fn new(settings: RoomSettings) -> Self {
+        let parallelism = std::thread::available_parallelism()
+            .map(|n| n.get())
+            .unwrap_or(4);
+        Self::with_shards(settings, (parallelism * 4).next_power_of_two())
+    }
+
+    pub fn with_shards(settings: RoomSettings, shard_count: usize) -> Self {
+        let shards = (0..shard_count.max(1))
+            .map(|_| Mutex::new(Shard::default()))
+            .collect();
+        Self {
+            inner: Arc::new(Inner {
+                shards,
+                live_channels: AtomicUsize::new(0),
+                next_generation: AtomicU64::new(0),
+                settings,
+                lifecycle: OnceLock::new(),
+                block_changes: broadcast::channel(256).0,
+            }),
+        }
+    }
+
+    fn shard(&self, room_id: Uuid) -> MutexGuard<'_, Shard> {
+        let idx = (room_id.as_u128() % self.inner.shards.len() as u128) as usize;
+        self.inner.shards[idx]
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Start receiving [`RoomLifecycle`] events. Only one watcher is supported;
+    /// later calls return `None`. Once watched, new room channels only count as
+    /// subscribed after [`RoomRegistry::mark_subscribed`].
+    #[cfg_attr(not(feature = "redis"), allow(dead_code))]
+    pub fn watch_lifecycle(&self) -> Option<mpsc::UnboundedReceiver<RoomLifecycle>> {
+        let (tx, rx) = mpsc::unbounded_channel();
+        self.inner.lifecycle.set(tx).ok().map(|_| rx)
+    }
+
+    /// Emitted while the shard lock is held, so events for one room arrive in
+    /// the order they happened.
+    fn emit(&self, event: RoomLifecycle) {
+        if let Some(tx) = self.inner.lifecycle.get() {
+            let _ = tx.send(event);
+        }
+    }
+
+    /// Subscribe `user_id` to `room_id`, creating the room's channel if needed.
+    ///
+    /// Subscribing and presence bookkeeping happen under the shard lock, so a
+    /// concurrent [`RoomSubscription`] drop never removes a channel that is
+    /// about to gain a subscriber.
+    pub fn join(&self, room_id: Uuid, user_id: Uuid) -> RoomSubscription {
+        let mut shard = self.shard(room_id);
+        let entry = shard.rooms.entry(room_id).or_insert_with(|| {
+            self.inner.live_channels.fetch_add(1, Ordering::Relaxed);
+            let generation = self.inner.next_generation.fetch_add(1, Ordering::Relaxed);
+            self.emit(RoomLifecycle::Opened {
+                room_id,
+                generation,
+            });
+            let (tx, _rx) = broadcast::channel(self.inner.settings.channel_capacity.max(1));
+            RoomEntry {
+                tx,
+                presence: HashMap::new(),
+                empty_since: None,
+                generation,
+                // Without a lifecycle watcher nothing needs subscribing.
+                subscribed: watch::channel(self.inner.lifecycle.get().is_none()).0,
+            }
+        });
+        entry.empty_since = None;
+        let sockets = entry.presence.entry(user_id).or_insert(0);
+        *sockets += 1;
+        if *sockets == 1 {
+            self.emit(RoomLifecycle::UserJoined { room_id, user_id });
+        }
+        let rx = entry.tx.subscribe();
+        let subscribed = entry.subscribed.subscribe();
+
+        RoomSubscription {
+            registry: self.clone(),
+            room_id,
+            user_id,
+            rx: Some(rx),
+            subscribed,
+        }
+    }
+
+    /// Record that the channel `generation` of `room_id` now receives events
+    /// from other instances. Stale generations are ignored.
+    #[cfg_attr(not(feature = "redis"), allow(dead_code))]
+    pub fn mark_subscribed(&self, room_id: Uuid, generation: u64) {
+        if let Some(entry) = self.shard(room_id).rooms.get(&room_id) {
+            if entry.generation == generation {
+                entry.subscribed.send_replace(true);
+            }
+        }
+    }
+
+    /// Mark every live channel subscribed, after subscriptions were rebuilt
+    /// from [`RoomRegistry::live_rooms`].
+    #[cfg_attr(not(feature = "redis"), allow(dead_code))]
+    pub fn mark_all_subscribed(&self, room_ids: &[Uuid]) {
+        for room_id in room_ids {
+            if let Some(entry) = self.shard(*room_id).rooms.get(room_id) {
+                entry.subscribed.send_replace(true);
+            }
+        }
+    }
+
+    fn leave(&self, room_id: Uuid, user_id: Uuid) {
+        let mut shard = self.shard(room_id);
+        let Some(entry) = shard.rooms.get_mut(&room_id) else {
+            return;
+        };
+        if let Some(count) = entry.presence.get_mut(&user_id) {
+            *count -= 1;
+            if *count == 0 {
+                entry.presence.remove(&user_id);
+                self.emit(RoomLifecycle::UserLeft { room_id, user_id });
+            }
+        }
+        if entry.tx.receiver_count() == 0 {
+            shard.rooms.remove(&room_id);
+            self.inner.live_channels.fetch_sub(1, Ordering::Relaxed);
+            self.emit(RoomLifecycle::Closed(room_id));
+            tracing::debug!("removed idle broadcast channel for room {room_id}");
+        }
+    }
+
+    /// Send `message` to sockets subscribed to `room_id` on this instance.
+    /// Returns the number of receivers; rooms without a live channel are skipped.
+    pub fn broadcast(&self, room_id: Uuid, message: OutgoingWsMessage) -> usize {
+        self.shard(room_id)
+            .rooms
+            .get(&room_id)
+            .and_then(|entry| entry.tx.send(message).ok())
+            .unwrap_or(0)
+    }
+
+    /// Tell sockets on this instance that `user_id`'s block list changed, or
+    /// with `None`, that changes relayed from other instances may have been
+    /// missed and every list should be reloaded.
+    pub fn blocks_changed(&self, user_id: Option<Uuid>) {
+        let _ = self.inner.block_changes.send(user_id);
+    }
+
+    pub fn watch_block_changes(&self) -> broadcast::Receiver<Option<Uuid>> {
+        self.inner.block_changes.subscribe()
+    }
+
+    pub fn settings(&self) -> RoomSettings {
+        self.inner.settings
+    }
+
+    /// Users with at least one open socket in `room_id` on this instance.
+    pub fn online_users(&self, room_id: Uuid) -> Vec<Uuid> {
+        self.shard(room_id)
+            .rooms
+            .get(&room_id)
+            .map(|entry| entry.presence.keys().copied().collect())
+            .unwrap_or_default()
+    }
+
+    /// Rooms with a live channel on this instance.
+    #[cfg_attr(not(feature = "redis"), allow(dead_code))]
+    pub fn live_rooms(&self) -> Vec<Uuid> {
+        self.inner
+            .shards
+            .iter()
+            .flat_map(|shard| {
+                let shard = shard.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+                shard.rooms.keys().copied().collect::<Vec<_>>()
+            })
+            .collect()
+    }
+
+    /// Every (room, user) pair with an open socket on this instance.
+    #[cfg_attr(not(feature = "redis"), allow(dead_code))]
+    pub fn presence_snapshot(&self) -> Vec<(Uuid, Uuid)> {
+        self.inner
+            .shards
+            .iter()
+            .flat_map(|shard| {
+                let shard = shard.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+                shard
+                    .rooms
+                    .iter()
+                    .flat_map(|(room_id, entry)| {
+                        entry.presence.keys().map(move |user_id| (*room_id, *user_id))
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .collect()
+    }
+
+    /// Drop rooms that have had no receivers for at least `idle_timeout`.
+    /// Returns the number of channels removed.
+    pub fn sweep(&self, idle_timeout: Duration) -> usize {
+        let now = Instant::now();
+        let mut removed = 0;
+        for shard in self.inner.shards.iter() {
+            let mut shard = shard.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+            let before = shard.rooms.len();
+            shard.rooms.retain(|room_id, entry| {
+                if entry.tx.receiver_count() > 0 {
+                    entry.empty_since = None;
+                    return true;
+                }
+                let since = *entry.empty_since.get_or_insert(now);
+                let keep = now.duration_since(since) < idle_timeout;
+                if !keep {
+                    self.emit(RoomLifecycle::Closed(*room_id));
+                }
+                keep
+            });
+            removed += before - shard.rooms.len();
+        }
+        self.inner.live_channels.fetch_sub(removed, Ordering::Relaxed);
+        removed
+    }
+
+    /// Number of room channels currently held in memory.
+    pub fn live_count(&self) -> usize {
+        self.inner.live_channels.load(Ordering::Relaxed)
+    }
+}
+
+/// A socket's membership in a live room. Dropping it unsubscribes from the
+/// room's channel, updates presence and removes the channel if it was the last
+/// subscriber.
+pub struct RoomSubscription {
+    registry: RoomRegistry,
+    room_id: Uuid,
+    user_id: Uuid,
+    rx: Option<broadcast::Receiver<OutgoingWsMessage>>,
+    subscribed: watch::Receiver<bool>,
+}
+
+impl RoomSubscription {
+    /// Wait until events from other instances reach this subscription, so
+    /// history loaded afterwards leaves no gap before live delivery. Gives up
+    /// after `timeout`; returns whether the subscription is in place.
+    pub async fn wait_subscribed(&mut self, timeout: Duration) -> bool {
+        matches!(
+            tokio::time::timeout(timeout, self.subscribed.wait_for(|subscribed| *subscribed)).await,
+            Ok(Ok(_))
+        )
+    }
+
+    pub async fn recv(&mut self) -> Result<OutgoingWsMessage, broadcast::error::RecvError> {
+        match self.rx.as_mut() {
+            Some(rx) => rx.recv().await,
+            None => Err(broadcast::error::RecvError::Closed),
+        }
+    }
+}
+
+impl Drop for RoomSubscription {
+    fn drop(&mut self) {
+        // Drop the receiver first so `leave` sees the updated receiver count.
+        self.rx.take();
+        self.registry.leave(self.room_id, self.user_id);
+    }
+}
+
+/// Spawn the background task that periodically removes idle room channels.
+///
+/// Channels are normally released as soon as their last socket disconnects;
+/// the sweeper catches anything left behind.
+pub fn spawn_sweeper(registry: RoomRegistry, interval: Duration, idle_timeout: Duration) {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            ticker.tick().await;
+            let removed = registry.sweep(idle_timeout);
+            if removed > 0 {
+                tracing::debug!("room registry sweeper removed {removed} idle channels");
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SETTINGS: RoomSettings = RoomSettings { channel_capacity: 16 };
+    const WAIT: Duration = Duration::from_millis(50);
+
+    #[tokio::test]
+    async fn rooms_are_subscribed_at_once_without_a_watcher() {
+        let registry = RoomRegistry::with_shards(SETTINGS, 4);
+        let mut subscription = registry.join(Uuid::new_v4(), Uuid::new_v4());
+        assert!(subscription.wait_subscribed(WAIT).await);
+    }
+
+    #[tokio::test]
+    async fn watched_rooms_wait_for_their_own_generation() {
+        let registry = RoomRegistry::with_shards(SETTINGS, 4);
+        let mut lifecycle = registry.watch_lifecycle().unwrap();
+        let room_id = Uuid::new_v4();
+
+        let first = registry.join(room_id, Uuid::new_v4());
+        let Some(RoomLifecycle::Opened { generation: stale, .. }) = lifecycle.recv().await else {
+            panic!("expected Opened");
+        };
+        drop(first);
+
+        let mut subscription = registry.join(room_id, Uuid::new_v4());
+        let generation = loop {
+            if let Some(RoomLifecycle::Opened { generation, .. }) = lifecycle.recv().await {
+                break generation;
+            }
+        };
+        assert!(!subscription.wait_subscribed(WAIT).await);
+
+        registry.mark_subscribed(room_id, stale);
+        assert!(!subscription.wait_subscribed(WAIT).await);
+
+        registry.mark_subscribed(room_id, generation);
+        assert!(subscription.wait_subscribed(WAIT).await);
+    }
+
+    #[test]
+    fn sweep_evicts_idle_rooms_and_keeps_live_ones() {
+        let registry = RoomRegistry::with_shards(SETTINGS, 4);
+        let idle_room = Uuid::new_v4();
+        let live_room = Uuid::new_v4();
+
+        // A channel left behind without receivers, as if its last socket
+        // went away without `leave` removing it.
+        let mut idle = registry.join(idle_room, Uuid::new_v4());
+        idle.rx.take();
+        let live = registry.join(live_room, Uuid::new_v4());
+        assert_eq!(registry.live_count(), 2);
+
+        assert_eq!(registry.sweep(Duration::ZERO), 1);
+        assert_eq!(registry.live_rooms(), vec![live_room]);
+        assert_eq!(registry.live_count(), 1);
+
+        drop(idle);
+        assert_eq!(registry.live_count(), 1);
+        drop(live);
+        assert_eq!(registry.live_count(), 0);
+    }
+}
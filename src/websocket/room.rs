@@ -1,63 +1,165 @@
+use std::{
+    collections::{HashSet, VecDeque},
+    time::Duration,
+};
+
 use axum::{
     extract::{
-        ws::{Message, WebSocket, WebSocketUpgrade},
-        Path, Query, State,
+        ws::{close_code, CloseFrame, Message, WebSocket, WebSocketUpgrade},
+        State,
     },
+    http::{header, HeaderMap},
     response::IntoResponse,
 };
 use chrono::Utc;
-use serde::Deserialize;
-use tokio::sync::broadcast;
+use serde::{Deserialize, Serialize};
+use tokio::{
+    sync::{broadcast, watch},
+    time::Instant,
+};
+use tracing::Instrument;
 use uuid::Uuid;
 
 use crate::{
-    auth::{extractor::AuthUser, jwt::validate_token},
+    auth::{jwt::validate_token, session::ClientInfo, ws_ticket::redeem_ticket},
+    config::LagPolicy,
     db::{
-        messages::{create_message, list_recent_messages_with_usernames},
+        blocks::list_blocked_user_ids,
+        messages::{create_message, list_messages_after, list_recent_messages_with_usernames},
         rooms::get_room_if_member,
+        sessions::touch_session,
+        users::get_user_profile,
     },
     error::AppError,
-    models::message::{
-        IncomingWsMessage, OutgoingWsMessage, WsMessageKind, MAX_MESSAGE_LEN,
+    extract::{Path, Query},
+    link_previews::spawn_link_previews,
+    models::{
+        attachment::MAX_ATTACHMENTS_PER_MESSAGE,
+        message::{GapEvent, IncomingWsMessage, OutgoingWsMessage, WsMessageKind, MAX_MESSAGE_LEN},
     },
     state::AppState,
 };
 
+/// Close code sent to clients dropped under `LagPolicy::Disconnect`.
+const CLOSE_SLOW_CONSUMER: u16 = 4008;
+
+/// Close code sent when the session or token the socket was opened with is
+/// revoked.
+const CLOSE_TOKEN_REVOKED: u16 = 4001;
+
+/// Messages sent as history when a socket connects.
+const HISTORY_LIMIT: i64 = 50;
+
+/// How long a socket waits for its room's cross-instance subscription.
+const SUBSCRIBE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Upper bound on messages replayed to a lagged client under `LagPolicy::Resync`.
+const MAX_RESYNC_MESSAGES: i64 = 1000;
+
+/// Subprotocol the server agrees to. Clients that authenticate through
+/// `Sec-WebSocket-Protocol` must offer it, since a browser drops the connection
+/// unless the server picks one of the offered protocols.
+const WS_PROTOCOL: &str = "chat";
+
+/// Prefix of the `Sec-WebSocket-Protocol` entry carrying a ticket.
+const TICKET_PROTOCOL_PREFIX: &str = "ticket.";
+
 /// Query params for WebSocket connect (browsers cannot set Authorization header on WS).
 #[derive(Debug, Deserialize)]
 pub struct WsConnectQuery {
+    /// Ticket from `POST /api/ws/ticket`.
+    pub ticket: Option<String>,
+    /// Access token. Deprecated: URLs end up in logs; use a ticket instead.
     pub token: Option<String>,
 }
 
+/// Who a socket belongs to.
+struct SocketUser {
+    user_id: Uuid,
+    username: String,
+    session_id: Uuid,
+    /// `jti` of the access token the socket was opened with; `None` when it
+    /// was opened with a ticket.
+    token_id: Option<Uuid>,
+}
+
 pub async fn room_ws_handler(
     ws: WebSocketUpgrade,
     Path(room_id): Path<Uuid>,
     State(state): State<AppState>,
     Query(q): Query<WsConnectQuery>,
+    headers: HeaderMap,
+    client: ClientInfo,
 ) -> Result<impl IntoResponse, AppError> {
-    let token = q
-        .token
-        .as_deref()
-        .filter(|s| !s.is_empty())
-        .ok_or_else(|| AppError::Unauthorized("missing token (use ?token=JWT)".into()))?;
-    let claims = validate_token(&state, token)?;
-    let auth = AuthUser {
+    let auth = authenticate(&state, room_id, &q, &headers).await?;
+    // Opening a socket counts as activity on the session.
+    touch_session(&state.db, auth.session_id, client.ip.as_deref()).await?;
+    let span = tracing::info_span!(
+        "ws_connection",
+        %room_id,
+        user_id = %auth.user_id,
+        disconnect_reason = tracing::field::Empty,
+    );
+    Ok(ws
+        .protocols([WS_PROTOCOL])
+        .on_upgrade(move |socket| handle_socket(socket, state, room_id, auth).instrument(span)))
+}
+
+/// Accepts, in order: a ticket in `Sec-WebSocket-Protocol` (`ticket.<ticket>`)
+/// or in `?ticket=`, an `Authorization: Bearer` header (non-browser clients),
+/// and the deprecated `?token=`.
+async fn authenticate(
+    state: &AppState,
+    room_id: Uuid,
+    q: &WsConnectQuery,
+    headers: &HeaderMap,
+) -> Result<SocketUser, AppError> {
+    let protocol_ticket = headers
+        .get_all(header::SEC_WEBSOCKET_PROTOCOL)
+        .iter()
+        .filter_map(|h| h.to_str().ok())
+        .flat_map(|h| h.split(','))
+        .find_map(|p| p.trim().strip_prefix(TICKET_PROTOCOL_PREFIX));
+    let non_empty = |s: &&str| !s.is_empty();
+    if let Some(ticket) = protocol_ticket.or(q.ticket.as_deref()).filter(non_empty) {
+        let ticket = redeem_ticket(state, ticket, room_id).await?;
+        return Ok(SocketUser {
+            user_id: ticket.user_id,
+            username: ticket.username,
+            session_id: ticket.session_id,
+            token_id: None,
+        });
+    }
+
+    let bearer = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|h| h.to_str().ok())
+        .and_then(|h| h.strip_prefix("Bearer "));
+    let token = bearer
+        .or(q.token.as_deref())
+        .filter(non_empty)
+        .ok_or_else(|| {
+            AppError::Unauthorized("missing ticket (see POST /api/ws/ticket)".into())
+        })?;
+    let claims = validate_token(state, token)?;
+    Ok(SocketUser {
         user_id: claims.sub,
-        username: claims.username.clone(),
-        claims,
-    };
-    Ok(ws.on_upgrade(move |socket| handle_socket(socket, state, room_id, auth)))
+        username: claims.username,
+        session_id: claims.sid,
+        token_id: Some(claims.jti),
+    })
 }
 
 async fn handle_socket(
     mut socket: WebSocket,
     state: AppState,
     room_id: Uuid,
-    auth: AuthUser,
+    auth: SocketUser,
 ) {
     // Ensure room exists and user is a member.
     if let Err(e) = ensure_room_exists(&state, room_id).await {
         tracing::warn!("refusing WS connection for missing room: {e}");
+        tracing::Span::current().record("disconnect_reason", "room not found");
         let _ = socket.send(Message::Close(None)).await;
         return;
     }
@@ -68,88 +170,196 @@ async fn handle_socket(
         .is_none()
     {
         tracing::warn!("refusing WS connection: user {} not a member of room {}", auth.user_id, room_id);
+        tracing::Span::current().record("disconnect_reason", "not a member");
         let _ = socket.send(Message::Close(None)).await;
         return;
     }
 
-    // Get or create broadcast channel for this room.
-    let tx = {
-        let mut rooms = state.rooms.write().await;
-        rooms
-            .entry(room_id)
-            .or_insert_with(|| {
-                let (tx, _rx) = broadcast::channel(100);
-                tx
-            })
-            .clone()
-    };
-    let mut rx = tx.subscribe();
+    // Join the room's live channel (created on first connect, removed when the
+    // subscription is dropped by the last socket).
+    let mut subscription = state.rooms.join(room_id, auth.user_id);
+    // Load history only once events from other instances reach the channel,
+    // so nothing sent in between is missed.
+    if !subscription.wait_subscribed(SUBSCRIBE_TIMEOUT).await {
+        tracing::warn!("room channel not subscribed across instances yet; events may be missed");
+    }
+
+    // Copies of a message can arrive twice: from history or a resync and from
+    // the channel. Messages may also arrive out of `created_at` order (commit
+    // and relay order differ), so only ids tell duplicates apart.
+    let mut delivered = DeliveredIds::new(
+        state.rooms.settings().channel_capacity + HISTORY_LIMIT as usize,
+    );
 
     // Send last 50 messages as history (with usernames).
-    if let Err(err) = send_recent_history(&state, room_id, &mut socket).await {
-        tracing::warn!("failed to send history to ws client: {err}");
-    }
+    let mut last_delivered = match send_recent_history(&state, room_id, auth.user_id, &mut socket, &mut delivered).await {
+        Ok(newest) => newest,
+        Err(err) => {
+            tracing::warn!("failed to send history to ws client: {err}");
+            None
+        }
+    };
+    // Messages skipped since the last delivery, reported as a `gap` event
+    // before the next delivery under `LagPolicy::Gap`.
+    let mut pending_gap: Option<u64> = None;
 
     // Broadcast "user joined" system message.
-    let _ = tx.send(OutgoingWsMessage {
-        id: Uuid::nil(),
-        room_id,
-        user_id: Uuid::nil(),
-        username: auth.username.clone(),
-        content: "joined the room".to_string(),
-        created_at: Utc::now(),
-        kind: WsMessageKind::System,
-    });
-
-    let username_on_leave = auth.username.clone();
-
-    loop {
+    send_system_message(&state, room_id, &auth, "joined the room").await;
+
+    let mut heartbeat = tokio::time::interval(state.ws_heartbeat.ping_interval);
+    heartbeat.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    heartbeat.reset();
+    // Set when a ping is sent; cleared by any frame from the client.
+    let mut awaiting_pong_since: Option<Instant> = None;
+    let mut revoked = state.revocations.watch(auth.session_id);
+    // Without a token to watch the sender is dropped at once, which disables
+    // the arm below.
+    let mut token_revoked = match auth.token_id {
+        Some(jti) => state.revocations.watch(jti),
+        None => watch::channel(false).1,
+    };
+    // Senders whose messages this socket drops. Reloaded whenever the block
+    // list changes, on any instance.
+    let mut block_changes = state.rooms.watch_block_changes();
+    let mut blocked = HashSet::new();
+    reload_blocked_users(&state, auth.user_id, &mut blocked).await;
+
+    let reason = loop {
+        let pong_deadline = awaiting_pong_since
+            .map(|sent| sent + state.ws_heartbeat.pong_timeout)
+            .unwrap_or_else(Instant::now);
+
         tokio::select! {
             maybe_msg = socket.recv() => {
                 match maybe_msg {
                     Some(Ok(msg)) => {
-                        if let Err(e) = handle_incoming_message(&state, room_id, &auth, msg, &tx).await {
+                        awaiting_pong_since = None;
+                        if let Err(e) = handle_incoming_message(&state, room_id, &auth, msg).await {
                             tracing::warn!("error handling incoming ws message: {e}");
                         }
                     }
                     Some(Err(err)) => {
                         tracing::warn!("websocket receive error: {err}");
-                        send_leave_system_message(&tx, room_id, &username_on_leave);
-                        break;
-                    }
-                    None => {
-                        send_leave_system_message(&tx, room_id, &username_on_leave);
-                        break;
+                        break "receive error";
                     }
+                    None => break "client closed",
                 }
             }
-            broadcast_msg = rx.recv() => {
+            broadcast_msg = subscription.recv() => {
                 match broadcast_msg {
                     Ok(outgoing) => {
-                        let json: String = match serde_json::to_string(&outgoing) {
-                            Ok(j) => j,
-                            Err(err) => {
-                                tracing::error!("failed to serialize outgoing ws message: {err}");
+                        if blocked.contains(&outgoing.user_id) {
+                            continue;
+                        }
+                        if let Some(skipped) = pending_gap.take() {
+                            let gap = GapEvent {
+                                kind: WsMessageKind::Gap,
+                                room_id,
+                                after_id: last_delivered,
+                                before_id: matches!(outgoing.kind, WsMessageKind::Message)
+                                    .then_some(outgoing.id),
+                                skipped,
+                            };
+                            if !send_event(&mut socket, &gap).await {
+                                break "send failed";
+                            }
+                        }
+                        if matches!(outgoing.kind, WsMessageKind::Message) {
+                            // Skip anything history or a resync already delivered.
+                            if !delivered.insert(outgoing.id) {
                                 continue;
                             }
-                        };
-                        if socket.send(Message::Text(json.into())).await.is_err() {
-                            send_leave_system_message(&tx, room_id, &username_on_leave);
-                            break;
+                            last_delivered = Some(outgoing.id);
+                        }
+                        if !send_event(&mut socket, &outgoing).await {
+                            break "send failed";
                         }
                     }
                     Err(broadcast::error::RecvError::Lagged(skipped)) => {
-                        tracing::warn!("websocket client lagged; skipped {skipped} messages");
-                        continue;
+                        tracing::warn!(
+                            policy = ?state.lag_policy,
+                            "websocket client lagged; skipped {skipped} messages"
+                        );
+                        match state.lag_policy {
+                            LagPolicy::Disconnect => {
+                                let _ = socket
+                                    .send(Message::Close(Some(CloseFrame {
+                                        code: CLOSE_SLOW_CONSUMER,
+                                        reason: "slow consumer".into(),
+                                    })))
+                                    .await;
+                                break "slow consumer";
+                            }
+                            LagPolicy::Resync => {
+                                match resync_after_lag(&state, room_id, auth.user_id, &mut socket, &mut delivered, last_delivered, skipped).await {
+                                    Ok(newest) => last_delivered = newest.or(last_delivered),
+                                    Err(err) => {
+                                        tracing::warn!("failed to resync lagged ws client: {err}");
+                                        pending_gap = Some(pending_gap.unwrap_or(0) + skipped);
+                                    }
+                                }
+                            }
+                            LagPolicy::Gap => {
+                                pending_gap = Some(pending_gap.unwrap_or(0) + skipped);
+                            }
+                        }
                     }
-                    Err(broadcast::error::RecvError::Closed) => {
-                        send_leave_system_message(&tx, room_id, &username_on_leave);
-                        break;
+                    Err(broadcast::error::RecvError::Closed) => break "room channel closed",
+                }
+            }
+            _ = heartbeat.tick() => {
+                if awaiting_pong_since.is_none() {
+                    if socket.send(Message::Ping(Default::default())).await.is_err() {
+                        break "send failed";
+                    }
+                    awaiting_pong_since = Some(Instant::now());
+                }
+            }
+            _ = tokio::time::sleep_until(pong_deadline), if awaiting_pong_since.is_some() => {
+                let _ = socket
+                    .send(Message::Close(Some(CloseFrame {
+                        code: close_code::AWAY,
+                        reason: "pong timeout".into(),
+                    })))
+                    .await;
+                break "pong timeout";
+            }
+            changed = block_changes.recv() => {
+                match changed {
+                    Ok(Some(user_id)) if user_id != auth.user_id => {}
+                    // Also reload if notifications were missed.
+                    Ok(_) | Err(broadcast::error::RecvError::Lagged(_)) => {
+                        reload_blocked_users(&state, auth.user_id, &mut blocked).await;
                     }
+                    Err(broadcast::error::RecvError::Closed) => {}
                 }
             }
+            Ok(()) = async { revoked.wait_for(|revoked| *revoked).await.map(drop) } => {
+                let _ = socket
+                    .send(Message::Close(Some(CloseFrame {
+                        code: CLOSE_TOKEN_REVOKED,
+                        reason: "session revoked".into(),
+                    })))
+                    .await;
+                break "session revoked";
+            }
+            Ok(()) = async { token_revoked.wait_for(|revoked| *revoked).await.map(drop) } => {
+                let _ = socket
+                    .send(Message::Close(Some(CloseFrame {
+                        code: CLOSE_TOKEN_REVOKED,
+                        reason: "token revoked".into(),
+                    })))
+                    .await;
+                break "token revoked";
+            }
         }
-    }
+    };
+
+    tracing::Span::current().record("disconnect_reason", reason);
+    tracing::info!("websocket disconnected: {reason}");
+    send_system_message(&state, room_id, &auth, "left the room").await;
+    // Dropping the subscription removes this socket from the room's presence.
+    drop(subscription);
 }
 
 async fn ensure_room_exists(state: &AppState, room_id: Uuid) -> Result<(), AppError> {
@@ -167,62 +377,132 @@ async fn ensure_room_exists(state: &AppState, room_id: Uuid) -> Result<(), AppEr
     Ok(())
 }
 
-fn send_leave_system_message(
-    tx: &broadcast::Sender<OutgoingWsMessage>,
+/// Replace `blocked` with the users `user_id` has blocked. On a database
+/// error the current list is kept.
+async fn reload_blocked_users(state: &AppState, user_id: Uuid, blocked: &mut HashSet<Uuid>) {
+    match list_blocked_user_ids(&state.db, user_id).await {
+        Ok(ids) => *blocked = ids,
+        Err(err) => tracing::warn!("failed to load blocked users: {err}"),
+    }
+}
+
+/// Announce `auth` joining or leaving, under their current profile.
+async fn send_system_message(state: &AppState, room_id: Uuid, auth: &SocketUser, content: &str) {
+    let profile = match get_user_profile(&state.db, auth.user_id).await {
+        Ok(profile) => profile,
+        Err(err) => {
+            tracing::warn!("failed to load profile for system message: {err}");
+            None
+        }
+    };
+    let (username, display_name, avatar_url) = match profile {
+        Some(p) => (p.username, p.display_name, p.avatar_url),
+        None => (auth.username.clone(), None, None),
+    };
+    state
+        .broadcaster
+        .publish(OutgoingWsMessage {
+            id: Uuid::nil(),
+            room_id,
+            user_id: Uuid::nil(),
+            username,
+            display_name,
+            avatar_url,
+            content: content.to_string(),
+            attachments: Vec::new(),
+            previews: Vec::new(),
+            created_at: Utc::now(),
+            kind: WsMessageKind::System,
+        })
+        .await;
+}
+
+/// Serialize `event` and send it as a text frame. Returns `false` once the
+/// socket is gone.
+async fn send_event<T: Serialize>(socket: &mut WebSocket, event: &T) -> bool {
+    let json: String = match serde_json::to_string(event) {
+        Ok(j) => j,
+        Err(err) => {
+            tracing::error!("failed to serialize outgoing ws message: {err}");
+            return true;
+        }
+    };
+    socket.send(Message::Text(json.into())).await.is_ok()
+}
+
+/// Re-send messages a lagged client missed, straight from the database,
+/// leaving out those it already has. Returns the id of the last message sent.
+async fn resync_after_lag(
+    state: &AppState,
     room_id: Uuid,
-    username: &str,
-) {
-    let _ = tx.send(OutgoingWsMessage {
-        id: Uuid::nil(),
-        room_id,
-        user_id: Uuid::nil(),
-        username: username.to_string(),
-        content: "left the room".to_string(),
-        created_at: Utc::now(),
-        kind: WsMessageKind::System,
-    });
+    viewer_id: Uuid,
+    socket: &mut WebSocket,
+    delivered: &mut DeliveredIds,
+    last_delivered: Option<Uuid>,
+    skipped: u64,
+) -> Result<Option<Uuid>, AppError> {
+    let Some(after) = last_delivered else {
+        return send_recent_history(state, room_id, viewer_id, socket, delivered).await;
+    };
+
+    // Everything skipped plus what is still buffered in the channel; the
+    // buffered copies are dropped by the caller's dedupe.
+    let limit = (skipped as i64 + state.rooms.settings().channel_capacity as i64)
+        .min(MAX_RESYNC_MESSAGES);
+    let messages = list_messages_after(&state.db, room_id, viewer_id, after, limit).await?;
+
+    let mut newest = None;
+    for m in messages {
+        if !delivered.insert(m.id) {
+            continue;
+        }
+        newest = Some(m.id);
+        let outgoing = OutgoingWsMessage::stored(m, WsMessageKind::Message);
+        if !send_event(socket, &outgoing).await {
+            break;
+        }
+    }
+
+    Ok(newest)
 }
 
+/// Returns the id of the newest message sent.
 async fn send_recent_history(
     state: &AppState,
     room_id: Uuid,
+    viewer_id: Uuid,
     socket: &mut WebSocket,
-) -> Result<(), AppError> {
-    let messages = list_recent_messages_with_usernames(&state.db, room_id, 50).await?;
+    delivered: &mut DeliveredIds,
+) -> Result<Option<Uuid>, AppError> {
+    let messages =
+        list_recent_messages_with_usernames(&state.db, room_id, viewer_id, HISTORY_LIMIT).await?;
+    let newest = messages.first().map(|m| m.id);
 
     for m in messages.into_iter().rev() {
-        let outgoing = OutgoingWsMessage {
-            id: m.id,
-            room_id: m.room_id,
-            user_id: m.user_id,
-            username: m.username,
-            content: m.content,
-            created_at: m.created_at,
-            kind: WsMessageKind::History,
-        };
+        delivered.insert(m.id);
+        let outgoing = OutgoingWsMessage::stored(m, WsMessageKind::History);
         let json: String = serde_json::to_string(&outgoing)
             .map_err(|e| AppError::Internal(e.into()))?;
         socket.send(Message::Text(json.into())).await.ok();
     }
 
-    Ok(())
+    Ok(newest)
 }
 
 async fn handle_incoming_message(
     state: &AppState,
     room_id: Uuid,
-    auth: &AuthUser,
+    auth: &SocketUser,
     msg: Message,
-    tx: &broadcast::Sender<OutgoingWsMessage>,
 ) -> Result<(), AppError> {
-    let content = match msg {
+    let (content, attachment_ids) = match msg {
         Message::Text(text) => {
             let text = text.to_string();
             // Try to parse as JSON payload first, fall back to raw text.
             if let Ok(parsed) = serde_json::from_str::<IncomingWsMessage>(&text) {
-                parsed.content
+                (parsed.content, parsed.attachment_ids)
             } else {
-                text
+                (text, Vec::new())
             }
         }
         Message::Binary(_) => {
@@ -237,10 +517,10 @@ async fn handle_incoming_message(
         }
     };
 
-    if content.trim().is_empty() {
+    if content.trim().is_empty() && attachment_ids.is_empty() {
         return Ok(());
     }
-    if content.len() > MAX_MESSAGE_LEN {
+    if content.len() > MAX_MESSAGE_LEN || attachment_ids.len() > MAX_ATTACHMENTS_PER_MESSAGE {
         return Ok(());
     }
 
@@ -252,20 +532,100 @@ async fn handle_incoming_message(
         return Err(AppError::Forbidden("not a member of this room".into()));
     }
 
-    let message = create_message(&state.db, room_id, auth.user_id, &content).await?;
+    let message = create_message(&state.db, room_id, auth.user_id, &content, &attachment_ids).await?;
+    let message_id = message.id;
 
-    let outgoing = OutgoingWsMessage {
-        id: message.id,
-        room_id: message.room_id,
-        user_id: message.user_id,
-        username: auth.username.clone(),
-        content: message.content,
-        created_at: message.created_at,
-        kind: WsMessageKind::Message,
-    };
+    let outgoing = OutgoingWsMessage::stored(message, WsMessageKind::Message);
 
-    let _ = tx.send(outgoing);
+    state.broadcaster.publish(outgoing).await;
+    spawn_link_previews(state, message_id, &content);
 
     Ok(())
 }
 
+/// Ids of the messages most recently sent to a socket, oldest forgotten
+/// first.
+struct DeliveredIds {
+    order: VecDeque<Uuid>,
+    ids: HashSet<Uuid>,
+    capacity: usize,
+}
+
+impl DeliveredIds {
+    fn new(capacity: usize) -> Self {
+        Self {
+            order: VecDeque::with_capacity(capacity),
+            ids: HashSet::with_capacity(capacity),
+            capacity: capacity.max(1),
+        }
+    }
+
+    /// Record `id` as sent. `false` if it already was.
+    fn insert(&mut self, id: Uuid) -> bool {
+        if !self.ids.insert(id) {
+            return false;
+        }
+        self.order.push_back(id);
+        if self.order.len() > self.capacity {
+            if let Some(oldest) = self.order.pop_front() {
+                self.ids.remove(&oldest);
+            }
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::DateTime;
+
+    use super::*;
+
+    fn message(created_at: DateTime<Utc>) -> OutgoingWsMessage {
+        OutgoingWsMessage {
+            id: Uuid::new_v4(),
+            room_id: Uuid::nil(),
+            user_id: Uuid::new_v4(),
+            username: "alice".to_string(),
+            display_name: None,
+            avatar_url: None,
+            content: "hi".to_string(),
+            attachments: Vec::new(),
+            previews: Vec::new(),
+            created_at,
+            kind: WsMessageKind::Message,
+        }
+    }
+
+    #[test]
+    fn messages_out_of_created_at_order_are_both_delivered() {
+        let now = Utc::now();
+        let newer = message(now);
+        let older = message(now - chrono::Duration::seconds(1));
+        let mut delivered = DeliveredIds::new(8);
+
+        assert!(delivered.insert(newer.id));
+        assert!(delivered.insert(older.id));
+    }
+
+    #[test]
+    fn repeated_messages_are_dropped() {
+        let first = message(Utc::now());
+        let mut delivered = DeliveredIds::new(8);
+
+        assert!(delivered.insert(first.id));
+        assert!(!delivered.insert(first.id));
+    }
+
+    #[test]
+    fn oldest_ids_are_forgotten_past_capacity() {
+        let mut delivered = DeliveredIds::new(2);
+        let ids: Vec<Uuid> = (0..3).map(|_| Uuid::new_v4()).collect();
+        for id in &ids {
+            assert!(delivered.insert(*id));
+        }
+
+        assert!(!delivered.insert(ids[2]));
+        assert!(delivered.insert(ids[0]));
+    }
+}
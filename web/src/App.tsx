@@ -1,11 +1,12 @@
 import { useMemo } from 'react'
 import { LogOut, MessageSquare } from 'lucide-react'
-import { useAuth, useRooms, useRoomWebSocket } from './hooks'
+import { useAuth, useProfile, useRooms, useRoomWebSocket } from './hooks'
 import { AuthScreen, Avatar, ChatArea, Sidebar } from './components'
 import './App.css'
 
 export default function App() {
   const auth = useAuth()
+  const profile = useProfile(auth.auth)
   const rooms = useRooms(auth.auth)
   const ws = useRoomWebSocket(auth.auth, rooms.selectedRoomId)
 
@@ -30,6 +31,11 @@ export default function App() {
         setPassword={auth.setPassword}
         error={auth.error}
         setError={auth.setError}
+        challenge={auth.challenge}
+        providers={auth.providers}
+        code={auth.code}
+        setCode={auth.setCode}
+        cancelChallenge={auth.cancelChallenge}
         onSubmit={auth.handleSubmit}
       />
     )
@@ -46,8 +52,14 @@ export default function App() {
         </div>
         <div className="app-user">
           <div className="user-badge">
-            <Avatar name={auth.auth.username} isSelf />
-            <span className="username">@{auth.auth.username}</span>
+            <Avatar
+              name={profile?.display_name || auth.auth.username}
+              src={profile?.avatar_url}
+              isSelf
+            />
+            <span className="username" title={`@${auth.auth.username}`}>
+              {profile?.display_name || `@${auth.auth.username}`}
+            </span>
           </div>
           <button type="button" className="icon-btn ghost" onClick={handleLogout} title="Log out">
             <LogOut size={18} />
@@ -73,7 +85,7 @@ export default function App() {
           messages={ws.messages}
           status={ws.status}
           wsError={ws.error}
-          onSendMessage={(content) => ws.sendMessage(content)}
+          onSendMessage={(content, attachmentIds) => ws.sendMessage(content, attachmentIds)}
         />
       </main>
     </div>
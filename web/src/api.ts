@@ -1,5 +1,28 @@
 import { API_BASE, WS_BASE } from './config'
-import type { AuthState, LoginResponse, Room } from './types'
+import type {
+  Attachment,
+  AuthState,
+  BlockedUser,
+  LoginResponse,
+  OidcProvider,
+  ProfileUpdate,
+  Room,
+  TwoFactorChallenge,
+  UserProfile,
+  UserSummary,
+} from './types'
+
+/** The `error` message from a failed response's body, or `fallback`. */
+async function errorMessage(res: Response, fallback: string): Promise<string> {
+  const text = await res.text()
+  try {
+    const body = JSON.parse(text) as { error?: string }
+    if (body.error) return body.error
+  } catch {
+    // Not JSON (e.g. a rejected path parameter); use the text as is.
+  }
+  return text || fallback
+}
 
 async function authFetch(path: string, init: RequestInit, token: string): Promise<Response> {
   return fetch(`${API_BASE}${path}`, {
@@ -11,13 +34,48 @@ async function authFetch(path: string, init: RequestInit, token: string): Promis
   })
 }
 
-export async function login(username: string, password: string): Promise<LoginResponse> {
+export async function login(
+  username: string,
+  password: string,
+): Promise<LoginResponse | TwoFactorChallenge> {
   const res = await fetch(`${API_BASE}/api/auth/login`, {
     method: 'POST',
     headers: { 'Content-Type': 'application/json' },
     body: JSON.stringify({ username, password }),
   })
-  if (!res.ok) throw new Error(await res.text() || `Login failed: ${res.status}`)
+  if (!res.ok) throw new Error(await errorMessage(res, `Login failed: ${res.status}`))
+  return res.json()
+}
+
+export async function loginTwoFactor(challengeToken: string, code: string): Promise<LoginResponse> {
+  const res = await fetch(`${API_BASE}/api/auth/login/2fa`, {
+    method: 'POST',
+    headers: { 'Content-Type': 'application/json' },
+    body: JSON.stringify({ challenge_token: challengeToken, code }),
+  })
+  if (!res.ok) throw new Error(await errorMessage(res, `Login failed: ${res.status}`))
+  return res.json()
+}
+
+export async function fetchOidcProviders(): Promise<OidcProvider[]> {
+  const res = await fetch(`${API_BASE}/api/auth/oidc/providers`)
+  if (!res.ok) throw new Error(`Failed to load sign-in providers: ${res.status}`)
+  return res.json()
+}
+
+/** Full-page navigation target that starts single sign-on with `provider`. */
+export function oidcAuthorizeUrl(provider: string): string {
+  return `${API_BASE}/api/auth/oidc/${encodeURIComponent(provider)}/authorize`
+}
+
+/** Exchange the grant the server appends to the post-sign-on redirect. */
+export async function exchangeOidcGrant(grant: string): Promise<LoginResponse | TwoFactorChallenge> {
+  const res = await fetch(`${API_BASE}/api/auth/oidc/exchange`, {
+    method: 'POST',
+    headers: { 'Content-Type': 'application/json' },
+    body: JSON.stringify({ grant }),
+  })
+  if (!res.ok) throw new Error(await errorMessage(res, `Sign-in failed: ${res.status}`))
   return res.json()
 }
 
@@ -27,10 +85,25 @@ export async function register(username: string, password: string): Promise<Logi
     headers: { 'Content-Type': 'application/json' },
     body: JSON.stringify({ username, password }),
   })
-  if (!res.ok) throw new Error(await res.text() || `Register failed: ${res.status}`)
+  if (!res.ok) throw new Error(await errorMessage(res, `Register failed: ${res.status}`))
+  return res.json()
+}
+
+export async function refreshSession(refreshToken: string): Promise<LoginResponse> {
+  const res = await fetch(`${API_BASE}/api/auth/refresh`, {
+    method: 'POST',
+    headers: { 'Content-Type': 'application/json' },
+    body: JSON.stringify({ refresh_token: refreshToken }),
+  })
+  if (!res.ok) throw new Error(await errorMessage(res, `Refresh failed: ${res.status}`))
   return res.json()
 }
 
+/** Revoke the session server-side. Best effort: local state is cleared regardless. */
+export async function logout(auth: AuthState): Promise<void> {
+  await authFetch('/api/auth/logout', { method: 'POST' }, auth.token)
+}
+
 export async function fetchRooms(auth: AuthState): Promise<Room[]> {
   const res = await authFetch('/api/rooms', {}, auth.token)
   if (!res.ok) throw new Error(`Failed to load rooms: ${res.status}`)
@@ -43,16 +116,113 @@ export async function createRoom(auth: AuthState, name: string): Promise<Room> {
     headers: { 'Content-Type': 'application/json' },
     body: JSON.stringify({ name }),
   }, auth.token)
-  if (!res.ok) throw new Error(await res.text() || `Failed to create room: ${res.status}`)
+  if (!res.ok) throw new Error(await errorMessage(res, `Failed to create room: ${res.status}`))
   return res.json()
 }
 
 export async function joinRoom(auth: AuthState, roomId: string): Promise<Room> {
   const res = await authFetch(`/api/rooms/${roomId}/join`, { method: 'POST' }, auth.token)
-  if (!res.ok) throw new Error(await res.text() || `Failed to join room: ${res.status}`)
+  if (!res.ok) throw new Error(await errorMessage(res, `Failed to join room: ${res.status}`))
+  return res.json()
+}
+
+/** Upload a file to a room; send it by passing its id with a message. */
+export async function uploadAttachment(auth: AuthState, roomId: string, file: File): Promise<Attachment> {
+  const body = new FormData()
+  body.append('file', file)
+  const res = await authFetch(`/api/rooms/${roomId}/attachments`, { method: 'POST', body }, auth.token)
+  if (!res.ok) throw new Error(await errorMessage(res, `Failed to upload file: ${res.status}`))
+  return res.json()
+}
+
+export async function fetchAttachment(auth: AuthState, attachmentId: string): Promise<Blob> {
+  const res = await authFetch(`/api/attachments/${attachmentId}`, {}, auth.token)
+  if (!res.ok) throw new Error(await errorMessage(res, `Failed to load file: ${res.status}`))
+  return res.blob()
+}
+
+export async function fetchThumbnail(auth: AuthState, attachmentId: string, size: number): Promise<Blob> {
+  const res = await authFetch(`/api/attachments/${attachmentId}/thumbnails/${size}`, {}, auth.token)
+  if (!res.ok) throw new Error(await errorMessage(res, `Failed to load thumbnail: ${res.status}`))
+  return res.blob()
+}
+
+export async function fetchProfile(auth: AuthState): Promise<UserProfile> {
+  const res = await authFetch('/api/me/profile', {}, auth.token)
+  if (!res.ok) throw new Error(await errorMessage(res, `Failed to load profile: ${res.status}`))
+  return res.json()
+}
+
+export async function updateProfile(auth: AuthState, update: ProfileUpdate): Promise<UserProfile> {
+  const res = await authFetch('/api/me/profile', {
+    method: 'PATCH',
+    headers: { 'Content-Type': 'application/json' },
+    body: JSON.stringify(update),
+  }, auth.token)
+  if (!res.ok) throw new Error(await errorMessage(res, `Failed to update profile: ${res.status}`))
+  return res.json()
+}
+
+/** Users whose username or display name starts with `query`; pass the last username to get the next page. */
+export async function searchUsers(auth: AuthState, query: string, after?: string): Promise<UserSummary[]> {
+  const params = new URLSearchParams({ q: query })
+  if (after) params.set('after', after)
+  const res = await authFetch(`/api/users?${params}`, {}, auth.token)
+  if (!res.ok) throw new Error(await errorMessage(res, `Failed to search users: ${res.status}`))
+  return res.json()
+}
+
+/** Everything the server stores about the signed-in user. */
+export async function exportAccount(auth: AuthState): Promise<Blob> {
+  const res = await authFetch('/api/me/export', {}, auth.token)
+  if (!res.ok) throw new Error(await errorMessage(res, `Failed to export account: ${res.status}`))
+  return res.blob()
+}
+
+/** Permanently delete the account. `code` is needed when 2FA is on. */
+export async function deleteAccount(auth: AuthState, password?: string, code?: string): Promise<void> {
+  const res = await authFetch('/api/me', {
+    method: 'DELETE',
+    headers: { 'Content-Type': 'application/json' },
+    body: JSON.stringify({ password, code }),
+  }, auth.token)
+  if (!res.ok) throw new Error(await errorMessage(res, `Failed to delete account: ${res.status}`))
+}
+
+export async function fetchBlockedUsers(auth: AuthState): Promise<BlockedUser[]> {
+  const res = await authFetch('/api/me/blocks', {}, auth.token)
+  if (!res.ok) throw new Error(await errorMessage(res, `Failed to load blocked users: ${res.status}`))
   return res.json()
 }
 
-export function buildWsUrl(roomId: string, token: string): string {
-  return `${WS_BASE}/ws/rooms/${roomId}?token=${encodeURIComponent(token)}`
+/** Hide a user's messages from the signed-in user. */
+export async function blockUser(auth: AuthState, userId: string): Promise<void> {
+  const res = await authFetch(`/api/me/blocks/${userId}`, { method: 'POST' }, auth.token)
+  if (!res.ok) throw new Error(await errorMessage(res, `Failed to block user: ${res.status}`))
+}
+
+export async function unblockUser(auth: AuthState, userId: string): Promise<void> {
+  const res = await authFetch(`/api/me/blocks/${userId}`, { method: 'DELETE' }, auth.token)
+  if (!res.ok) throw new Error(await errorMessage(res, `Failed to unblock user: ${res.status}`))
+}
+
+/** Single-use ticket for opening the room's WebSocket; expires in seconds. */
+export async function createWsTicket(token: string, roomId: string): Promise<string> {
+  const res = await authFetch('/api/ws/ticket', {
+    method: 'POST',
+    headers: { 'Content-Type': 'application/json' },
+    body: JSON.stringify({ room_id: roomId }),
+  }, token)
+  if (!res.ok) throw new Error(await errorMessage(res, `Failed to open connection: ${res.status}`))
+  const data: { ticket: string } = await res.json()
+  return data.ticket
+}
+
+export function buildWsUrl(roomId: string): string {
+  return `${WS_BASE}/ws/rooms/${roomId}`
+}
+
+/** Subprotocols to open a room socket with; the ticket travels in the handshake, not the URL. */
+export function wsProtocols(ticket: string): string[] {
+  return ['chat', `ticket.${ticket}`]
 }
@@ -0,0 +1,91 @@
+import { useEffect, useState } from 'react'
+import { FileText } from 'lucide-react'
+import { fetchAttachment, fetchThumbnail } from '../api'
+import type { Attachment, AuthState } from '../types'
+
+type Props = {
+  auth: AuthState
+  attachment: Attachment
+}
+
+/** Longest edge images are shown at in the message list. */
+const DISPLAY_SIZE = 320
+
+function formatSize(bytes: number): string {
+  if (bytes < 1024) return `${bytes} B`
+  if (bytes < 1024 * 1024) return `${(bytes / 1024).toFixed(1)} KB`
+  return `${(bytes / (1024 * 1024)).toFixed(1)} MB`
+}
+
+/** Downloads need the bearer token, so files are fetched and shown from a blob URL. */
+export function AttachmentView({ auth, attachment }: Props) {
+  const [url, setUrl] = useState<string | null>(null)
+  const isImage = attachment.content_type.startsWith('image/')
+  // The smallest thumbnail big enough to show; without one the image is small anyway.
+  const thumbnailSize = attachment.thumbnails?.find((t) => t.size >= DISPLAY_SIZE)?.size
+
+  useEffect(() => {
+    if (!isImage) return
+    let objectUrl: string | null = null
+    let cancelled = false
+    const load = thumbnailSize
+      ? fetchThumbnail(auth, attachment.id, thumbnailSize)
+      : fetchAttachment(auth, attachment.id)
+    load
+      .then((blob) => {
+        if (cancelled) return
+        objectUrl = URL.createObjectURL(blob)
+        setUrl(objectUrl)
+      })
+      .catch(() => {})
+    return () => {
+      cancelled = true
+      if (objectUrl) URL.revokeObjectURL(objectUrl)
+    }
+  }, [auth, attachment.id, isImage, thumbnailSize])
+
+  const download = () => {
+    fetchAttachment(auth, attachment.id)
+      .then((blob) => {
+        const objectUrl = URL.createObjectURL(blob)
+        const a = document.createElement('a')
+        a.href = objectUrl
+        a.download = attachment.filename
+        a.click()
+        URL.revokeObjectURL(objectUrl)
+      })
+      .catch(() => {})
+  }
+
+  if (isImage && (url || attachment.width)) {
+    // Reserve the image's space before it loads, so the list does not jump.
+    const width = attachment.width ?? DISPLAY_SIZE
+    const height = attachment.height ?? DISPLAY_SIZE
+    const scale = Math.min(1, DISPLAY_SIZE / Math.max(width, height))
+    return (
+      <button type="button" className="attachment-image-btn" onClick={download} title={attachment.filename}>
+        {url ? (
+          <img
+            className="attachment-image"
+            src={url}
+            alt={attachment.filename}
+            width={Math.round(width * scale)}
+            height={Math.round(height * scale)}
+          />
+        ) : (
+          <div
+            className="attachment-image placeholder"
+            style={{ width: Math.round(width * scale), height: Math.round(height * scale) }}
+          />
+        )}
+      </button>
+    )
+  }
+  return (
+    <button type="button" className="attachment-file" onClick={download} title="Download">
+      <FileText size={16} />
+      <span>{attachment.filename}</span>
+      <span className="muted">{formatSize(attachment.size_bytes)}</span>
+    </button>
+  )
+}
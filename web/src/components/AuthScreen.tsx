@@ -1,4 +1,6 @@
-import { MessageCircle, UserPlus, LogIn } from 'lucide-react'
+import { MessageCircle, UserPlus, LogIn, KeyRound } from 'lucide-react'
+import { oidcAuthorizeUrl } from '../api'
+import type { OidcProvider } from '../types'
 
 type Props = {
   mode: 'login' | 'register'
@@ -9,6 +11,13 @@ type Props = {
   setPassword: (s: string) => void
   error: string | null
   setError: (s: string | null) => void
+  /** Pending 2FA login challenge; the form asks for a code while set. */
+  challenge: string | null
+  /** Single sign-on providers, shown as buttons below the form. */
+  providers: OidcProvider[]
+  code: string
+  setCode: (s: string) => void
+  cancelChallenge: () => void
   onSubmit: (e: React.FormEvent) => void
 }
 
@@ -21,6 +30,11 @@ export function AuthScreen({
   setPassword,
   error,
   setError,
+  challenge,
+  providers,
+  code,
+  setCode,
+  cancelChallenge,
   onSubmit,
 }: Props) {
   return (
@@ -86,27 +100,44 @@ export function AuthScreen({
             </button>
           </div>
           <form onSubmit={onSubmit} className="auth-form-wrap space-y-4">
-            <label className="block">
-              <span className="block text-xs font-semibold text-gray-400 uppercase tracking-wider mb-1.5">Username</span>
-              <input
-                value={username}
-                onChange={(e) => setUsername(e.target.value)}
-                autoComplete="username"
-                placeholder="Enter username"
-                className="w-full rounded-lg border border-white/10 bg-white/5 px-4 py-3 text-white placeholder-gray-500 transition-[border-color,box-shadow,background-color] duration-200 ease-out focus:outline-none focus:ring-2 focus:ring-brand/50 focus:border-brand hover:bg-white/[0.07] hover:border-white/15"
-              />
-            </label>
-            <label className="block">
-              <span className="block text-xs font-semibold text-gray-400 uppercase tracking-wider mb-1.5">Password</span>
-              <input
-                type="password"
-                value={password}
-                onChange={(e) => setPassword(e.target.value)}
-                autoComplete={mode === 'login' ? 'current-password' : 'new-password'}
-                placeholder="Enter password"
-                className="w-full rounded-lg border border-white/10 bg-white/5 px-4 py-3 text-white placeholder-gray-500 transition-[border-color,box-shadow,background-color] duration-200 ease-out focus:outline-none focus:ring-2 focus:ring-brand/50 focus:border-brand hover:bg-white/[0.07] hover:border-white/15"
-              />
-            </label>
+            {challenge ? (
+              <label className="block">
+                <span className="block text-xs font-semibold text-gray-400 uppercase tracking-wider mb-1.5">Authentication code</span>
+                <input
+                  value={code}
+                  onChange={(e) => setCode(e.target.value)}
+                  autoComplete="one-time-code"
+                  inputMode="numeric"
+                  autoFocus
+                  placeholder="6-digit code or recovery code"
+                  className="w-full rounded-lg border border-white/10 bg-white/5 px-4 py-3 text-white placeholder-gray-500 transition-[border-color,box-shadow,background-color] duration-200 ease-out focus:outline-none focus:ring-2 focus:ring-brand/50 focus:border-brand hover:bg-white/[0.07] hover:border-white/15"
+                />
+              </label>
+            ) : (
+              <>
+                <label className="block">
+                  <span className="block text-xs font-semibold text-gray-400 uppercase tracking-wider mb-1.5">Username</span>
+                  <input
+                    value={username}
+                    onChange={(e) => setUsername(e.target.value)}
+                    autoComplete="username"
+                    placeholder="Enter username"
+                    className="w-full rounded-lg border border-white/10 bg-white/5 px-4 py-3 text-white placeholder-gray-500 transition-[border-color,box-shadow,background-color] duration-200 ease-out focus:outline-none focus:ring-2 focus:ring-brand/50 focus:border-brand hover:bg-white/[0.07] hover:border-white/15"
+                  />
+                </label>
+                <label className="block">
+                  <span className="block text-xs font-semibold text-gray-400 uppercase tracking-wider mb-1.5">Password</span>
+                  <input
+                    type="password"
+                    value={password}
+                    onChange={(e) => setPassword(e.target.value)}
+                    autoComplete={mode === 'login' ? 'current-password' : 'new-password'}
+                    placeholder="Enter password"
+                    className="w-full rounded-lg border border-white/10 bg-white/5 px-4 py-3 text-white placeholder-gray-500 transition-[border-color,box-shadow,background-color] duration-200 ease-out focus:outline-none focus:ring-2 focus:ring-brand/50 focus:border-brand hover:bg-white/[0.07] hover:border-white/15"
+                  />
+                </label>
+              </>
+            )}
             {error && (
               <div className="auth-error-enter rounded-lg bg-red-500/10 border border-red-500/20 px-4 py-2.5 text-sm text-red-300" role="alert">
                 {error}
@@ -116,9 +147,37 @@ export function AuthScreen({
               type="submit"
               className="w-full py-3 rounded-lg bg-brand hover:bg-brand-hover text-white font-semibold shadow-lg shadow-brand/25 transition-[background-color,transform,box-shadow] duration-200 ease-out hover:scale-[1.01] active:scale-[0.99] focus:outline-none focus:ring-2 focus:ring-brand focus:ring-offset-2 focus:ring-offset-[#1c1c24]"
             >
-              {mode === 'register' ? 'Create account & join' : 'Log in'}
+              {challenge ? 'Verify' : mode === 'register' ? 'Create account & join' : 'Log in'}
             </button>
+            {challenge && (
+              <button
+                type="button"
+                onClick={cancelChallenge}
+                className="w-full text-sm text-gray-400 hover:text-white transition-colors duration-200"
+              >
+                Start over
+              </button>
+            )}
           </form>
+          {!challenge && providers.length > 0 && (
+            <div className="mt-6 space-y-2">
+              <div className="flex items-center gap-3 text-xs text-gray-500 uppercase tracking-wider">
+                <span className="h-px flex-1 bg-white/10" />
+                or
+                <span className="h-px flex-1 bg-white/10" />
+              </div>
+              {providers.map((p) => (
+                <a
+                  key={p.name}
+                  href={oidcAuthorizeUrl(p.name)}
+                  className="w-full flex items-center justify-center gap-2 py-3 rounded-lg border border-white/10 bg-white/5 text-white font-medium transition-colors duration-200 hover:bg-white/10"
+                >
+                  <KeyRound size={18} className="shrink-0" />
+                  Continue with {p.display_name}
+                </a>
+              ))}
+            </div>
+          )}
         </div>
       </div>
     </div>
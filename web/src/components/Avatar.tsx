@@ -1,13 +1,37 @@
-type Props = { name: string; isSelf?: boolean }
+import { useState } from 'react'
+
+type Props = {
+  /** Shown as the tooltip and, without a picture, as initials. */
+  name: string
+  /** Profile picture; initials are shown if it is missing or fails to load. */
+  src?: string | null
+  /** Stable value (such as the user id) the fallback colour is derived from, so it survives renames. */
+  seed?: string
+  isSelf?: boolean
+}
+
+export function Avatar({ name, src, seed = name, isSelf }: Props) {
+  const [failedSrc, setFailedSrc] = useState<string | null>(null)
+  if (src && src !== failedSrc) {
+    return (
+      <img
+        className="avatar"
+        src={src}
+        alt={name}
+        title={name}
+        referrerPolicy="no-referrer"
+        onError={() => setFailedSrc(src)}
+      />
+    )
+  }
 
-export function Avatar({ name, isSelf }: Props) {
   const initials = name
     .split(/\s+/)
     .map((w) => w[0])
     .join('')
     .toUpperCase()
     .slice(0, 2)
-  const hue = isSelf ? 250 : (name.split('').reduce((a, c) => a + c.charCodeAt(0), 0) % 360)
+  const hue = isSelf ? 250 : (seed.split('').reduce((a, c) => a + c.charCodeAt(0), 0) % 360)
   return (
     <div
       className="avatar"
@@ -1,7 +1,10 @@
-import { type FormEvent, useEffect, useRef, useState } from 'react'
-import { Hash, MessageCircle, Send, UserPlus, Loader2, WifiOff, CheckCircle2 } from 'lucide-react'
+import { type ChangeEvent, type FormEvent, useEffect, useRef, useState } from 'react'
+import { Hash, MessageCircle, Paperclip, Send, UserPlus, Loader2, WifiOff, CheckCircle2, X } from 'lucide-react'
+import { uploadAttachment } from '../api'
+import { AttachmentView } from './AttachmentView'
+import { LinkPreviewCard } from './LinkPreviewCard'
 import { Avatar } from './Avatar'
-import type { AuthState, ChatMessage, Room } from '../types'
+import type { Attachment, AuthState, ChatMessage, Room } from '../types'
 import type { WsStatus } from '../hooks'
 
 type Props = {
@@ -10,7 +13,7 @@ type Props = {
   messages: ChatMessage[]
   status: WsStatus
   wsError: string | null
-  onSendMessage: (content: string) => void
+  onSendMessage: (content: string, attachmentIds: string[]) => void
 }
 
 export function ChatArea({
@@ -22,6 +25,10 @@ export function ChatArea({
   onSendMessage,
 }: Props) {
   const [input, setInput] = useState('')
+  const [pending, setPending] = useState<Attachment[]>([])
+  const [uploading, setUploading] = useState(false)
+  const [uploadError, setUploadError] = useState<string | null>(null)
+  const fileInputRef = useRef<HTMLInputElement | null>(null)
   const messagesEndRef = useRef<HTMLDivElement | null>(null)
 
   useEffect(() => {
@@ -33,12 +40,36 @@ export function ChatArea({
       ? `${window.location.origin}/#room=${selectedRoom.id}`
       : null
 
+  useEffect(() => {
+    setPending([])
+    setUploadError(null)
+  }, [selectedRoom?.id])
+
   const handleSubmit = (e: FormEvent) => {
     e.preventDefault()
     const text = input.trim()
-    if (!text) return
-    onSendMessage(text)
+    if (!text && pending.length === 0) return
+    onSendMessage(text, pending.map((a) => a.id))
     setInput('')
+    setPending([])
+  }
+
+  const handleFiles = async (e: ChangeEvent<HTMLInputElement>) => {
+    const files = Array.from(e.target.files ?? [])
+    e.target.value = ''
+    if (!selectedRoom || files.length === 0) return
+    setUploading(true)
+    setUploadError(null)
+    try {
+      for (const file of files) {
+        const attachment = await uploadAttachment(auth, selectedRoom.id, file)
+        setPending((p) => [...p, attachment])
+      }
+    } catch (err) {
+      setUploadError(err instanceof Error ? err.message : 'Failed to upload file')
+    } finally {
+      setUploading(false)
+    }
   }
 
   return (
@@ -109,24 +140,40 @@ export function ChatArea({
             {m.kind === 'system' ? (
               <span className="message-system">
                 <span className="message-meta">{new Date(m.created_at).toLocaleTimeString()}</span>{' '}
-                <strong>{m.username}</strong> {m.content}
+                <strong>{m.display_name || m.username}</strong> {m.content}
               </span>
             ) : (
               <>
                 <Avatar
-                  name={m.user_id === auth.userId ? auth.username : m.username}
+                  name={m.display_name || m.username}
+                  src={m.avatar_url}
+                  seed={m.user_id}
                   isSelf={m.user_id === auth.userId}
                 />
                 <div className="message-body">
                   <div className="message-top">
-                    <span className="message-username">
-                      {m.user_id === auth.userId ? 'You' : m.username}
+                    <span className="message-username" title={`@${m.username}`}>
+                      {m.user_id === auth.userId ? 'You' : m.display_name || m.username}
                     </span>
                     <span className="message-time">
                       {new Date(m.created_at).toLocaleTimeString()}
                     </span>
                   </div>
-                  <div className="message-content">{m.content}</div>
+                  {m.content && <div className="message-content">{m.content}</div>}
+                  {m.attachments && m.attachments.length > 0 && (
+                    <div className="message-attachments">
+                      {m.attachments.map((a) => (
+                        <AttachmentView key={a.id} auth={auth} attachment={a} />
+                      ))}
+                    </div>
+                  )}
+                  {m.previews && m.previews.length > 0 && (
+                    <div className="message-previews">
+                      {m.previews.map((p) => (
+                        <LinkPreviewCard key={p.url} preview={p} />
+                      ))}
+                    </div>
+                  )}
                 </div>
               </>
             )}
@@ -135,7 +182,41 @@ export function ChatArea({
         <div ref={messagesEndRef} />
       </div>
 
+      {(pending.length > 0 || uploadError) && (
+        <div className="chat-pending">
+          {pending.map((a) => (
+            <span key={a.id} className="pending-attachment">
+              {a.filename}
+              <button
+                type="button"
+                className="icon-btn ghost"
+                onClick={() => setPending((p) => p.filter((x) => x.id !== a.id))}
+                title="Remove"
+              >
+                <X size={14} />
+              </button>
+            </span>
+          ))}
+          {uploadError && <span className="error small">{uploadError}</span>}
+        </div>
+      )}
       <form className="chat-input" onSubmit={handleSubmit}>
+        <input
+          ref={fileInputRef}
+          type="file"
+          multiple
+          hidden
+          onChange={handleFiles}
+        />
+        <button
+          type="button"
+          className="icon-btn ghost"
+          onClick={() => fileInputRef.current?.click()}
+          disabled={status !== 'connected' || uploading || pending.length >= 10}
+          title="Attach files"
+        >
+          {uploading ? <Loader2 size={18} className="spin" /> : <Paperclip size={18} />}
+        </button>
         <input
           placeholder={
             status === 'connected'
@@ -150,7 +231,7 @@ export function ChatArea({
         <button
           type="submit"
           className="icon-btn primary"
-          disabled={status !== 'connected' || !input.trim()}
+          disabled={status !== 'connected' || (!input.trim() && pending.length === 0)}
           title="Send message"
         >
           <Send size={18} />
@@ -0,0 +1,34 @@
+import { useState } from 'react'
+import type { LinkPreview } from '../types'
+
+type Props = {
+  preview: LinkPreview
+}
+
+/** A card for a link in a message. The image is loaded from the linked site directly. */
+export function LinkPreviewCard({ preview }: Props) {
+  const [imageFailed, setImageFailed] = useState(false)
+  const site = preview.site_name || new URL(preview.url).hostname
+
+  return (
+    <a className="link-preview" href={preview.url} target="_blank" rel="noopener noreferrer">
+      <div className="link-preview-text">
+        <span className="link-preview-site">{site}</span>
+        {preview.title && <span className="link-preview-title">{preview.title}</span>}
+        {preview.description && (
+          <span className="link-preview-description">{preview.description}</span>
+        )}
+      </div>
+      {preview.image_url && !imageFailed && (
+        <img
+          className="link-preview-image"
+          src={preview.image_url}
+          alt=""
+          loading="lazy"
+          referrerPolicy="no-referrer"
+          onError={() => setImageFailed(true)}
+        />
+      )}
+    </a>
+  )
+}
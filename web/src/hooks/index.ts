@@ -1,3 +1,4 @@
 export { useAuth } from './useAuth'
+export { useProfile } from './useProfile'
 export { useRooms } from './useRooms'
 export { useRoomWebSocket, type WsStatus } from './useRoomWebSocket'
@@ -1,8 +1,18 @@
-import { type FormEvent, useState } from 'react'
-import { register, login } from '../api'
-import type { AuthState } from '../types'
+import { type FormEvent, useEffect, useState } from 'react'
+import {
+  register,
+  login,
+  loginTwoFactor,
+  logout as revokeSession,
+  refreshSession,
+  exchangeOidcGrant,
+  fetchOidcProviders,
+} from '../api'
+import type { AuthState, LoginResponse, OidcProvider } from '../types'
 
 const STORAGE_KEY = 'chat_auth'
+/** Refresh this long before the access token expires. */
+const REFRESH_MARGIN_MS = 60_000
 
 function loadStoredAuth(): AuthState | null {
   const raw = localStorage.getItem(STORAGE_KEY)
@@ -15,39 +25,142 @@ function loadStoredAuth(): AuthState | null {
   }
 }
 
+function toAuthState(data: LoginResponse): AuthState {
+  return {
+    token: data.token,
+    userId: data.user_id,
+    username: data.username,
+    refreshToken: data.refresh_token,
+    expiresAt: Date.now() + data.expires_in * 1000,
+  }
+}
+
+const OIDC_ERRORS: Record<string, string> = {
+  access_denied: 'Sign-in was cancelled',
+  login_failed: 'Single sign-on failed',
+}
+
+/**
+ * Read and clear the result of a single sign-on redirect
+ * (`#oidc_grant=...` or `#oidc_error=...`).
+ */
+function takeOidcResult(): { grant?: string; error?: string } | null {
+  const params = new URLSearchParams(window.location.hash.slice(1))
+  const grant = params.get('oidc_grant')
+  const error = params.get('oidc_error')
+  if (!grant && !error) return null
+  history.replaceState(null, '', window.location.pathname + window.location.search)
+  return { grant: grant ?? undefined, error: error ?? undefined }
+}
+
+function storeAuth(next: AuthState | null) {
+  if (next) localStorage.setItem(STORAGE_KEY, JSON.stringify(next))
+  else localStorage.removeItem(STORAGE_KEY)
+}
+
+/**
+ * Rotate the refresh token. Refresh tokens are single use and shared by all
+ * tabs through localStorage, so tabs take a lock and reuse a rotation another
+ * tab already did instead of presenting a spent token (which the server treats
+ * as theft and revokes the session).
+ */
+async function rotateSession(current: AuthState): Promise<AuthState> {
+  const run = async () => {
+    const stored = loadStoredAuth()
+    if (stored?.refreshToken && stored.refreshToken !== current.refreshToken) return stored
+    return toAuthState(await refreshSession(current.refreshToken!))
+  }
+  return navigator.locks ? navigator.locks.request(STORAGE_KEY, run) : run()
+}
+
 export function useAuth() {
   const [auth, setAuthState] = useState<AuthState | null>(loadStoredAuth)
   const [mode, setMode] = useState<'login' | 'register'>('register')
   const [username, setUsername] = useState('')
   const [password, setPassword] = useState('')
   const [error, setError] = useState<string | null>(null)
+  // Set after a correct password on an account with 2FA; cleared on success.
+  const [challenge, setChallenge] = useState<string | null>(null)
+  const [code, setCode] = useState('')
+  const [providers, setProviders] = useState<OidcProvider[]>([])
 
   const setAuth = (next: AuthState | null) => {
     setAuthState(next)
-    if (next) localStorage.setItem(STORAGE_KEY, JSON.stringify(next))
-    else localStorage.removeItem(STORAGE_KEY)
+    storeAuth(next)
   }
 
+  useEffect(() => {
+    fetchOidcProviders().then(setProviders).catch(() => setProviders([]))
+
+    const result = takeOidcResult()
+    if (!result) return
+    if (result.error || !result.grant) {
+      setError(OIDC_ERRORS[result.error ?? ''] ?? OIDC_ERRORS.login_failed)
+      return
+    }
+    exchangeOidcGrant(result.grant)
+      .then((data) => {
+        if ('two_factor_required' in data) {
+          setChallenge(data.challenge_token)
+          return
+        }
+        const next = toAuthState(data)
+        setAuthState(next)
+        storeAuth(next)
+      })
+      .catch((err: unknown) => setError(err instanceof Error ? err.message : OIDC_ERRORS.login_failed))
+  }, [])
+
+  useEffect(() => {
+    if (!auth?.refreshToken || !auth.expiresAt) return
+    const delay = Math.max(auth.expiresAt - Date.now() - REFRESH_MARGIN_MS, 0)
+    const timer = setTimeout(() => {
+      rotateSession(auth)
+        .then((next) => {
+          setAuthState(next)
+          storeAuth(next)
+        })
+        .catch(() => {
+          setAuthState(null)
+          storeAuth(null)
+        })
+    }, delay)
+    return () => clearTimeout(timer)
+  }, [auth])
+
   const handleSubmit = async (e: FormEvent) => {
     e.preventDefault()
     setError(null)
     try {
+      if (challenge) {
+        const data = await loginTwoFactor(challenge, code)
+        setChallenge(null)
+        setCode('')
+        setAuth(toAuthState(data))
+        return
+      }
       const data = mode === 'register'
         ? await register(username, password)
         : await login(username, password)
-      const next: AuthState = {
-        token: data.token,
-        userId: data.user_id,
-        username: data.username,
-      }
-      setAuth(next)
       setPassword('')
+      if ('two_factor_required' in data) {
+        setChallenge(data.challenge_token)
+        return
+      }
+      setAuth(toAuthState(data))
     } catch (err: unknown) {
       setError(err instanceof Error ? err.message : 'Authentication failed')
     }
   }
 
+  const cancelChallenge = () => {
+    setChallenge(null)
+    setCode('')
+    setError(null)
+  }
+
   const logout = () => {
+    if (auth) revokeSession(auth).catch(() => {})
     setAuth(null)
   }
 
@@ -62,6 +175,11 @@ export function useAuth() {
     setPassword,
     error,
     setError,
+    challenge,
+    providers,
+    code,
+    setCode,
+    cancelChallenge,
     handleSubmit,
     logout,
   }
@@ -0,0 +1,20 @@
+import { useEffect, useState } from 'react'
+import { fetchProfile } from '../api'
+import type { AuthState, UserProfile } from '../types'
+
+/** The signed-in user's own profile; `null` until loaded. */
+export function useProfile(auth: AuthState | null) {
+  const [profile, setProfile] = useState<UserProfile | null>(null)
+
+  useEffect(() => {
+    if (!auth) {
+      setProfile(null)
+      return
+    }
+    fetchProfile(auth)
+      .then(setProfile)
+      .catch(() => setProfile(null))
+  }, [auth])
+
+  return profile
+}
@@ -1,5 +1,5 @@
 import { useEffect, useRef, useState } from 'react'
-import { buildWsUrl } from '../api'
+import { buildWsUrl, createWsTicket, wsProtocols } from '../api'
 import type { AuthState, ChatMessage } from '../types'
 import { WS_BASE } from '../config'
 
@@ -13,9 +13,17 @@ export function useRoomWebSocket(
   const [status, setStatus] = useState<WsStatus>('disconnected')
   const [error, setError] = useState<string | null>(null)
   const wsRef = useRef<WebSocket | null>(null)
+  // The token is only needed to open the socket; refreshing it must not
+  // reconnect, so the effect below keys on the user instead.
+  const tokenRef = useRef(auth?.token)
+  const userId = auth?.userId ?? null
 
   useEffect(() => {
-    if (!auth || !roomId) {
+    tokenRef.current = auth?.token
+  }, [auth?.token])
+
+  useEffect(() => {
+    if (!userId || !tokenRef.current || !roomId) {
       wsRef.current?.close()
       wsRef.current = null
       setStatus('disconnected')
@@ -24,53 +32,77 @@ export function useRoomWebSocket(
       return
     }
 
-    const url = buildWsUrl(roomId, auth.token)
     setStatus('connecting')
     setError(null)
-    const ws = new WebSocket(url)
-    wsRef.current = ws
+    let ws: WebSocket | null = null
+    let cancelled = false
 
-    ws.onopen = () => {
-      setStatus('connected')
-      setError(null)
-      setMessages([])
-    }
+    const connect = (ticket: string) => {
+      const socket = new WebSocket(buildWsUrl(roomId), wsProtocols(ticket))
+      ws = socket
+      wsRef.current = socket
 
-    ws.onmessage = (event) => {
-      try {
-        const msg = JSON.parse(event.data) as ChatMessage
-        setMessages((prev) => [...prev, msg])
-      } catch {
-        console.warn('Failed to parse WS message', event.data)
+      socket.onopen = () => {
+        setStatus('connected')
+        setError(null)
+        setMessages([])
       }
-    }
 
-    ws.onclose = (ev) => {
-      setStatus('disconnected')
-      if (!ev.wasClean) {
-        setError(
-          ev.code === 1006
-            ? `Connection failed. Is the backend running at ${WS_BASE}?`
-            : `Connection closed (${ev.code}${ev.reason ? ': ' + ev.reason : ''})`,
-        )
+      socket.onmessage = (event) => {
+        try {
+          const msg = JSON.parse(event.data) as ChatMessage
+          if (msg.kind === 'preview') {
+            // The same message again, now with its link previews.
+            setMessages((prev) =>
+              prev.map((m) => (m.id === msg.id ? { ...m, previews: msg.previews } : m)),
+            )
+            return
+          }
+          setMessages((prev) => [...prev, msg])
+        } catch {
+          console.warn('Failed to parse WS message', event.data)
+        }
       }
-    }
 
-    ws.onerror = () => {
-      setStatus('disconnected')
-      setError('WebSocket error. Check backend is running and CORS.')
+      socket.onclose = (ev) => {
+        setStatus('disconnected')
+        if (!ev.wasClean) {
+          setError(
+            ev.code === 1006
+              ? `Connection failed. Is the backend running at ${WS_BASE}?`
+              : `Connection closed (${ev.code}${ev.reason ? ': ' + ev.reason : ''})`,
+          )
+        }
+      }
+
+      socket.onerror = () => {
+        setStatus('disconnected')
+        setError('WebSocket error. Check backend is running and CORS.')
+      }
     }
 
+    // Tickets are single use, so each (re)connect fetches a fresh one.
+    createWsTicket(tokenRef.current, roomId)
+      .then((ticket) => {
+        if (!cancelled) connect(ticket)
+      })
+      .catch((err: unknown) => {
+        if (cancelled) return
+        setStatus('disconnected')
+        setError(err instanceof Error ? err.message : 'Failed to open connection')
+      })
+
     return () => {
-      ws.close()
+      cancelled = true
+      ws?.close()
       wsRef.current = null
     }
-  }, [auth, roomId])
+  }, [userId, roomId])
 
-  const sendMessage = (content: string) => {
+  const sendMessage = (content: string, attachmentIds: string[] = []) => {
     const ws = wsRef.current
     if (!ws || ws.readyState !== WebSocket.OPEN) return
-    ws.send(JSON.stringify({ content }))
+    ws.send(JSON.stringify({ content, attachment_ids: attachmentIds }))
   }
 
   return { messages, setMessages, status, error, wsRef, sendMessage }
@@ -1,5 +1,7 @@
 export type LoginResponse = {
   token: string
+  expires_in: number
+  refresh_token: string
   user_id: string
   username: string
 }
@@ -11,20 +13,89 @@ export type Room = {
   owner_user_id?: string | null
 }
 
-export type WsMessageKind = 'history' | 'message' | 'system'
+export type WsMessageKind = 'history' | 'message' | 'system' | 'preview'
+
+/** A downscaled copy of an image attachment; `size` bounds its longest edge. */
+export type Thumbnail = {
+  size: number
+  width: number
+  height: number
+}
+
+/** A file sent with a message; download it with `fetchAttachment`. */
+export type Attachment = {
+  id: string
+  filename: string
+  content_type: string
+  size_bytes: number
+  /** Pixel size, for images. */
+  width?: number | null
+  height?: number | null
+  /** Smallest first. */
+  thumbnails?: Thumbnail[]
+}
+
+/** What a page linked from a message says about itself. */
+export type LinkPreview = {
+  url: string
+  title?: string | null
+  description?: string | null
+  image_url?: string | null
+  site_name?: string | null
+}
 
 export type ChatMessage = {
   id: string
   room_id: string
   user_id: string
   username: string
+  /** The sender's current display name, if set. */
+  display_name?: string | null
+  avatar_url?: string | null
   content: string
+  attachments?: Attachment[]
+  previews?: LinkPreview[]
   created_at: string
   kind: WsMessageKind
 }
 
+export type UserProfile = {
+  id: string
+  username: string
+  display_name: string | null
+  avatar_url: string | null
+  bio: string | null
+  status: string | null
+  created_at: string
+}
+
+/** A user search result. */
+export type UserSummary = Pick<UserProfile, 'id' | 'username' | 'display_name' | 'avatar_url'>
+
+export type BlockedUser = UserSummary & { blocked_at: string }
+
+/** Fields to change; `null` or `''` clears one, a missing field is left alone. */
+export type ProfileUpdate = Partial<Pick<UserProfile, 'display_name' | 'avatar_url' | 'bio' | 'status'>>
+
+/** Returned by login instead of tokens when the account has 2FA enabled. */
+export type TwoFactorChallenge = {
+  two_factor_required: true
+  challenge_token: string
+  expires_in: number
+}
+
+/** A single sign-on provider offered on the sign-in screen. */
+export type OidcProvider = {
+  name: string
+  display_name: string
+}
+
 export type AuthState = {
   token: string
   userId: string
   username: string
+  /** Single-use token for POST /api/auth/refresh. */
+  refreshToken?: string
+  /** When `token` expires (ms since epoch). */
+  expiresAt?: number
 }